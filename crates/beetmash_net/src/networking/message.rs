@@ -1,9 +1,12 @@
-use crate::prelude::RegistrationId;
+use crate::prelude::*;
 use anyhow::Result;
 use bevy::prelude::*;
+use bincode::Options;
 use serde::de::DeserializeOwned;
+use serde::de::DeserializeSeed;
 use serde::Deserialize;
 use serde::Serialize;
+use std::time::Duration;
 
 #[derive(Debug, Default, Clone, PartialEq, Deref, DerefMut, Resource)]
 pub struct MessageIncoming(pub Vec<Message>);
@@ -12,6 +15,31 @@ pub struct MessageIncoming(pub Vec<Message>);
 #[derive(Debug, Default, Clone, PartialEq, Deref, DerefMut, Resource)]
 pub struct MessageOutgoing(pub Vec<Message>);
 
+impl MessageOutgoing {
+	/// Whether the latest queued [`Message::SpawnWith`] for this entity
+	/// includes the component, and it has not been removed since.
+	pub fn spawned_with(&self, entity: Entity, reg_id: RegistrationId) -> bool {
+		self.iter()
+			.rev()
+			.find_map(|msg| match msg {
+				Message::SpawnWith {
+					entity: spawned,
+					components,
+				} if *spawned == entity => Some(
+					components
+						.iter()
+						.any(|(component, _)| *component == reg_id),
+				),
+				Message::Remove {
+					entity: removed,
+					reg_id: component,
+				} if *removed == entity && *component == reg_id => Some(false),
+				_ => None,
+			})
+			.unwrap_or(false)
+	}
+}
+
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Message {
@@ -21,6 +49,12 @@ pub enum Message {
 	Despawn {
 		entity: Entity,
 	},
+	/// Spawn an entity with its initial components, applied by
+	/// the receiver in a single insert.
+	SpawnWith {
+		entity: Entity,
+		components: Vec<(RegistrationId, MessagePayload)>,
+	},
 	Add {
 		reg_id: RegistrationId,
 		entity: Entity,
@@ -53,6 +87,69 @@ pub enum Message {
 	SendObserver {
 		reg_id: RegistrationId,
 		payload: MessagePayload,
+		/// Entities targeted by the trigger, mapped through
+		/// [`ReplicateRegistry::entities`] by the receiver.
+		/// If empty the observer is triggered globally.
+		/// Targets not yet spawned by the receiver are skipped, and
+		/// if none of the targets exist the trigger is dropped.
+		#[serde(default)]
+		targets: Vec<Entity>,
+		/// Entities targeted by the trigger that belong to the receiver,
+		/// ie the sender's mirror of an entity replicated from the
+		/// receiver, translated with [`ReplicateRegistry::remote_entities`].
+		#[serde(default)]
+		owned_targets: Vec<Entity>,
+	},
+	/// Clock synchronization request, see [`NetworkClock`].
+	/// Times are the sender's [`Time<Real>`] elapsed.
+	Ping {
+		/// The [`PeerId`] of the sender
+		peer: PeerId,
+		sent: Duration,
+	},
+	/// Clock synchronization response, see [`NetworkClock`].
+	Pong {
+		/// The `peer` of the [`Message::Ping`], other peers
+		/// receiving the pong ignore it
+		peer: PeerId,
+		/// The `sent` time of the [`Message::Ping`]
+		ping_sent: Duration,
+		/// When the ping was received by the responder
+		received: Duration,
+		/// When the pong was sent by the responder
+		sent: Duration,
+	},
+	/// Request the file of a [`StreamedAsset`] that is missing locally,
+	/// see [`AssetStreamPlugin`].
+	AssetRequest {
+		/// Path relative to the asset root, excluding any label
+		path: String,
+	},
+	/// Part of an asset file sent in response to a [`Message::AssetRequest`].
+	AssetChunk {
+		/// The [`PeerId`] of the sender, see [`AssetStreams::cancel_chunks`]
+		peer: PeerId,
+		path: String,
+		/// [`content_hash`] of the whole file
+		hash: u64,
+		index: u32,
+		total: u32,
+		bytes: Vec<u8>,
+	},
+	/// Marks the start of a batch of messages sent by a transport,
+	/// see [`MessageSequencePlugin`].
+	Batch {
+		peer: PeerId,
+		seq: u64,
+	},
+	/// Marks the start of a batch of messages sent on a network tick,
+	/// see [`NetworkTickPlugin`].
+	Tick {
+		peer: PeerId,
+		tick: u64,
+		/// The tick of the previous batch sent by this peer,
+		/// used by receivers to detect missing batches.
+		prev: Option<u64>,
 	},
 }
 
@@ -97,9 +194,16 @@ impl Message {
 
 	fn with_payload(
 		&self,
-		func: impl FnOnce(&MessagePayload) -> Result<MessagePayload>,
+		func: impl Fn(&MessagePayload) -> Result<MessagePayload>,
 	) -> Result<Self> {
 		match self {
+			Self::SpawnWith { entity, components } => Ok(Self::SpawnWith {
+				entity: *entity,
+				components: components
+					.iter()
+					.map(|(reg_id, payload)| Ok((*reg_id, func(payload)?)))
+					.collect::<Result<_>>()?,
+			}),
 			Self::Add {
 				entity,
 				reg_id,
@@ -134,10 +238,74 @@ impl Message {
 				reg_id: *reg_id,
 				payload: func(payload)?,
 			}),
+			Self::SendObserver {
+				reg_id,
+				payload,
+				targets,
+				owned_targets,
+			} => Ok(Self::SendObserver {
+				reg_id: *reg_id,
+				payload: func(payload)?,
+				targets: targets.clone(),
+				owned_targets: owned_targets.clone(),
+			}),
 			other => Ok(other.clone()),
 		}
 	}
 
+	/// The registration this message refers to, `None` for
+	/// entity, clock, asset and marker messages.
+	pub fn reg_id(&self) -> Option<RegistrationId> {
+		match self {
+			Self::Spawn { .. }
+			| Self::Despawn { .. }
+			| Self::SpawnWith { .. }
+			| Self::Ping { .. }
+			| Self::Pong { .. }
+			| Self::AssetRequest { .. }
+			| Self::AssetChunk { .. }
+			| Self::Batch { .. }
+			| Self::Tick { .. } => None,
+			Self::Add { reg_id, .. }
+			| Self::Change { reg_id, .. }
+			| Self::Remove { reg_id, .. }
+			| Self::InsertResource { reg_id, .. }
+			| Self::ChangeResource { reg_id, .. }
+			| Self::RemoveResource { reg_id }
+			| Self::SendEvent { reg_id, .. }
+			| Self::SendObserver { reg_id, .. } => Some(*reg_id),
+		}
+	}
+
+	pub fn kind(&self) -> MessageKind {
+		match self {
+			Self::Spawn { .. }
+			| Self::Despawn { .. }
+			| Self::SpawnWith { .. }
+			| Self::Add { .. }
+			| Self::Change { .. }
+			| Self::Remove { .. } => MessageKind::Entity,
+			Self::InsertResource { .. }
+			| Self::ChangeResource { .. }
+			| Self::RemoveResource { .. } => MessageKind::Resource,
+			Self::SendEvent { .. } => MessageKind::Event,
+			Self::SendObserver { .. } => MessageKind::Observer,
+			Self::Ping { .. } | Self::Pong { .. } => MessageKind::Clock,
+			Self::AssetRequest { .. } | Self::AssetChunk { .. } => {
+				MessageKind::Asset
+			}
+			Self::Batch { .. } | Self::Tick { .. } => MessageKind::Marker,
+		}
+	}
+
+	/// Approximate size on the wire, as encoded by
+	/// [`Message::vec_into_bytes`]. Json only payloads are counted as is.
+	pub fn encoded_len(&self) -> usize {
+		let message =
+			self.with_bytes_payload().unwrap_or_else(|_| self.clone());
+		bincode::serialized_size(&message).unwrap_or_default() as usize
+	}
+
 	pub fn with_bytes_payload(&self) -> Result<Self> {
 		self.with_payload(|payload| payload.into_bytes())
 	}
@@ -147,6 +315,21 @@ impl Message {
 }
 
 
+/// Broad category of a [`Message`], used for routing.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+	/// Spawn, despawn and component messages
+	Entity,
+	Resource,
+	Event,
+	Observer,
+	/// Ping and pong messages
+	Clock,
+	/// Asset requests and chunks
+	Asset,
+	/// Batch and tick markers, always sent to every transport
+	Marker,
+}
 
 /// A serializable container for message payloads.
 /// With the `serde_json` feature enabled, both binary and json representations are stored
@@ -207,6 +390,34 @@ impl MessagePayload {
 			Self::Dual(bytes, _) => Ok(bincode::deserialize(bytes)?),
 		}
 	}
+
+	/// Deserialize using a [`DeserializeSeed`], ie a
+	/// [`TypedReflectDeserializer`](bevy::reflect::serde::TypedReflectDeserializer).
+	pub fn deserialize_seed<'de, T: DeserializeSeed<'de>>(
+		&'de self,
+		seed: T,
+	) -> Result<T::Value> {
+		match self {
+			Self::Bytes(bytes) | Self::Dual(bytes, _) => {
+				Ok(bincode_options().deserialize_seed(seed, bytes)?)
+			}
+			Self::Json(json) => {
+				#[cfg(feature = "serde_json")]
+				return Ok(seed.deserialize(
+					&mut serde_json::Deserializer::from_str(json),
+				)?);
+				#[cfg(not(feature = "serde_json"))]
+				anyhow::bail!("message payload is json but `serde_json` feature is not enabled")
+			}
+		}
+	}
+}
+
+/// The options used by [`bincode::serialize`] and [`bincode::deserialize`].
+fn bincode_options() -> impl bincode::Options {
+	bincode::DefaultOptions::new()
+		.with_fixint_encoding()
+		.allow_trailing_bytes()
 }
 
 
@@ -0,0 +1,236 @@
+use crate::prelude::*;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::collections::BTreeSet;
+
+/// Sequence numbers this far behind the highest received from a peer
+/// are considered duplicates.
+pub const DEFAULT_SEQUENCE_WINDOW: u64 = 1024;
+
+/// Prefixes every batch sent by a transport with a [`Message::Batch`]
+/// carrying a per peer sequence number, and drops incoming batches
+/// that have already been received, ie resent or delivered by more
+/// than one transport.
+///
+/// Batches are not reordered, see [`NetworkTickPlugin`] for that.
+#[derive(Debug, Clone)]
+pub struct MessageSequencePlugin {
+	pub window: u64,
+}
+
+impl Default for MessageSequencePlugin {
+	fn default() -> Self {
+		Self {
+			window: DEFAULT_SEQUENCE_WINDOW,
+		}
+	}
+}
+
+impl Plugin for MessageSequencePlugin {
+	fn build(&self, app: &mut App) {
+		app.init_resource::<PeerId>()
+			.init_resource::<MessageSequence>()
+			.insert_resource(ReceivedSequences::new(self.window))
+			.add_systems(
+				Update,
+				dedup_incoming
+					.in_set(MessageIncomingSet)
+					.before(handle_incoming),
+			);
+	}
+}
+
+/// The sequence number of the next batch sent by the local [`PeerId`].
+#[derive(Debug, Default, Clone, PartialEq, Resource)]
+pub struct MessageSequence {
+	pub next: u64,
+}
+
+impl MessageSequence {
+	/// Create the marker for the next batch sent by `peer`
+	pub fn next_marker(&mut self, peer: PeerId) -> Message {
+		let seq = self.next;
+		self.next += 1;
+		Message::Batch { peer, seq }
+	}
+}
+
+/// Tracks batch sequence numbers received from each peer.
+#[derive(Debug, Clone, PartialEq, Resource)]
+pub struct ReceivedSequences {
+	pub window: u64,
+	seen: HashMap<PeerId, BTreeSet<u64>>,
+}
+
+impl Default for ReceivedSequences {
+	fn default() -> Self { Self::new(DEFAULT_SEQUENCE_WINDOW) }
+}
+
+impl ReceivedSequences {
+	pub fn new(window: u64) -> Self {
+		Self {
+			window,
+			seen: default(),
+		}
+	}
+
+	/// The highest sequence number received from this peer
+	pub fn last_received(&self, peer: PeerId) -> Option<u64> {
+		self.seen.get(&peer).and_then(|seen| seen.last().copied())
+	}
+
+	/// Record the sequence number, returning false if it is a duplicate
+	/// or too old to tell.
+	pub fn insert(&mut self, peer: PeerId, seq: u64) -> bool {
+		let seen = self.seen.entry(peer).or_default();
+		let oldest = seen
+			.last()
+			.map_or(0, |last| last.max(&seq).saturating_sub(self.window));
+		if seq < oldest || !seen.insert(seq) {
+			return false;
+		}
+		while seen.first().is_some_and(|first| *first < oldest) {
+			seen.pop_first();
+		}
+		true
+	}
+
+	/// Remove duplicate batches and all batch markers, keeping the
+	/// original order. Messages received before any marker are kept.
+	/// Returns whether each original message was kept.
+	pub fn dedup(&mut self, messages: &mut Vec<Message>) -> Vec<bool> {
+		let mut keep_batch = true;
+		let kept = messages
+			.iter()
+			.map(|message| match message {
+				Message::Batch { peer, seq } => {
+					keep_batch = self.insert(*peer, *seq);
+					if !keep_batch {
+						log::warn!(
+							"dropping duplicate batch {seq} from peer {}",
+							**peer
+						);
+					}
+					false
+				}
+				_ => keep_batch,
+			})
+			.collect::<Vec<_>>();
+		let mut kept_iter = kept.iter();
+		messages.retain(|_| *kept_iter.next().unwrap());
+		kept
+	}
+}
+
+pub(crate) fn dedup_incoming(
+	mut incoming: ResMut<MessageIncoming>,
+	mut received: ResMut<ReceivedSequences>,
+	sources: Option<ResMut<MessageIncomingSources>>,
+) {
+	let kept = received.dedup(&mut incoming.0);
+	// keep sources aligned with the remaining messages
+	if let Some(mut sources) = sources {
+		let kept_sources = sources
+			.take(kept.len())
+			.into_iter()
+			.zip(kept)
+			.filter_map(|(source, kept)| kept.then_some(source))
+			.collect();
+		sources.set(kept_sources);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use bevy::ecs::system::RunSystemOnce;
+	use bevy::prelude::*;
+	use sweet::*;
+
+	fn spawn(index: u32) -> Message {
+		Message::Spawn {
+			entity: Entity::from_raw(index),
+		}
+	}
+	fn batch(peer: u64, seq: u64) -> Message {
+		Message::Batch {
+			peer: PeerId(peer),
+			seq,
+		}
+	}
+
+	#[test]
+	fn stamps() -> Result<()> {
+		let (transport, mut remote) = ChannelsTransport::pair();
+		let mut app = App::new();
+		app.add_plugins((ReplicatePlugin, MessageSequencePlugin::default()))
+			.add_transport(transport);
+		let peer = *app.world().resource::<PeerId>();
+
+		for _ in 0..2 {
+			app.world_mut()
+				.resource_mut::<MessageOutgoing>()
+				.push(spawn(0));
+			app.world_mut()
+				.run_system_once(transport_outgoing::<ChannelsTransport>)?;
+		}
+		expect(remote.recv()?).to_be(vec![
+			batch(*peer, 0),
+			spawn(0),
+			batch(*peer, 1),
+			spawn(0),
+		])?;
+		Ok(())
+	}
+
+	#[test]
+	fn dedup() -> Result<()> {
+		let mut received = ReceivedSequences::new(2);
+		let mut messages = vec![
+			spawn(0),
+			batch(1, 0),
+			spawn(1),
+			batch(1, 0),
+			spawn(2),
+			batch(2, 0),
+			spawn(3),
+		];
+		expect(received.dedup(&mut messages))
+			.to_be(vec![true, false, true, false, false, false, true])?;
+		expect(&messages).to_be(&vec![spawn(0), spawn(1), spawn(3)])?;
+
+		// out of order is fine, too old is dropped
+		expect(received.insert(PeerId(1), 3)).to_be_true()?;
+		expect(received.insert(PeerId(1), 2)).to_be_true()?;
+		expect(received.insert(PeerId(1), 2)).to_be_false()?;
+		expect(received.insert(PeerId(1), 0)).to_be_false()?;
+		expect(received.last_received(PeerId(1))).to_be(Some(3))?;
+		Ok(())
+	}
+
+	#[test]
+	fn system() -> Result<()> {
+		let mut app = App::new();
+		app.add_plugins((ReplicatePlugin, MessageSequencePlugin::default()));
+		app.world_mut().resource_mut::<MessageIncoming>().extend([
+			batch(1, 0),
+			spawn(1),
+			batch(1, 0),
+			spawn(1),
+			spawn(2),
+		]);
+		let mut sources =
+			app.world_mut().resource_mut::<MessageIncomingSources>();
+		sources.push(1, MessageSource::new(Some("a".into()), None));
+		sources.push(3, MessageSource::new(Some("b".into()), None));
+		sources.push(4, MessageSource::new(Some("b".into()), None));
+		app.world_mut().run_system_once(super::dedup_incoming)?;
+		expect(&app.world().resource::<MessageIncoming>().0)
+			.to_be(&vec![spawn(1)])?;
+		let sources = app.world().resource::<MessageIncomingSources>();
+		expect(sources.len()).to_be(1)?;
+		expect(sources.source(0)).to_be(Some("a"))?;
+		Ok(())
+	}
+}
@@ -7,9 +7,30 @@ pub use self::default_transport_plugin::*;
 pub mod message;
 #[allow(unused_imports)]
 pub use self::message::*;
+pub mod message_sequence;
+#[allow(unused_imports)]
+pub use self::message_sequence::*;
+pub mod network_clock;
+#[allow(unused_imports)]
+pub use self::network_clock::*;
+pub mod network_diagnostics;
+#[allow(unused_imports)]
+pub use self::network_diagnostics::*;
+pub mod network_tick;
+#[allow(unused_imports)]
+pub use self::network_tick::*;
+pub mod peer_id;
+#[allow(unused_imports)]
+pub use self::peer_id::*;
+pub mod relay_envelope;
+#[allow(unused_imports)]
+pub use self::relay_envelope::*;
 pub mod transport;
 #[allow(unused_imports)]
 pub use self::transport::*;
 pub mod transport_plugin;
 #[allow(unused_imports)]
 pub use self::transport_plugin::*;
+pub mod transport_router;
+#[allow(unused_imports)]
+pub use self::transport_router::*;
@@ -0,0 +1,245 @@
+use crate::prelude::*;
+use bevy::prelude::*;
+use bevy::time::common_conditions::on_timer;
+use std::time::Duration;
+
+pub const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(1);
+/// Weight of each new sample in the moving average.
+pub const DEFAULT_CLOCK_SMOOTHING: f64 = 0.1;
+
+/// Periodically pings the remote peer to estimate round trip time
+/// and clock offset, stored in [`NetworkClock`].
+/// Peers respond to pings regardless of whether they send their own.
+/// Pongs are addressed to the pinging peer so that broadcast pongs
+/// from other peers are ignored, but estimates still assume
+/// a single responding peer, ie a client and its host.
+#[derive(Debug, Clone)]
+pub struct NetworkClockPlugin {
+	pub ping_interval: Duration,
+	pub smoothing: f64,
+}
+
+impl Default for NetworkClockPlugin {
+	fn default() -> Self {
+		Self {
+			ping_interval: DEFAULT_PING_INTERVAL,
+			smoothing: DEFAULT_CLOCK_SMOOTHING,
+		}
+	}
+}
+
+impl Plugin for NetworkClockPlugin {
+	fn build(&self, app: &mut App) {
+		app.init_resource::<PeerId>()
+			.insert_resource(NetworkClock::new(self.smoothing))
+			.add_systems(
+				Update,
+				(
+					handle_clock_incoming.in_set(MessageIncomingSet),
+					send_ping
+						.run_if(on_timer(self.ping_interval))
+						.in_set(MessageOutgoingSet),
+				),
+			);
+	}
+}
+
+/// NTP style estimate of the remote clock, where both clocks are
+/// the [`Time<Real>`] elapsed of their app.
+#[derive(Debug, Clone, PartialEq, Resource)]
+pub struct NetworkClock {
+	/// Smoothed round trip time, excluding time spent by the responder
+	pub rtt: Duration,
+	/// Smoothed seconds that the remote clock is ahead of the local clock
+	pub offset: f64,
+	/// Number of pongs received
+	pub samples: usize,
+	pub smoothing: f64,
+}
+
+impl Default for NetworkClock {
+	fn default() -> Self { Self::new(DEFAULT_CLOCK_SMOOTHING) }
+}
+
+impl NetworkClock {
+	pub fn new(smoothing: f64) -> Self {
+		Self {
+			rtt: Duration::ZERO,
+			offset: 0.,
+			samples: 0,
+			smoothing,
+		}
+	}
+
+	/// Whether at least one pong has been received
+	pub fn is_synced(&self) -> bool { self.samples > 0 }
+
+	/// Update the estimate from the four timestamps of a ping and pong.
+	/// The first sample is used as is, following samples are smoothed.
+	pub fn add_sample(
+		&mut self,
+		ping_sent: Duration,
+		ping_received: Duration,
+		pong_sent: Duration,
+		pong_received: Duration,
+	) {
+		let [t0, t1, t2, t3] =
+			[ping_sent, ping_received, pong_sent, pong_received]
+				.map(|time| time.as_secs_f64());
+		let rtt = ((t3 - t0) - (t2 - t1)).max(0.);
+		let offset = ((t1 - t0) + (t2 - t3)) / 2.;
+
+		if self.samples == 0 {
+			self.rtt = Duration::from_secs_f64(rtt);
+			self.offset = offset;
+		} else {
+			let rtt = lerp(self.rtt.as_secs_f64(), rtt, self.smoothing);
+			self.rtt = Duration::from_secs_f64(rtt);
+			self.offset = lerp(self.offset, offset, self.smoothing);
+		}
+		self.samples += 1;
+	}
+
+	/// Convert a [`Time<Real>`] elapsed of the remote app to the local one,
+	/// saturating at zero.
+	pub fn remote_to_local(&self, remote: Duration) -> Duration {
+		Duration::from_secs_f64((remote.as_secs_f64() - self.offset).max(0.))
+	}
+
+	/// Convert a local [`Time<Real>`] elapsed to the remote one,
+	/// saturating at zero.
+	pub fn local_to_remote(&self, local: Duration) -> Duration {
+		Duration::from_secs_f64((local.as_secs_f64() + self.offset).max(0.))
+	}
+}
+
+fn lerp(from: f64, to: f64, weight: f64) -> f64 { from + (to - from) * weight }
+
+fn send_ping(
+	time: Res<Time<Real>>,
+	peer: Res<PeerId>,
+	mut outgoing: ResMut<MessageOutgoing>,
+) {
+	outgoing.push(Message::Ping {
+		peer: *peer,
+		sent: time.elapsed(),
+	});
+}
+
+fn handle_clock_incoming(
+	time: Res<Time<Real>>,
+	local_peer: Res<PeerId>,
+	incoming: Res<MessageIncoming>,
+	mut outgoing: ResMut<MessageOutgoing>,
+	mut clock: ResMut<NetworkClock>,
+) {
+	let now = time.elapsed();
+	for msg in incoming.iter() {
+		match msg {
+			Message::Ping { peer, sent } => {
+				outgoing.push(Message::Pong {
+					peer: *peer,
+					ping_sent: *sent,
+					received: now,
+					sent: now,
+				});
+			}
+			Message::Pong {
+				peer,
+				ping_sent,
+				received,
+				sent,
+			} if peer == &*local_peer => {
+				clock.add_sample(*ping_sent, *received, *sent, now);
+			}
+			_ => {}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use bevy::prelude::*;
+	use bevy::time::TimePlugin;
+	use bevy::time::TimeUpdateStrategy;
+	use std::time::Duration;
+	use sweet::*;
+
+	fn secs(secs: f64) -> Duration { Duration::from_secs_f64(secs) }
+
+	#[test]
+	fn add_sample() -> Result<()> {
+		let mut clock = NetworkClock::new(0.5);
+		expect(clock.is_synced()).to_be_false()?;
+
+		// remote is 10s ahead, 1s each way, 1s to respond
+		clock.add_sample(secs(0.), secs(11.), secs(12.), secs(3.));
+		expect(clock.is_synced()).to_be_true()?;
+		expect(clock.rtt).to_be(secs(2.))?;
+		expect(clock.offset).to_be(10.)?;
+		expect(clock.remote_to_local(secs(20.))).to_be(secs(10.))?;
+		expect(clock.local_to_remote(secs(10.))).to_be(secs(20.))?;
+
+		// smoothed
+		clock.add_sample(secs(10.), secs(24.), secs(24.), secs(14.));
+		expect(clock.rtt).to_be(secs(3.))?;
+		expect(clock.offset).to_be(11.)?;
+		Ok(())
+	}
+
+	fn app(start: Duration) -> App {
+		let mut app = App::new();
+		app.add_plugins((TimePlugin, ReplicatePlugin))
+			.add_plugins(NetworkClockPlugin {
+				ping_interval: Duration::ZERO,
+				..default()
+			})
+			.insert_resource(TimeUpdateStrategy::ManualDuration(start));
+		// the first update has zero delta
+		app.update();
+		app.update();
+		app.world_mut().resource_mut::<MessageOutgoing>().clear();
+		app.insert_resource(TimeUpdateStrategy::ManualDuration(
+			Duration::from_millis(100),
+		));
+		app
+	}
+
+	#[test]
+	fn works() -> Result<()> {
+		let mut app1 = app(secs(1.));
+		let mut app2 = app(secs(5.));
+
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+		Message::loopback(app2.world_mut(), app1.world_mut());
+		app1.update();
+
+		let clock = app1.world().resource::<NetworkClock>();
+		expect(clock.samples).to_be(1)?;
+		expect(clock.rtt.as_secs_f64()).to_be_close_to(0.1)?;
+		// app2 has been running 4s longer, minus half the rtt
+		expect(clock.offset).to_be_close_to(3.95)?;
+		Ok(())
+	}
+
+	#[test]
+	fn other_peer() -> Result<()> {
+		let mut app1 = app(secs(1.));
+		let mut app2 = app(secs(5.));
+		let mut app3 = app(secs(5.));
+
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+		// app3 receives the pong meant for app1
+		Message::loopback(app2.world_mut(), app3.world_mut());
+		app3.update();
+
+		expect(app3.world().resource::<NetworkClock>().samples).to_be(0)?;
+		Ok(())
+	}
+}
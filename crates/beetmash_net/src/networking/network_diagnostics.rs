@@ -0,0 +1,325 @@
+use crate::prelude::*;
+use bevy::diagnostic::Diagnostic;
+use bevy::diagnostic::DiagnosticMeasurement;
+use bevy::diagnostic::DiagnosticPath;
+use bevy::diagnostic::DiagnosticsStore;
+use bevy::prelude::*;
+use bevy::time::common_conditions::on_timer;
+use bevy::utils::HashMap;
+use bevy::utils::Instant;
+use std::time::Duration;
+
+pub const DEFAULT_DIAGNOSTICS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Records replication traffic as Bevy diagnostics, see
+/// [`NetworkDiagnosticsPlugin::MESSAGES_SENT`] etc. for the totals.
+/// Per registration and per transport rates are added as they are
+/// first seen, see [`registration_diagnostic_path`] and
+/// [`transport_diagnostic_path`].
+///
+/// Byte counts are the [`Message::encoded_len`], which may differ from
+/// the size on the wire for json transports.
+#[derive(Debug, Clone, Resource)]
+pub struct NetworkDiagnosticsPlugin {
+	/// How often rates are measured
+	pub interval: Duration,
+	/// Log all network diagnostics when they are measured
+	pub log: bool,
+}
+
+impl Default for NetworkDiagnosticsPlugin {
+	fn default() -> Self {
+		Self {
+			interval: DEFAULT_DIAGNOSTICS_INTERVAL,
+			log: false,
+		}
+	}
+}
+
+impl NetworkDiagnosticsPlugin {
+	pub const MESSAGES_SENT: DiagnosticPath =
+		DiagnosticPath::const_new("net/messages_sent");
+	pub const MESSAGES_RECEIVED: DiagnosticPath =
+		DiagnosticPath::const_new("net/messages_received");
+	pub const BYTES_SENT: DiagnosticPath =
+		DiagnosticPath::const_new("net/bytes_sent");
+	pub const BYTES_RECEIVED: DiagnosticPath =
+		DiagnosticPath::const_new("net/bytes_received");
+	pub const INCOMING_QUEUE: DiagnosticPath =
+		DiagnosticPath::const_new("net/incoming_queue");
+	pub const OUTGOING_QUEUE: DiagnosticPath =
+		DiagnosticPath::const_new("net/outgoing_queue");
+	/// Only measured if the [`NetworkClockPlugin`] is added
+	pub const RTT: DiagnosticPath = DiagnosticPath::const_new("net/rtt");
+}
+
+impl Plugin for NetworkDiagnosticsPlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(self.clone())
+			.init_resource::<NetworkStats>()
+			.init_resource::<DiagnosticsStore>()
+			.add_systems(
+				Update,
+				measure_network_diagnostics
+					.run_if(on_timer(self.interval))
+					.in_set(MessageIncomingSet),
+			);
+		for path in [
+			Self::MESSAGES_SENT,
+			Self::MESSAGES_RECEIVED,
+			Self::BYTES_SENT,
+			Self::BYTES_RECEIVED,
+		] {
+			app.world_mut()
+				.resource_mut::<DiagnosticsStore>()
+				.add(Diagnostic::new(path).with_suffix("/s"));
+		}
+		app.world_mut()
+			.resource_mut::<DiagnosticsStore>()
+			.add(Diagnostic::new(Self::INCOMING_QUEUE));
+		app.world_mut()
+			.resource_mut::<DiagnosticsStore>()
+			.add(Diagnostic::new(Self::OUTGOING_QUEUE));
+		app.world_mut()
+			.resource_mut::<DiagnosticsStore>()
+			.add(Diagnostic::new(Self::RTT).with_suffix("ms"));
+	}
+}
+
+/// Path of the per second rate for a registration,
+/// where `name` is ie `bytes_sent`.
+pub fn registration_diagnostic_path(
+	reg_id: RegistrationId,
+	name: &str,
+) -> DiagnosticPath {
+	DiagnosticPath::from_components([
+		"net",
+		"registration",
+		&reg_id.inner().to_string(),
+		name,
+	])
+}
+
+/// Path of the per second rate for a transport,
+/// where `name` is ie `bytes_sent`.
+pub fn transport_diagnostic_path(
+	transport: &str,
+	name: &str,
+) -> DiagnosticPath {
+	DiagnosticPath::from_components(["net", "transport", transport, name])
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct TrafficCount {
+	pub messages: usize,
+	pub bytes: usize,
+}
+
+impl TrafficCount {
+	/// Count a message of this many bytes, see [`Message::encoded_len`].
+	pub fn add(&mut self, bytes: usize) {
+		self.messages += 1;
+		self.bytes += bytes;
+	}
+}
+
+/// Traffic since the last measurement, counted by the transport systems
+/// if this resource exists.
+#[derive(Debug, Default, Clone, PartialEq, Resource)]
+pub struct NetworkStats {
+	pub sent: TrafficCount,
+	pub received: TrafficCount,
+	pub registration_sent: HashMap<RegistrationId, TrafficCount>,
+	pub registration_received: HashMap<RegistrationId, TrafficCount>,
+	pub transport_sent: HashMap<TransportName, TrafficCount>,
+	pub transport_received: HashMap<TransportName, TrafficCount>,
+}
+
+impl NetworkStats {
+	pub fn record_sent(&mut self, transport: &str, messages: &[Message]) {
+		Self::record(
+			messages,
+			&mut self.sent,
+			&mut self.registration_sent,
+			self.transport_sent.entry_ref(transport).or_default(),
+		);
+	}
+	pub fn record_received(&mut self, transport: &str, messages: &[Message]) {
+		Self::record(
+			messages,
+			&mut self.received,
+			&mut self.registration_received,
+			self.transport_received.entry_ref(transport).or_default(),
+		);
+	}
+
+	fn record(
+		messages: &[Message],
+		total: &mut TrafficCount,
+		registrations: &mut HashMap<RegistrationId, TrafficCount>,
+		transport: &mut TrafficCount,
+	) {
+		for message in messages {
+			// encoding is not free, measure once per message
+			let bytes = message.encoded_len();
+			total.add(bytes);
+			transport.add(bytes);
+			if let Some(reg_id) = message.reg_id() {
+				registrations.entry(reg_id).or_default().add(bytes);
+			}
+		}
+	}
+}
+
+fn measure_network_diagnostics(
+	config: Res<NetworkDiagnosticsPlugin>,
+	time: Res<Time<Real>>,
+	mut last: Local<Option<Duration>>,
+	mut stats: ResMut<NetworkStats>,
+	mut store: ResMut<DiagnosticsStore>,
+	(incoming, outgoing): (Res<MessageIncoming>, Res<MessageOutgoing>),
+	clock: Option<Res<NetworkClock>>,
+) {
+	let now = time.elapsed();
+	let Some(prev) = last.replace(now) else {
+		return;
+	};
+	let secs = (now - prev).as_secs_f64();
+	if secs == 0. {
+		return;
+	}
+	let stats = std::mem::take(stats.as_mut());
+	let mut measurements = Vec::new();
+	let mut push_rates = |messages: DiagnosticPath,
+	                      bytes: DiagnosticPath,
+	                      count: &TrafficCount| {
+		measurements.push((messages, count.messages as f64 / secs));
+		measurements.push((bytes, count.bytes as f64 / secs));
+	};
+	type Net = NetworkDiagnosticsPlugin;
+	push_rates(Net::MESSAGES_SENT, Net::BYTES_SENT, &stats.sent);
+	push_rates(Net::MESSAGES_RECEIVED, Net::BYTES_RECEIVED, &stats.received);
+	for (reg_id, count) in stats.registration_sent.iter() {
+		push_rates(
+			registration_diagnostic_path(*reg_id, "messages_sent"),
+			registration_diagnostic_path(*reg_id, "bytes_sent"),
+			count,
+		);
+	}
+	for (reg_id, count) in stats.registration_received.iter() {
+		push_rates(
+			registration_diagnostic_path(*reg_id, "messages_received"),
+			registration_diagnostic_path(*reg_id, "bytes_received"),
+			count,
+		);
+	}
+	for (name, count) in stats.transport_sent.iter() {
+		push_rates(
+			transport_diagnostic_path(name, "messages_sent"),
+			transport_diagnostic_path(name, "bytes_sent"),
+			count,
+		);
+	}
+	for (name, count) in stats.transport_received.iter() {
+		push_rates(
+			transport_diagnostic_path(name, "messages_received"),
+			transport_diagnostic_path(name, "bytes_received"),
+			count,
+		);
+	}
+	measurements.push((Net::INCOMING_QUEUE, incoming.len() as f64));
+	measurements.push((Net::OUTGOING_QUEUE, outgoing.len() as f64));
+	if let Some(clock) = clock.filter(|clock| clock.is_synced()) {
+		measurements.push((Net::RTT, clock.rtt.as_secs_f64() * 1000.));
+	}
+
+	let time = Instant::now();
+	for (path, value) in measurements {
+		if config.log {
+			log::info!("{path}: {value:.2}");
+		}
+		if store.get(&path).is_none() {
+			store.add(Diagnostic::new(path.clone()).with_suffix("/s"));
+		}
+		if let Some(diagnostic) = store.get_mut(&path) {
+			diagnostic.add_measurement(DiagnosticMeasurement { time, value });
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use bevy::diagnostic::DiagnosticPath;
+	use bevy::diagnostic::DiagnosticsStore;
+	use bevy::prelude::*;
+	use bevy::time::TimePlugin;
+	use bevy::time::TimeUpdateStrategy;
+	use std::time::Duration;
+	use sweet::*;
+
+	fn value(app: &App, path: &DiagnosticPath) -> Option<f64> {
+		app.world()
+			.resource::<DiagnosticsStore>()
+			.get_measurement(path)
+			.map(|measurement| measurement.value)
+	}
+
+	#[test]
+	fn works() -> Result<()> {
+		type Net = NetworkDiagnosticsPlugin;
+		let (transport, mut remote) = ChannelsTransport::pair();
+		let mut app = App::new();
+		app.add_plugins((TimePlugin, ReplicatePlugin))
+			.add_plugins(NetworkDiagnosticsPlugin {
+				interval: Duration::ZERO,
+				..default()
+			})
+			.add_transport_with_duration(transport, Duration::ZERO)
+			.insert_resource(TimeUpdateStrategy::ManualDuration(
+				Duration::from_secs(1),
+			));
+		// the first update has zero delta
+		app.update();
+
+		let message = Message::SendEvent {
+			reg_id: RegistrationId::new_with(3),
+			payload: MessagePayload::new(7)?,
+		};
+		let len = message.encoded_len() as f64;
+		remote.send(&vec![message.clone(), message.clone()])?;
+		app.world_mut()
+			.resource_mut::<MessageOutgoing>()
+			.push(message.clone());
+		app.update();
+
+		expect(value(&app, &Net::MESSAGES_RECEIVED)).to_be(Some(2.))?;
+		expect(value(&app, &Net::BYTES_RECEIVED)).to_be(Some(len * 2.))?;
+		expect(value(&app, &Net::INCOMING_QUEUE)).to_be(Some(2.))?;
+		expect(value(
+			&app,
+			&registration_diagnostic_path(
+				RegistrationId::new_with(3),
+				"messages_received",
+			),
+		))
+		.to_be(Some(2.))?;
+		// outgoing is sent after measuring
+		expect(value(&app, &Net::MESSAGES_SENT)).to_be(Some(0.))?;
+		expect(value(&app, &Net::OUTGOING_QUEUE)).to_be(Some(1.))?;
+
+		app.update();
+		expect(value(&app, &Net::MESSAGES_SENT)).to_be(Some(1.))?;
+		expect(value(
+			&app,
+			&transport_diagnostic_path(
+				std::any::type_name::<ChannelsTransport>(),
+				"bytes_sent",
+			),
+		))
+		.to_be(Some(len))?;
+		expect(value(&app, &Net::RTT)).to_be_none()?;
+		Ok(())
+	}
+}
@@ -0,0 +1,348 @@
+use crate::prelude::*;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::collections::BTreeMap;
+
+/// Received batches are held until the previous batch arrives,
+/// or this many batches from the same peer are waiting.
+pub const DEFAULT_MAX_PENDING_TICKS: usize = 64;
+
+/// The set in [`FixedUpdate`] that stamps [`MessageOutgoing`]
+/// with a [`Message::Tick`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemSet)]
+pub struct NetworkTickSet;
+
+/// Stamps outgoing batches with a monotonically increasing tick in
+/// [`FixedUpdate`], and applies incoming batches in tick order per peer.
+/// Use [`AppExtTransport::add_ticked_transport`] so that each send
+/// contains exactly one batch.
+#[derive(Debug, Clone)]
+pub struct NetworkTickPlugin {
+	pub max_pending_ticks: usize,
+}
+
+impl Default for NetworkTickPlugin {
+	fn default() -> Self {
+		Self {
+			max_pending_ticks: DEFAULT_MAX_PENDING_TICKS,
+		}
+	}
+}
+
+impl Plugin for NetworkTickPlugin {
+	fn build(&self, app: &mut App) {
+		app.init_resource::<PeerId>()
+			.init_resource::<NetworkTick>()
+			.insert_resource(ReceivedTicks::new(self.max_pending_ticks))
+			.add_systems(FixedUpdate, stamp_outgoing.in_set(NetworkTickSet))
+			.add_systems(
+				Update,
+				order_incoming
+					.in_set(MessageIncomingSet)
+					.after(dedup_incoming)
+					.before(handle_incoming),
+			);
+	}
+}
+
+/// The current tick of the local [`PeerId`].
+#[derive(Debug, Default, Clone, PartialEq, Resource)]
+pub struct NetworkTick {
+	/// Incremented every [`FixedUpdate`]
+	pub tick: u64,
+	/// The tick of the last batch sent
+	pub last_sent: Option<u64>,
+	/// Length of the last batch including its marker, used to find
+	/// unstamped messages if it has not been sent yet.
+	last_batch_len: usize,
+}
+
+/// A message and where it was received from,
+/// see [`MessageIncomingSources`].
+pub type SourcedMessage = (Message, MessageSource);
+
+/// Batches waiting to be applied, keyed by tick,
+/// with the previous tick of each batch.
+type PendingBatches = BTreeMap<u64, (Option<u64>, Vec<SourcedMessage>)>;
+
+/// Tracks ticked batches received from each peer.
+#[derive(Debug, Clone, PartialEq, Resource)]
+pub struct ReceivedTicks {
+	pub max_pending: usize,
+	last: HashMap<PeerId, u64>,
+	pending: HashMap<PeerId, PendingBatches>,
+}
+
+impl Default for ReceivedTicks {
+	fn default() -> Self { Self::new(DEFAULT_MAX_PENDING_TICKS) }
+}
+
+impl ReceivedTicks {
+	pub fn new(max_pending: usize) -> Self {
+		Self {
+			max_pending,
+			last: default(),
+			pending: default(),
+		}
+	}
+
+	/// The tick of the last batch applied from this peer
+	pub fn last_received(&self, peer: PeerId) -> Option<u64> {
+		self.last.get(&peer).copied()
+	}
+
+	/// All peers that ticked batches have been received from
+	pub fn peers(&self) -> impl Iterator<Item = &PeerId> { self.last.keys() }
+
+	/// Split messages into ticked batches, returning those ready to apply
+	/// in tick order with markers removed. Messages received before
+	/// any marker are returned first.
+	/// Stale batches, ie duplicates, are dropped.
+	pub fn order(&mut self, messages: Vec<Message>) -> Vec<Message> {
+		self.order_with_sources(
+			messages
+				.into_iter()
+				.map(|message| (message, MessageSource::default()))
+				.collect(),
+		)
+		.into_iter()
+		.map(|(message, _)| message)
+		.collect()
+	}
+
+	/// Like [`Self::order`], keeping the source of each message.
+	pub fn order_with_sources(
+		&mut self,
+		messages: Vec<SourcedMessage>,
+	) -> Vec<SourcedMessage> {
+		let mut ready = Vec::new();
+		let mut batch: Option<(PeerId, u64, Option<u64>, Vec<SourcedMessage>)> =
+			None;
+		for (message, source) in messages {
+			if let Message::Tick { peer, tick, prev } = message {
+				if let Some(batch) = batch.take() {
+					self.push_batch(batch);
+				}
+				batch = Some((peer, tick, prev, Vec::new()));
+			} else if let Some((_, _, _, messages)) = batch.as_mut() {
+				messages.push((message, source));
+			} else {
+				ready.push((message, source));
+			}
+		}
+		if let Some(batch) = batch.take() {
+			self.push_batch(batch);
+		}
+
+		for (peer, pending) in self.pending.iter_mut() {
+			let last = self.last.get(peer).copied();
+			let last =
+				release_pending(pending, last, self.max_pending, |msgs| {
+					ready.extend(msgs);
+				});
+			if let Some(last) = last {
+				self.last.insert(*peer, last);
+			}
+		}
+		self.pending.retain(|_, pending| !pending.is_empty());
+		ready
+	}
+
+	fn push_batch(
+		&mut self,
+		(peer, tick, prev, messages): (
+			PeerId,
+			u64,
+			Option<u64>,
+			Vec<SourcedMessage>,
+		),
+	) {
+		if self.last.get(&peer).is_some_and(|last| tick <= *last) {
+			log::warn!("dropping stale batch {tick} from peer {}", *peer);
+			return;
+		}
+		self.pending
+			.entry(peer)
+			.or_default()
+			.insert(tick, (prev, messages));
+	}
+}
+
+/// Release pending batches in order while they follow the last received,
+/// returning the new last received tick.
+fn release_pending(
+	pending: &mut PendingBatches,
+	mut last: Option<u64>,
+	max_pending: usize,
+	mut release: impl FnMut(Vec<SourcedMessage>),
+) -> Option<u64> {
+	while let Some(entry) = pending.first_entry() {
+		let (prev, _) = entry.get();
+		let follows = match (last, prev) {
+			// first batch from this peer
+			(None, _) => true,
+			(Some(_), None) => true,
+			(Some(last), Some(prev)) => *prev <= last,
+		};
+		if !follows && pending.len() <= max_pending {
+			break;
+		}
+		let (tick, (_, messages)) = pending.pop_first().unwrap();
+		last = Some(tick);
+		release(messages);
+	}
+	last
+}
+
+fn stamp_outgoing(
+	peer: Res<PeerId>,
+	mut tick: ResMut<NetworkTick>,
+	mut outgoing: ResMut<MessageOutgoing>,
+) {
+	tick.tick += 1;
+	// messages after the last unsent batch
+	let start = outgoing
+		.iter()
+		.rposition(|msg| matches!(msg, Message::Tick { .. }))
+		.map_or(0, |index| index + tick.last_batch_len);
+	if start >= outgoing.len() {
+		return;
+	}
+	outgoing.insert(start, Message::Tick {
+		peer: *peer,
+		tick: tick.tick,
+		prev: tick.last_sent,
+	});
+	tick.last_sent = Some(tick.tick);
+	tick.last_batch_len = outgoing.len() - start;
+}
+
+fn order_incoming(
+	mut incoming: ResMut<MessageIncoming>,
+	mut received: ResMut<ReceivedTicks>,
+	sources: Option<ResMut<MessageIncomingSources>>,
+) {
+	let messages = std::mem::take(&mut incoming.0);
+	let Some(mut sources) = sources else {
+		incoming.0 = received.order(messages);
+		return;
+	};
+	let message_sources = sources.take(messages.len());
+	let messages = messages.into_iter().zip(message_sources).collect();
+	let (messages, ordered_sources) =
+		received.order_with_sources(messages).into_iter().unzip();
+	incoming.0 = messages;
+	sources.set(ordered_sources);
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use bevy::ecs::system::RunSystemOnce;
+	use bevy::prelude::*;
+	use sweet::*;
+
+	fn spawn(index: u32) -> Message {
+		Message::Spawn {
+			entity: Entity::from_raw(index),
+		}
+	}
+	fn tick(peer: u64, tick: u64, prev: Option<u64>) -> Message {
+		Message::Tick {
+			peer: PeerId(peer),
+			tick,
+			prev,
+		}
+	}
+
+	#[test]
+	fn stamps() -> Result<()> {
+		let mut app = App::new();
+		app.add_plugins((ReplicatePlugin, NetworkTickPlugin::default()));
+		let peer = *app.world().resource::<PeerId>();
+
+		app.world_mut().run_schedule(FixedUpdate);
+		expect(app.world().resource::<MessageOutgoing>().len()).to_be(0)?;
+
+		for _ in 0..2 {
+			app.world_mut()
+				.resource_mut::<MessageOutgoing>()
+				.push(spawn(0));
+			app.world_mut().run_schedule(FixedUpdate);
+		}
+		// not yet sent
+		expect(&app.world().resource::<MessageOutgoing>().0).to_be(&vec![
+			tick(*peer, 2, None),
+			spawn(0),
+			tick(*peer, 3, Some(2)),
+			spawn(0),
+		])?;
+
+		app.world_mut().resource_mut::<MessageOutgoing>().clear();
+		app.world_mut()
+			.resource_mut::<MessageOutgoing>()
+			.push(spawn(0));
+		app.world_mut().run_schedule(FixedUpdate);
+		expect(&app.world().resource::<MessageOutgoing>().0)
+			.to_be(&vec![tick(*peer, 4, Some(3)), spawn(0)])?;
+		Ok(())
+	}
+
+	#[test]
+	fn orders() -> Result<()> {
+		let mut received = ReceivedTicks::new(2);
+
+		// unstamped first, then in order, tick 7 waits for tick 5
+		expect(received.order(vec![
+			tick(1, 7, Some(5)),
+			spawn(7),
+			tick(1, 3, None),
+			spawn(3),
+		]))
+		.to_be(vec![spawn(3)])?;
+		expect(received.last_received(PeerId(1))).to_be(Some(3))?;
+
+		expect(received.order(vec![spawn(0), tick(1, 5, Some(3)), spawn(5)]))
+			.to_be(vec![spawn(0), spawn(5), spawn(7)])?;
+		expect(received.last_received(PeerId(1))).to_be(Some(7))?;
+
+		// peers are independent
+		expect(received.order(vec![tick(2, 1, None), spawn(1)]))
+			.to_be(vec![spawn(1)])?;
+		expect(received.last_received(PeerId(2))).to_be(Some(1))?;
+
+		// stale
+		expect(received.order(vec![tick(1, 5, Some(3)), spawn(5)]))
+			.to_be(vec![])?;
+		Ok(())
+	}
+
+	#[test]
+	fn skips_missing() -> Result<()> {
+		let mut received = ReceivedTicks::new(1);
+		received.order(vec![tick(1, 1, None)]);
+		expect(received.order(vec![tick(1, 3, Some(2)), spawn(3)]))
+			.to_be(vec![])?;
+		// tick 2 is lost, released once max pending is exceeded
+		expect(received.order(vec![tick(1, 4, Some(3)), spawn(4)]))
+			.to_be(vec![spawn(3), spawn(4)])?;
+		Ok(())
+	}
+
+	#[test]
+	fn system() -> Result<()> {
+		let mut app = App::new();
+		app.add_plugins((ReplicatePlugin, NetworkTickPlugin::default()));
+		app.world_mut().resource_mut::<MessageIncoming>().extend([
+			tick(1, 2, Some(1)),
+			spawn(2),
+			tick(1, 1, None),
+			spawn(1),
+		]);
+		app.world_mut().run_system_once(super::order_incoming)?;
+		expect(&app.world().resource::<MessageIncoming>().0)
+			.to_be(&vec![spawn(1), spawn(2)])?;
+		Ok(())
+	}
+}
@@ -0,0 +1,92 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Randomly generated id of this app, shared by the networking plugins
+/// that stamp outgoing messages, ie [`NetworkTickPlugin`],
+/// [`MessageSequencePlugin`] and [`NetworkClockPlugin`].
+///
+/// [`NetworkTickPlugin`]: crate::prelude::NetworkTickPlugin
+/// [`MessageSequencePlugin`]: crate::prelude::MessageSequencePlugin
+/// [`NetworkClockPlugin`]: crate::prelude::NetworkClockPlugin
+/// Insert before adding these plugins to use a known id.
+#[derive(
+	Debug,
+	Copy,
+	Clone,
+	PartialEq,
+	Eq,
+	Hash,
+	Deref,
+	Serialize,
+	Deserialize,
+	Resource,
+)]
+pub struct PeerId(pub u64);
+
+impl Default for PeerId {
+	fn default() -> Self { Self::random() }
+}
+
+impl PeerId {
+	pub fn random() -> Self { Self(rand::random()) }
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use bevy::prelude::*;
+	use bevy::time::TimePlugin;
+	use std::time::Duration;
+	use sweet::*;
+
+	#[test]
+	fn shared() -> Result<()> {
+		let (transport, mut remote) = ChannelsTransport::pair();
+		let mut app = App::new();
+		app.add_plugins((TimePlugin, ReplicatePlugin))
+			.add_plugins((
+				NetworkTickPlugin::default(),
+				MessageSequencePlugin::default(),
+				NetworkClockPlugin {
+					ping_interval: Duration::ZERO,
+					..default()
+				},
+			))
+			.add_ticked_transport(transport);
+		let peer = *app.world().resource::<PeerId>();
+
+		// the first update has zero delta
+		app.update();
+		app.update();
+		app.world_mut().run_schedule(FixedUpdate);
+
+		let peers = remote
+			.recv()?
+			.into_iter()
+			.filter_map(|msg| match msg {
+				Message::Batch { peer, .. }
+				| Message::Tick { peer, .. }
+				| Message::Ping { peer, .. } => Some(peer),
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+		expect(peers.len()).to_be_greater_or_equal_to(3)?;
+		expect(peers.iter().all(|other| *other == peer)).to_be_true()?;
+		Ok(())
+	}
+
+	#[test]
+	fn inserted() -> Result<()> {
+		let mut app = App::new();
+		app.insert_resource(PeerId(7));
+		app.add_plugins((TimePlugin, ReplicatePlugin)).add_plugins((
+			NetworkTickPlugin::default(),
+			MessageSequencePlugin::default(),
+			NetworkClockPlugin::default(),
+		));
+		expect(*app.world().resource::<PeerId>()).to_be(PeerId(7))?;
+		Ok(())
+	}
+}
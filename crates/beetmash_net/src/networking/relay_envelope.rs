@@ -0,0 +1,203 @@
+use crate::prelude::*;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Who a [`RelayEnvelope`] should be delivered to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RelayTarget {
+	/// Every other client in the lobby
+	All,
+	Client(ClientId),
+	Clients(Vec<ClientId>),
+}
+
+impl RelayTarget {
+	/// Whether the envelope should be delivered to this client,
+	/// never including the sender.
+	pub fn includes(&self, client: ClientId, sender: ClientId) -> bool {
+		client != sender
+			&& match self {
+				RelayTarget::All => true,
+				RelayTarget::Client(target) => *target == client,
+				RelayTarget::Clients(targets) => targets.contains(&client),
+			}
+	}
+}
+
+/// A websocket frame the relay server understands, delivering the payload
+/// to specific clients instead of broadcasting it.
+/// The server stamps the sender id before delivery, so recipients
+/// always receive envelopes with a `sender`, except for the
+/// [`Self::joined`] frame.
+///
+/// Encoded as [`Self::MAGIC`] followed by the bincode envelope,
+/// frames without the prefix are broadcast as-is.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelayEnvelope {
+	pub target: RelayTarget,
+	/// Set by the server, ignored if sent by a client
+	pub sender: Option<ClientId>,
+	/// Usually the bytes of a `Vec<Message>`
+	pub payload: Vec<u8>,
+}
+
+impl RelayEnvelope {
+	/// Prefix distinguishing envelopes from message frames.
+	pub const MAGIC: &'static [u8; 4] = b"BMRE";
+
+	pub fn new(target: RelayTarget, payload: Vec<u8>) -> Self {
+		Self {
+			target,
+			sender: None,
+			payload,
+		}
+	}
+
+	/// Sent by the server to each client on join, telling it its id.
+	pub fn joined(client_id: ClientId) -> Self {
+		Self::new(RelayTarget::Client(client_id), Vec::new())
+	}
+
+	/// The id of the receiving client if this is a [`Self::joined`] frame.
+	pub fn joined_id(&self) -> Option<ClientId> {
+		match (&self.target, self.sender) {
+			(RelayTarget::Client(client_id), None) => Some(*client_id),
+			_ => None,
+		}
+	}
+
+	/// Wrap messages for delivery to the target
+	pub fn from_messages(
+		target: RelayTarget,
+		messages: &Vec<Message>,
+	) -> Result<Self> {
+		Ok(Self::new(target, Message::vec_into_bytes(messages)?))
+	}
+
+	pub fn is_envelope(bytes: &[u8]) -> bool { bytes.starts_with(Self::MAGIC) }
+
+	pub fn to_bytes(&self) -> Result<Vec<u8>> {
+		let mut bytes = Self::MAGIC.to_vec();
+		bincode::serialize_into(&mut bytes, self)?;
+		Ok(bytes)
+	}
+
+	/// Returns `None` if the bytes are not an envelope.
+	/// # Errors
+	/// If the bytes have the prefix but are not a valid envelope
+	pub fn from_bytes(bytes: &[u8]) -> Result<Option<Self>> {
+		if !Self::is_envelope(bytes) {
+			return Ok(None);
+		}
+		Ok(Some(bincode::deserialize(&bytes[Self::MAGIC.len()..])?))
+	}
+
+	pub fn messages(&self) -> Result<Vec<Message>> {
+		Ok(Message::vec_from_bytes(&self.payload)?)
+	}
+}
+
+/// A frame received by a client of the relay server.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RelayFrame {
+	/// The id of this client, see [`RelayEnvelope::joined`]
+	Joined(ClientId),
+	/// Messages and the client that sent them,
+	/// `None` if they were not sent in an envelope.
+	Messages(Option<ClientId>, Vec<Message>),
+}
+
+impl RelayFrame {
+	/// Unwrap an envelope, or parse the bytes as messages.
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+		match RelayEnvelope::from_bytes(bytes)? {
+			Some(envelope) => match envelope.joined_id() {
+				Some(client_id) => Ok(Self::Joined(client_id)),
+				None => {
+					Ok(Self::Messages(envelope.sender, envelope.messages()?))
+				}
+			},
+			None => Ok(Self::Messages(None, Message::vec_from_bytes(bytes)?)),
+		}
+	}
+
+	/// Messages grouped by sender, as returned by
+	/// [`Transport::recv_with_sender`], storing the id
+	/// from any [`Self::Joined`] frame.
+	pub fn collect(
+		frames: Vec<Self>,
+		client_id: &mut Option<ClientId>,
+	) -> Vec<SenderMessages> {
+		frames
+			.into_iter()
+			.filter_map(|frame| match frame {
+				Self::Joined(id) => {
+					*client_id = Some(id);
+					None
+				}
+				Self::Messages(sender, messages) => Some((sender, messages)),
+			})
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use bevy::prelude::*;
+	use sweet::*;
+
+	#[test]
+	fn works() -> Result<()> {
+		let messages = vec![Message::Spawn {
+			entity: Entity::from_raw(1),
+		}];
+		let envelope =
+			RelayEnvelope::from_messages(RelayTarget::Client(2), &messages)?;
+		let bytes = envelope.to_bytes()?;
+		expect(RelayEnvelope::from_bytes(&bytes)?.as_ref())
+			.to_be(Some(&envelope))?;
+		expect(envelope.messages()?).to_be(messages.clone())?;
+
+		let raw = Message::vec_into_bytes(&messages)?;
+		expect(RelayEnvelope::from_bytes(&raw)?).to_be_none()?;
+		expect(RelayEnvelope::from_bytes(b"BMRE").is_err()).to_be_true()?;
+		Ok(())
+	}
+
+	#[test]
+	fn frames() -> Result<()> {
+		let messages = vec![Message::Spawn {
+			entity: Entity::from_raw(1),
+		}];
+		let mut envelope =
+			RelayEnvelope::from_messages(RelayTarget::Client(2), &messages)?;
+		envelope.sender = Some(3);
+		let frames = vec![
+			RelayFrame::from_bytes(&RelayEnvelope::joined(2).to_bytes()?)?,
+			RelayFrame::from_bytes(&envelope.to_bytes()?)?,
+			RelayFrame::from_bytes(&Message::vec_into_bytes(&messages)?)?,
+		];
+		expect(&frames[0]).to_be(&RelayFrame::Joined(2))?;
+
+		let mut client_id = None;
+		expect(RelayFrame::collect(frames, &mut client_id))
+			.to_be(vec![(Some(3), messages.clone()), (None, messages)])?;
+		expect(client_id).to_be(Some(2))?;
+		Ok(())
+	}
+
+	#[test]
+	fn targets() -> Result<()> {
+		expect(RelayTarget::All.includes(1, 0)).to_be_true()?;
+		expect(RelayTarget::All.includes(0, 0)).to_be_false()?;
+		expect(RelayTarget::Client(1).includes(1, 0)).to_be_true()?;
+		expect(RelayTarget::Client(1).includes(2, 0)).to_be_false()?;
+		expect(RelayTarget::Clients(vec![0, 2]).includes(2, 0)).to_be_true()?;
+		expect(RelayTarget::Clients(vec![0, 2]).includes(0, 0))
+			.to_be_false()?;
+		Ok(())
+	}
+}
@@ -3,9 +3,19 @@ use crate::prelude::*;
 use flume::Receiver;
 use flume::Sender;
 
+/// Messages and the relay client that sent them, if known.
+pub type SenderMessages = (Option<ClientId>, Vec<Message>);
+
 pub trait Transport {
 	fn send(&mut self, messages: &Vec<Message>) -> Result<(), anyhow::Error>;
 	fn recv(&mut self) -> Result<Vec<Message>, anyhow::Error>;
+	/// Like [`Self::recv`], grouped by the relay client that sent them
+	/// if known, see [`RelayEnvelope`].
+	fn recv_with_sender(
+		&mut self,
+	) -> Result<Vec<SenderMessages>, anyhow::Error> {
+		Ok(vec![(None, self.recv()?)])
+	}
 }
 
 pub struct ChannelsTransport {
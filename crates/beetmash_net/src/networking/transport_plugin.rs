@@ -38,15 +38,43 @@ pub impl App {
 		);
 		self
 	}
+	/// Receive every frame and send once per network tick,
+	/// see [`NetworkTickPlugin`].
+	fn add_ticked_transport<T: 'static + Transport>(
+		&mut self,
+		transport: T,
+	) -> &mut Self {
+		self.insert_non_send_resource(transport)
+			.add_systems(
+				Update,
+				transport_incoming::<T>.before(MessageIncomingSet),
+			)
+			.add_systems(
+				FixedUpdate,
+				transport_outgoing::<T>.after(NetworkTickSet),
+			);
+		self
+	}
 }
 
 pub(crate) fn transport_incoming<T: Transport>(
 	mut events: ResMut<MessageIncoming>,
+	mut sources: ResMut<MessageIncomingSources>,
 	mut transport: NonSendMut<T>,
+	mut stats: Option<ResMut<NetworkStats>>,
 ) {
-	if let Some(messages) = transport.recv().ok_or(|e| log::error!("foo {e}")) {
+	let Some(received) =
+		transport.recv_with_sender().ok_or(|e| log::error!("{e}"))
+	else {
+		return;
+	};
+	for (sender, messages) in received {
+		if let Some(stats) = stats.as_mut() {
+			stats.record_received(std::any::type_name::<T>(), &messages);
+		}
 		for message in messages {
 			// log::info!("<<< MESSAGE: {:?}", message);
+			sources.push(events.len(), MessageSource::new(None, sender));
 			events.push(message);
 		}
 	}
@@ -55,13 +83,28 @@ pub(crate) fn transport_incoming<T: Transport>(
 pub(crate) fn transport_outgoing<T: Transport>(
 	mut outgoing: ResMut<MessageOutgoing>,
 	mut transport: NonSendMut<T>,
+	stats: Option<ResMut<NetworkStats>>,
+	sequence: Option<ResMut<MessageSequence>>,
+	peer: Option<Res<PeerId>>,
 ) {
 	if outgoing.is_empty() {
 		return;
 	}
 
-	let messages = outgoing.drain(..).collect();
-	transport.send(&messages).ok_or(|e| log::error!("{e}"));
+	let mut messages = outgoing.drain(..).collect::<Vec<_>>();
+	if let (Some(mut sequence), Some(peer)) = (sequence, peer) {
+		messages.insert(0, sequence.next_marker(*peer));
+	}
+	if transport
+		.send(&messages)
+		.ok_or(|e| log::error!("{e}"))
+		.is_none()
+	{
+		return;
+	}
+	if let Some(mut stats) = stats {
+		stats.record_sent(std::any::type_name::<T>(), &messages);
+	}
 	// {
 	// 	#[cfg(target_arch = "wasm32")]
 	// 	wasm_bindgen_futures::spawn_local(async move {
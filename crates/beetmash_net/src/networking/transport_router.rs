@@ -0,0 +1,471 @@
+use crate::prelude::*;
+use bevy::prelude::*;
+use bevy::time::common_conditions::on_timer;
+use bevy::utils::HashMap;
+use forky::prelude::ResultTEExt;
+use std::time::Duration;
+
+/// Name of a transport added with [`AppExtTransportRouter::add_routed_transport`].
+pub type TransportName = String;
+
+/// Multiple named transports, each receiving a subset of
+/// [`MessageOutgoing`] according to its routes.
+/// Messages are routed by the first match of:
+/// 1. routes for the message's [`RegistrationId`]
+/// 2. routes for the message's [`MessageKind`]
+/// 3. all transports
+///
+/// This drains [`MessageOutgoing`] so should not be combined with
+/// [`AppExtTransport::add_transport`].
+#[derive(Default)]
+pub struct TransportRouter {
+	pub transports: Vec<(TransportName, Box<dyn Transport>)>,
+	pub routes: TransportRoutes,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct TransportRoutes {
+	pub kinds: HashMap<MessageKind, Vec<TransportName>>,
+	pub registrations: HashMap<RegistrationId, Vec<TransportName>>,
+}
+
+impl TransportRoutes {
+	/// Whether the message should be sent via the named transport.
+	/// [`MessageKind::Marker`] messages are sent to every transport.
+	pub fn is_routed(&self, name: &str, message: &Message) -> bool {
+		if message.kind() == MessageKind::Marker {
+			return true;
+		}
+		let routes = message
+			.reg_id()
+			.and_then(|id| self.registrations.get(&id))
+			.or_else(|| self.kinds.get(&message.kind()));
+		match routes {
+			Some(routes) => routes.iter().any(|route| route == name),
+			None => true,
+		}
+	}
+}
+
+/// Where a message in [`MessageIncoming`] was received from.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MessageSource {
+	/// The routed transport, see [`AppExtTransportRouter::add_routed_transport`]
+	pub transport: Option<TransportName>,
+	/// The relay client that sent it, see [`RelayEnvelope::sender`]
+	pub sender: Option<ClientId>,
+}
+
+impl MessageSource {
+	pub fn new(
+		transport: Option<TransportName>,
+		sender: Option<ClientId>,
+	) -> Self {
+		Self { transport, sender }
+	}
+}
+
+/// The source of each message in [`MessageIncoming`],
+/// stored at the same index and kept aligned when messages are
+/// deduplicated or reordered. Messages pushed by other systems
+/// have no source.
+#[derive(Debug, Default, Clone, PartialEq, Resource)]
+pub struct MessageIncomingSources {
+	sources: Vec<MessageSource>,
+	/// The source of the message being applied by [`handle_incoming`]
+	current: MessageSource,
+}
+
+impl MessageIncomingSources {
+	/// The transport of the message at this index of [`MessageIncoming`]
+	pub fn source(&self, index: usize) -> Option<&str> {
+		self.sources
+			.get(index)
+			.and_then(|source| source.transport.as_deref())
+	}
+
+	/// The relay client that sent the message at this index
+	/// of [`MessageIncoming`]
+	pub fn sender(&self, index: usize) -> Option<ClientId> {
+		self.sources.get(index).and_then(|source| source.sender)
+	}
+
+	/// The transport of the message currently being applied,
+	/// for observers and commands run by [`handle_incoming`].
+	pub fn current(&self) -> Option<&str> { self.current.transport.as_deref() }
+
+	/// The relay client that sent the message currently being applied.
+	pub fn current_sender(&self) -> Option<ClientId> { self.current.sender }
+
+	pub fn len(&self) -> usize { self.sources.len() }
+	pub fn is_empty(&self) -> bool { self.sources.is_empty() }
+
+	/// Add the source of the next message, padding messages
+	/// pushed by other systems with the default.
+	pub fn push(&mut self, index: usize, source: MessageSource) {
+		self.sources.resize(index, MessageSource::default());
+		self.sources.push(source);
+	}
+
+	/// Take the sources of all `len` incoming messages.
+	pub fn take(&mut self, len: usize) -> Vec<MessageSource> {
+		self.sources.resize(len, MessageSource::default());
+		std::mem::take(&mut self.sources)
+	}
+
+	/// Replace the sources after the incoming messages are changed,
+	/// see [`Self::take`].
+	pub fn set(&mut self, sources: Vec<MessageSource>) {
+		self.sources = sources;
+	}
+
+	/// Set the current source while applying the message at this index
+	pub(crate) fn set_current(&mut self, index: Option<usize>) {
+		self.current = index
+			.and_then(|index| self.sources.get(index).cloned())
+			.unwrap_or_default();
+	}
+
+	pub fn clear(&mut self) {
+		self.sources.clear();
+		self.current = MessageSource::default();
+	}
+}
+
+#[extend::ext(name=AppExtTransportRouter)]
+pub impl App {
+	/// Add a named transport to the [`TransportRouter`],
+	/// adding the router systems if this is the first transport.
+	fn add_routed_transport(
+		&mut self,
+		name: impl Into<TransportName>,
+		transport: impl 'static + Transport,
+	) -> &mut Self {
+		self.add_routed_transport_with_duration(
+			name,
+			transport,
+			DEFAULT_TRANSPORT_INTERVAL,
+		)
+	}
+	/// The interval is only applied when adding the first transport.
+	fn add_routed_transport_with_duration(
+		&mut self,
+		name: impl Into<TransportName>,
+		transport: impl 'static + Transport,
+		interval: Duration,
+	) -> &mut Self {
+		if self
+			.world()
+			.get_non_send_resource::<TransportRouter>()
+			.is_none()
+		{
+			self.insert_non_send_resource(TransportRouter::default())
+				.add_systems(
+					Update,
+					(
+						router_incoming
+							.run_if(on_timer(interval))
+							.before(MessageIncomingSet),
+						router_outgoing
+							.run_if(on_timer(interval))
+							.after(MessageOutgoingSet),
+					),
+				);
+		}
+		self.world_mut()
+			.non_send_resource_mut::<TransportRouter>()
+			.transports
+			.push((name.into(), Box::new(transport)));
+		self
+	}
+
+	/// Send all messages of this kind via the named transports,
+	/// unless overridden by [`Self::route_registration`].
+	fn route_kind(
+		&mut self,
+		kind: MessageKind,
+		names: impl IntoIterator<Item = impl Into<TransportName>>,
+	) -> &mut Self {
+		let names = names.into_iter().map(|name| name.into()).collect();
+		self.world_mut()
+			.non_send_resource_mut::<TransportRouter>()
+			.routes
+			.kinds
+			.insert(kind, names);
+		self
+	}
+
+	/// Send all messages for this registration via the named transports.
+	fn route_registration(
+		&mut self,
+		reg_id: RegistrationId,
+		names: impl IntoIterator<Item = impl Into<TransportName>>,
+	) -> &mut Self {
+		let names = names.into_iter().map(|name| name.into()).collect();
+		self.world_mut()
+			.non_send_resource_mut::<TransportRouter>()
+			.routes
+			.registrations
+			.insert(reg_id, names);
+		self
+	}
+
+	/// Send all messages for this type via the named transports.
+	/// # Panics
+	/// If the type has not been registered for replication.
+	fn route_type<T: 'static>(
+		&mut self,
+		names: impl IntoIterator<Item = impl Into<TransportName>>,
+	) -> &mut Self {
+		let reg_id = self
+			.world()
+			.resource::<ReplicateRegistry>()
+			.registration_id::<T>();
+		self.route_registration(reg_id, names)
+	}
+}
+
+pub(crate) fn router_incoming(
+	mut incoming: ResMut<MessageIncoming>,
+	mut sources: ResMut<MessageIncomingSources>,
+	mut router: NonSendMut<TransportRouter>,
+	mut stats: Option<ResMut<NetworkStats>>,
+) {
+	for (name, transport) in router.transports.iter_mut() {
+		let Some(received) = transport
+			.recv_with_sender()
+			.ok_or(|e| log::error!("{name}: {e}"))
+		else {
+			continue;
+		};
+		for (sender, messages) in received {
+			if let Some(stats) = stats.as_mut() {
+				stats.record_received(name, &messages);
+			}
+			for message in messages {
+				let source = MessageSource::new(Some(name.clone()), sender);
+				sources.push(incoming.len(), source);
+				incoming.push(message);
+			}
+		}
+	}
+}
+
+pub(crate) fn router_outgoing(
+	mut outgoing: ResMut<MessageOutgoing>,
+	mut router: NonSendMut<TransportRouter>,
+	mut stats: Option<ResMut<NetworkStats>>,
+	mut sequence: Option<ResMut<MessageSequence>>,
+	peer: Option<Res<PeerId>>,
+) {
+	if outgoing.is_empty() {
+		return;
+	}
+	let messages = outgoing.drain(..).collect::<Vec<_>>();
+	// every routed copy shares a marker so duplicates are dropped
+	let marker = sequence
+		.as_mut()
+		.zip(peer)
+		.map(|(sequence, peer)| sequence.next_marker(*peer));
+	let TransportRouter { transports, routes } = router.as_mut();
+	for (name, transport) in transports.iter_mut() {
+		let mut routed = messages
+			.iter()
+			.filter(|message| routes.is_routed(name, message))
+			.cloned()
+			.collect::<Vec<_>>();
+		if routed.is_empty() {
+			continue;
+		}
+		if let Some(marker) = &marker {
+			routed.insert(0, marker.clone());
+		}
+		if transport
+			.send(&routed)
+			.ok_or(|e| log::error!("{name}: {e}"))
+			.is_none()
+		{
+			continue;
+		}
+		if let Some(stats) = stats.as_mut() {
+			stats.record_sent(name, &routed);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use anyhow::Result;
+	use bevy::ecs::system::RunSystemOnce;
+	use serde::Deserialize;
+	use serde::Serialize;
+	use sweet::*;
+
+	#[derive(Debug, Clone, Event, Serialize, Deserialize, PartialEq)]
+	pub struct MyEvent(pub i32);
+	#[derive(Debug, Clone, Event, Serialize, Deserialize, PartialEq)]
+	pub struct OtherEvent(pub i32);
+
+	fn send_event<T: 'static>(app: &App, value: i32) -> Result<Message> {
+		Ok(Message::SendEvent {
+			reg_id: app
+				.world()
+				.resource::<ReplicateRegistry>()
+				.registration_id::<T>(),
+			payload: MessagePayload::new(value)?,
+		})
+	}
+
+	#[test]
+	fn outgoing() -> Result<()> {
+		let (host, mut host_remote) = ChannelsTransport::pair();
+		let (relay, mut relay_remote) = ChannelsTransport::pair();
+
+		let mut app = App::new();
+		app.add_plugins(ReplicatePlugin)
+			.add_event::<MyEvent>()
+			.add_event::<OtherEvent>()
+			.replicate_event_outgoing::<MyEvent>()
+			.replicate_event_outgoing::<OtherEvent>()
+			.add_routed_transport("host", host)
+			.add_routed_transport("relay", relay)
+			.route_kind(MessageKind::Event, ["relay"])
+			.route_type::<MyEvent>(["host"]);
+
+		let spawn = Message::Spawn {
+			entity: Entity::PLACEHOLDER,
+		};
+		let my_event = send_event::<MyEvent>(&app, 1)?;
+		let other_event = send_event::<OtherEvent>(&app, 2)?;
+		app.world_mut().resource_mut::<MessageOutgoing>().extend([
+			spawn.clone(),
+			my_event.clone(),
+			other_event.clone(),
+		]);
+		app.world_mut().run_system_once(router_outgoing)?;
+
+		expect(app.world().resource::<MessageOutgoing>().len()).to_be(0)?;
+		expect(host_remote.recv()?).to_be(vec![spawn.clone(), my_event])?;
+		expect(relay_remote.recv()?).to_be(vec![spawn, other_event])?;
+		Ok(())
+	}
+
+	#[test]
+	fn incoming() -> Result<()> {
+		let (host, mut host_remote) = ChannelsTransport::pair();
+		let (relay, mut relay_remote) = ChannelsTransport::pair();
+
+		let mut app = App::new();
+		app.add_plugins(ReplicatePlugin)
+			.add_routed_transport("host", host)
+			.add_routed_transport("relay", relay);
+
+		let spawn = Message::Spawn {
+			entity: Entity::PLACEHOLDER,
+		};
+		relay_remote.send(&vec![spawn.clone()])?;
+		host_remote.send(&vec![spawn.clone(), spawn.clone()])?;
+		app.world_mut().run_system_once(router_incoming)?;
+
+		expect(app.world().resource::<MessageIncoming>().len()).to_be(3)?;
+		let sources = app.world().resource::<MessageIncomingSources>();
+		expect(sources.source(0)).to_be(Some("host"))?;
+		expect(sources.source(1)).to_be(Some("host"))?;
+		expect(sources.source(2)).to_be(Some("relay"))?;
+
+		app.update();
+		expect(app.world().resource::<MessageIncomingSources>().len())
+			.to_be(0)?;
+		Ok(())
+	}
+
+	#[derive(Debug, Default, Resource)]
+	struct Applied(Vec<(i32, Option<String>)>);
+
+	#[test]
+	fn current_source() -> Result<()> {
+		let (host, mut host_remote) = ChannelsTransport::pair();
+		let (relay, mut relay_remote) = ChannelsTransport::pair();
+
+		let mut app = App::new();
+		app.add_plugins((ReplicatePlugin, NetworkTickPlugin::default()))
+			.add_routed_transport("host", host)
+			.add_routed_transport("relay", relay)
+			.replicate_observer_incoming::<MyEvent>()
+			.init_resource::<Applied>()
+			.add_observer(
+				|trigger: Trigger<MyEvent>,
+				 sources: Res<MessageIncomingSources>,
+				 mut applied: ResMut<Applied>| {
+					applied.0.push((
+						trigger.event().0,
+						sources.current().map(|name| name.to_string()),
+					));
+				},
+			);
+		let reg_id = app
+			.world()
+			.resource::<ReplicateRegistry>()
+			.registration_id::<MyEvent>();
+		let batch = |tick: u64, prev: Option<u64>| -> Result<Vec<Message>> {
+			Ok(vec![
+				Message::Tick {
+					peer: PeerId(1),
+					tick,
+					prev,
+				},
+				Message::SendObserver {
+					reg_id,
+					payload: MessagePayload::new(MyEvent(tick as i32))?,
+					targets: Vec::new(),
+					owned_targets: Vec::new(),
+				},
+			])
+		};
+		// the host batch is received first but applied second
+		host_remote.send(&batch(2, Some(1))?)?;
+		relay_remote.send(&batch(1, None)?)?;
+		app.world_mut().run_system_once(router_incoming)?;
+		app.update();
+
+		expect(&app.world().resource::<Applied>().0).to_be(&vec![
+			(1, Some("relay".to_string())),
+			(2, Some("host".to_string())),
+		])?;
+		Ok(())
+	}
+
+	#[test]
+	fn dedup() -> Result<()> {
+		let (host, host_remote) = ChannelsTransport::pair();
+		let (relay, relay_remote) = ChannelsTransport::pair();
+
+		let mut sender = App::new();
+		sender
+			.add_plugins((ReplicatePlugin, MessageSequencePlugin::default()))
+			.add_routed_transport("host", host)
+			.add_routed_transport("relay", relay);
+		let mut receiver = App::new();
+		receiver
+			.add_plugins((ReplicatePlugin, MessageSequencePlugin::default()))
+			.add_routed_transport("host", host_remote)
+			.add_routed_transport("relay", relay_remote);
+
+		sender.world_mut().resource_mut::<MessageOutgoing>().push(
+			Message::Spawn {
+				entity: Entity::from_raw(7),
+			},
+		);
+		sender.world_mut().run_system_once(router_outgoing)?;
+
+		let num_entities = |app: &App| app.world().iter_entities().count();
+		let before = num_entities(&receiver);
+		receiver.world_mut().run_system_once(router_incoming)?;
+		expect(receiver.world().resource::<MessageIncoming>().len())
+			.to_be(4)?;
+		receiver.update();
+		expect(num_entities(&receiver)).to_be(before + 1)?;
+		Ok(())
+	}
+}
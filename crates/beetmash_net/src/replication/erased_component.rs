@@ -0,0 +1,163 @@
+use anyhow::Result;
+use bevy::ecs::component::ComponentId;
+use bevy::prelude::*;
+use bevy::ptr::OwningPtr;
+use std::alloc::Layout;
+use std::any::Any;
+use std::ptr::NonNull;
+
+/// A component value whose type is only known by its [`ComponentId`],
+/// so that several can be inserted at once with [`insert_erased`].
+pub struct ErasedComponent {
+	id: ComponentId,
+	/// Always the type of the component with this id
+	value: Box<dyn Any>,
+}
+
+impl ErasedComponent {
+	pub fn new<T: Component>(world: &mut World, value: T) -> Self {
+		Self {
+			id: world.register_component::<T>(),
+			value: Box::new(value),
+		}
+	}
+
+	/// Create from a concrete reflected value, ie from
+	/// [`ReflectFromReflect`](bevy::reflect::ReflectFromReflect).
+	/// Errors if the value is not the type of the component.
+	pub fn from_reflect(
+		world: &World,
+		id: ComponentId,
+		value: Box<dyn Reflect>,
+	) -> Result<Self> {
+		let value = value.into_any();
+		let type_id = world
+			.components()
+			.get_info(id)
+			.and_then(|info| info.type_id());
+		if type_id != Some(value.as_ref().type_id()) {
+			anyhow::bail!("value does not match component {id:?}");
+		}
+		Ok(Self { id, value })
+	}
+
+	pub fn id(&self) -> ComponentId { self.id }
+}
+
+/// Insert all components in a single archetype move, so that hooks
+/// and observers see the complete set. Later duplicates are dropped,
+/// as are components created for another world whose id does not
+/// match the type of the value in this one.
+pub fn insert_erased(
+	entity: &mut EntityWorldMut,
+	components: Vec<ErasedComponent>,
+) {
+	let mut ids = Vec::with_capacity(components.len());
+	let mut values = Vec::with_capacity(components.len());
+	for component in components {
+		if ids.contains(&component.id) {
+			continue;
+		}
+		let type_id = entity
+			.world()
+			.components()
+			.get_info(component.id)
+			.and_then(|info| info.type_id());
+		if type_id != Some(component.value.as_ref().type_id()) {
+			log::error!(
+				"value does not match component {:?} in this world",
+				component.id
+			);
+			continue;
+		}
+		ids.push(component.id);
+		let layout = Layout::for_value(component.value.as_ref());
+		let ptr = Box::into_raw(component.value) as *mut u8;
+		values.push((NonNull::new(ptr).unwrap(), layout));
+	}
+	// SAFETY: ids are unique and each value is the type
+	// of its component in this world, checked above.
+	// Ownership of the values moves into the world.
+	unsafe {
+		entity.insert_by_ids(
+			&ids,
+			values.iter().map(|(ptr, _)| OwningPtr::new(*ptr)),
+		);
+	}
+	for (ptr, layout) in values {
+		if layout.size() != 0 {
+			// SAFETY: allocated by the box with this layout,
+			// the value itself has been moved out.
+			unsafe { std::alloc::dealloc(ptr.as_ptr(), layout) }
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use sweet::*;
+
+	#[derive(Debug, PartialEq, Component)]
+	struct Health(u32);
+	#[derive(Debug, PartialEq, Component)]
+	struct Name(String);
+	#[derive(Debug, PartialEq, Component)]
+	struct Marker;
+
+	#[derive(Default, Resource)]
+	struct Seen(Vec<bool>);
+
+	#[test]
+	fn works() -> Result<()> {
+		let mut world = World::new();
+		world.init_resource::<Seen>();
+		world.add_observer(
+			|trigger: Trigger<OnAdd, Health>,
+			 query: Query<Has<Name>>,
+			 mut seen: ResMut<Seen>| {
+				seen.0.push(query.get(trigger.entity()).unwrap());
+			},
+		);
+		let components = vec![
+			ErasedComponent::new(&mut world, Health(7)),
+			ErasedComponent::new(&mut world, Name("foo".into())),
+			ErasedComponent::new(&mut world, Marker),
+			ErasedComponent::new(&mut world, Health(8)),
+		];
+		let mut entity = world.spawn_empty();
+		insert_erased(&mut entity, components);
+		let entity = entity.id();
+		world.flush();
+
+		expect(world.get::<Health>(entity)).to_be(Some(&Health(7)))?;
+		expect(world.get::<Name>(entity)).to_be(Some(&Name("foo".into())))?;
+		expect(world.get::<Marker>(entity)).to_be(Some(&Marker))?;
+		expect(&world.resource::<Seen>().0).to_be(&vec![true])?;
+		Ok(())
+	}
+
+	#[test]
+	fn other_world() -> Result<()> {
+		let mut world1 = World::new();
+		let health = ErasedComponent::new(&mut world1, Health(7));
+		let mut world2 = World::new();
+		// same id as health in world1
+		world2.register_component::<Name>();
+		let mut entity = world2.spawn_empty();
+		insert_erased(&mut entity, vec![health]);
+		expect(entity.contains::<Name>()).to_be_false()?;
+		expect(entity.contains::<Health>()).to_be_false()?;
+		Ok(())
+	}
+
+	#[test]
+	fn from_reflect() -> Result<()> {
+		let mut world = World::new();
+		let id = world.register_component::<Health>();
+		let value: Box<dyn Reflect> = Box::new(7_u32);
+		expect(ErasedComponent::from_reflect(&world, id, value).is_err())
+			.to_be_true()?;
+		Ok(())
+	}
+}
@@ -1,116 +1,411 @@
 use crate::prelude::*;
+use bevy::ecs::world::CommandQueue;
 use bevy::prelude::*;
 use forky::prelude::ResultTEExt;
 
+/// Applies [`MessageIncoming`] in the order received, regardless of kind.
+/// Commands queued by each message are applied before the next message,
+/// so an event sent after a spawn can read the spawned entity.
+pub fn handle_incoming(world: &mut World) {
+	handle_incoming_where(world, |_| true);
+}
+
+/// Applies every incoming message except events.
+#[deprecated = "use `handle_incoming`, which applies all messages in order"]
+pub fn handle_incoming_commands(world: &mut World) {
+	handle_incoming_where(world, |msg| {
+		!matches!(msg, Message::SendEvent { .. })
+	});
+}
+
+/// Applies incoming events.
+#[deprecated = "use `handle_incoming`, which applies all messages in order"]
+pub fn handle_incoming_world(world: &mut World) {
+	handle_incoming_where(world, |msg| {
+		matches!(msg, Message::SendEvent { .. })
+	});
+}
+
+fn handle_incoming_where(world: &mut World, filter: fn(&Message) -> bool) {
+	let messages =
+		std::mem::take(&mut world.resource_mut::<MessageIncoming>().0);
+	let mut queue = CommandQueue::default();
+	for (index, msg) in messages.iter().enumerate() {
+		if !filter(msg) {
+			continue;
+		}
+		let sender = world
+			.get_resource_mut::<MessageIncomingSources>()
+			.and_then(|mut sources| {
+				sources.set_current(Some(index));
+				sources.current_sender()
+			});
+		let start = world.change_tick();
+		RemoteChanges::start_message(world, sender, msg);
+		handle_message(world, &mut queue, sender, msg);
+		queue.apply(world);
+		RemoteChanges::record_message(world, sender, msg, start);
+	}
+	if let Some(mut sources) =
+		world.get_resource_mut::<MessageIncomingSources>()
+	{
+		sources.set_current(None);
+	}
+	world.resource_mut::<MessageIncoming>().0 = messages;
+}
 
-pub fn handle_incoming_commands(
-	mut commands: Commands,
-	mut registrations: ResMut<ReplicateRegistry>,
-	incoming: Res<MessageIncoming>,
+/// Entities are mapped separately for each sender,
+/// see [`ReplicateRegistry::client_entities`].
+fn handle_message(
+	world: &mut World,
+	queue: &mut CommandQueue,
+	sender: Option<ClientId>,
+	msg: &Message,
 ) {
-	for msg in incoming.iter() {
-		match msg {
-			Message::Spawn { entity } => {
-				let local = commands.spawn_empty().id();
-				registrations.entities.insert(*entity, local);
+	// messages that require world access
+	match msg {
+		Message::Spawn { entity } => {
+			let local = world.spawn_empty().id();
+			world
+				.resource_mut::<ReplicateRegistry>()
+				.map_entity(sender, *entity, local);
+			return;
+		}
+		Message::SpawnWith { entity, components } => {
+			spawn_with(world, sender, *entity, components);
+			return;
+		}
+		Message::Despawn { entity } => {
+			let local = world
+				.resource_mut::<ReplicateRegistry>()
+				.unmap_entity(sender, *entity);
+			match local {
+				Some(local) => {
+					world.despawn(local);
+				}
+				None => {
+					log::warn!("despawned entity not replicated: {entity}")
+				}
 			}
-			Message::Despawn { entity } => {
-				commands.entity(*entity).despawn();
+			return;
+		}
+		Message::SendEvent { reg_id, payload } => {
+			let fns = world
+				.resource::<ReplicateRegistry>()
+				.incoming_event_fns
+				.get(reg_id)
+				.copied();
+			if let Some(fns) = fns {
+				(fns.send)(world, payload).ok_or(|e| log::error!("{e}"));
 			}
-			Message::Add {
-				entity,
-				reg_id,
-				payload,
-			} => {
-				if let Some((entity, fns)) =
-					registrations.entity_fns(*entity, *reg_id)
-				{
-					(fns.insert)(&mut commands.entity(entity), &payload)
-						.ok_or(|e| log::error!("{e}"));
-				}
+			return;
+		}
+		_ => {}
+	}
+	let registrations = world.resource::<ReplicateRegistry>();
+	let mut commands = Commands::new(queue, world);
+	match msg {
+		Message::Spawn { .. }
+		| Message::SpawnWith { .. }
+		| Message::Despawn { .. }
+		| Message::SendEvent { .. } => {
+			// applied with world access above
+		}
+		Message::Add {
+			entity,
+			reg_id,
+			payload,
+		} => {
+			if let Some((entity, fns)) =
+				entity_fns(world, registrations, sender, *entity, *reg_id)
+			{
+				(fns.insert)(&mut commands.entity(entity), payload)
+					.ok_or(|e| log::error!("{e}"));
 			}
-			Message::Change {
-				entity,
-				reg_id,
-				payload,
-			} => {
-				if let Some((entity, fns)) =
-					registrations.entity_fns(*entity, *reg_id)
-				{
-					(fns.change)(&mut commands.entity(entity), payload)
-						.ok_or(|e| log::error!("{e}"));
-				}
+		}
+		Message::Change {
+			entity,
+			reg_id,
+			payload,
+		} => {
+			if let Some((entity, fns)) =
+				entity_fns(world, registrations, sender, *entity, *reg_id)
+			{
+				(fns.change)(&mut commands.entity(entity), payload)
+					.ok_or(|e| log::error!("{e}"));
 			}
-			Message::Remove { entity, reg_id } => {
-				if let Some((entity, fns)) =
-					registrations.entity_fns(*entity, *reg_id)
-				{
-					(fns.remove)(&mut commands.entity(entity));
-				}
+		}
+		Message::Remove { entity, reg_id } => {
+			if let Some((entity, fns)) =
+				entity_fns(world, registrations, sender, *entity, *reg_id)
+			{
+				(fns.remove)(&mut commands.entity(entity));
 			}
-			Message::InsertResource { reg_id, payload } => {
-				if let Some(fns) =
-					registrations.incoming_resource_fns.get(reg_id)
-				{
-					(fns.insert)(&mut commands, payload)
-						.ok_or(|e| log::error!("{e}"));
-				}
+		}
+		Message::InsertResource { reg_id, payload } => {
+			if let Some(fns) = registrations.incoming_resource_fns.get(reg_id) {
+				(fns.insert)(&mut commands, payload)
+					.ok_or(|e| log::error!("{e}"));
 			}
-			Message::ChangeResource { reg_id, payload } => {
-				if let Some(fns) =
-					registrations.incoming_resource_fns.get(reg_id)
-				{
-					(fns.change)(&mut commands, payload)
-						.ok_or(|e| log::error!("{e}"));
-				}
+		}
+		Message::ChangeResource { reg_id, payload } => {
+			if let Some(fns) = registrations.incoming_resource_fns.get(reg_id) {
+				(fns.change)(&mut commands, payload)
+					.ok_or(|e| log::error!("{e}"));
 			}
-			Message::RemoveResource { reg_id } => {
-				if let Some(fns) =
-					registrations.incoming_resource_fns.get(reg_id)
-				{
-					(fns.remove)(&mut commands);
-				}
+		}
+		Message::RemoveResource { reg_id } => {
+			if let Some(fns) = registrations.incoming_resource_fns.get(reg_id) {
+				(fns.remove)(&mut commands);
 			}
-			Message::SendObserver { reg_id, payload } => {
-				if let Some(fns) =
-					registrations.incoming_observer_fns.get(reg_id)
-				{
-					(fns.send)(&mut commands, payload)
-						.ok_or(|e| log::error!("{e}"));
+		}
+		Message::SendObserver {
+			reg_id,
+			payload,
+			targets,
+			owned_targets,
+		} => {
+			if let Some(fns) = registrations.incoming_observer_fns.get(reg_id) {
+				let mut local_targets = targets
+					.iter()
+					.filter_map(|remote| {
+						registrations.local_entity(sender, *remote)
+					})
+					.collect::<Vec<_>>();
+				local_targets.extend(
+					owned_targets
+						.iter()
+						.filter(|entity| world.get_entity(**entity).is_ok()),
+				);
+				if local_targets.len() != targets.len() + owned_targets.len() {
+					log::warn!(
+						"observer target entities not replicated: {targets:?} {owned_targets:?}"
+					);
+					// do not trigger globally if none were replicated
+					if local_targets.is_empty() {
+						return;
+					}
 				}
+				(fns.send)(&mut commands, payload, local_targets)
+					.ok_or(|e| log::error!("{e}"));
 			}
-			Message::SendEvent {
-				reg_id: _,
-				payload: _,
-			} => {
-				// events require world access
-			}
+		}
+		Message::Ping { .. } | Message::Pong { .. } => {
+			// handled by the NetworkClockPlugin
+		}
+		Message::AssetRequest { .. } | Message::AssetChunk { .. } => {
+			// handled by the AssetStreamPlugin
+		}
+		Message::Batch { .. } | Message::Tick { .. } => {
+			// removed by the MessageSequencePlugin and NetworkTickPlugin
 		}
 	}
 }
 
-pub fn handle_incoming_world(world: &mut World) {
-	let registrations = world.resource::<ReplicateRegistry>();
-	let events = world
-		.resource::<MessageIncoming>()
+/// Spawn the entity and insert all components at once
+fn spawn_with(
+	world: &mut World,
+	sender: Option<ClientId>,
+	remote: Entity,
+	components: &[(RegistrationId, MessagePayload)],
+) {
+	let fns = components
 		.iter()
-		.filter_map(|msg| match msg {
-			Message::SendEvent { reg_id, payload } => {
-				if let Some(fns) = registrations.incoming_event_fns.get(reg_id)
-				{
-					Some((fns.clone(), payload.clone()))
-				} else {
-					None
-				}
-			}
-			_ => {
-				// all other messages are handled by `handle_incoming_commands`
-				None
-			}
+		.filter_map(|(reg_id, payload)| {
+			world
+				.resource::<ReplicateRegistry>()
+				.incoming_component_fns
+				.get(reg_id)
+				.map(|fns| (fns.erased.clone(), payload))
 		})
 		.collect::<Vec<_>>();
+	let components = fns
+		.into_iter()
+		.filter_map(|(erased, payload)| {
+			erased(world, payload).ok_or(|e| log::error!("{e}"))
+		})
+		.collect::<Vec<_>>();
+	let mut entity = world.spawn_empty();
+	insert_erased(&mut entity, components);
+	let local = entity.id();
+	world
+		.resource_mut::<ReplicateRegistry>()
+		.map_entity(sender, remote, local);
+}
+
+/// Like [`ReplicateRegistry::entity_fns`], but local entities with a
+/// [`Replicate`] component may exclude incoming components.
+fn entity_fns<'a>(
+	world: &World,
+	registrations: &'a ReplicateRegistry,
+	sender: Option<ClientId>,
+	remote: Entity,
+	reg_id: RegistrationId,
+) -> Option<(Entity, &'a ComponentFns)> {
+	let (entity, fns) = registrations.entity_fns(sender, remote, reg_id)?;
+	let allowed = match (
+		world.get::<Replicate>(entity),
+		registrations.type_id(reg_id),
+	) {
+		(None, _) => true,
+		(Some(replicate), Some(type_id)) => replicate.allows_incoming(type_id),
+		(Some(replicate), None) => replicate.is_incoming(),
+	};
+	allowed.then_some((entity, fns))
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use bevy::prelude::*;
+	use serde::Deserialize;
+	use serde::Serialize;
+	use sweet::*;
+
+	#[derive(Debug, Clone, Component, Serialize, Deserialize, PartialEq)]
+	pub struct MyComponent(pub i32);
+	#[derive(Debug, Clone, Event, Serialize, Deserialize, PartialEq)]
+	pub struct MyEvent(pub i32);
+
+	/// Number of entities with [`MyComponent`] when each event is sent
+	#[derive(Debug, Default, Resource, Deref, DerefMut)]
+	struct Counts(Vec<usize>);
+
+	#[test]
+	fn in_order() -> Result<()> {
+		let mut app = App::new();
+		app.add_plugins(ReplicatePlugin)
+			.init_resource::<Counts>()
+			.add_event::<MyEvent>()
+			.replicate::<MyComponent>()
+			.replicate_event_incoming::<MyEvent>();
+		let reg_id = app
+			.world()
+			.resource::<ReplicateRegistry>()
+			.registration_id::<MyEvent>();
+		app.world_mut()
+			.resource_mut::<ReplicateRegistry>()
+			.incoming_event_fns
+			.insert(reg_id, EventFns {
+				send: |world, _| {
+					let count =
+						world.query::<&MyComponent>().iter(world).count();
+					world.resource_mut::<Counts>().push(count);
+					Ok(())
+				},
+			});
+
+		let entity = Entity::from_raw(100);
+		let send_event = Message::SendEvent {
+			reg_id,
+			payload: MessagePayload::new(MyEvent(0))?,
+		};
+		app.world_mut().resource_mut::<MessageIncoming>().extend([
+			send_event.clone(),
+			Message::Spawn { entity },
+			Message::Add {
+				entity,
+				reg_id: RegistrationId::new_with(0),
+				payload: MessagePayload::new(MyComponent(1))?,
+			},
+			send_event.clone(),
+			Message::Remove {
+				entity,
+				reg_id: RegistrationId::new_with(0),
+			},
+			send_event,
+		]);
+		app.update();
+
+		expect(&app.world().resource::<Counts>().0).to_be(&vec![0, 1, 0])?;
+		Ok(())
+	}
+
+	#[test]
+	fn despawn() -> Result<()> {
+		let mut app = App::new();
+		app.add_plugins(ReplicatePlugin);
+		// an unrelated local entity with the same id as the remote one
+		let unrelated = app.world_mut().spawn_empty().id();
+		let remote = Entity::from_raw(100);
+		app.world_mut()
+			.resource_mut::<MessageIncoming>()
+			.extend([Message::Despawn { entity: unrelated }, Message::Spawn {
+				entity: remote,
+			}]);
+		app.update();
+		expect(app.world().get_entity(unrelated).is_ok()).to_be_true()?;
+		let local =
+			app.world().resource::<ReplicateRegistry>().entities[&remote];
+
+		app.world_mut()
+			.resource_mut::<MessageIncoming>()
+			.push(Message::Despawn { entity: remote });
+		app.update();
+		expect(app.world().get_entity(local).is_ok()).to_be_false()?;
+		expect(
+			app.world()
+				.resource::<ReplicateRegistry>()
+				.entities
+				.contains_key(&remote),
+		)
+		.to_be_false()?;
+		Ok(())
+	}
+
+	fn push_from(app: &mut App, messages: Vec<(ClientId, Message)>) {
+		for (sender, message) in messages {
+			let index = app.world().resource::<MessageIncoming>().len();
+			app.world_mut()
+				.resource_mut::<MessageIncomingSources>()
+				.push(index, MessageSource::new(None, Some(sender)));
+			app.world_mut()
+				.resource_mut::<MessageIncoming>()
+				.push(message);
+		}
+	}
+
+	#[test]
+	fn senders() -> Result<()> {
+		let mut app = App::new();
+		app.add_plugins(ReplicatePlugin).replicate::<MyComponent>();
+		let reg_id = RegistrationId::new_with(0);
+		// each client spawns its first entity, with the same id
+		let remote = Entity::from_raw(0);
+		let add = |value| -> Result<Message> {
+			Ok(Message::Add {
+				entity: remote,
+				reg_id,
+				payload: MessagePayload::new(MyComponent(value))?,
+			})
+		};
+		push_from(&mut app, vec![
+			(1, Message::Spawn { entity: remote }),
+			(2, Message::Spawn { entity: remote }),
+			(1, add(1)?),
+			(2, add(2)?),
+		]);
+		app.update();
+
+		let local = |app: &App, client_id| {
+			app.world()
+				.resource::<ReplicateRegistry>()
+				.local_entity(Some(client_id), remote)
+		};
+		let local1 = local(&app, 1).unwrap();
+		let local2 = local(&app, 2).unwrap();
+		expect(app.world().get::<MyComponent>(local1))
+			.to_be(Some(&MyComponent(1)))?;
+		expect(app.world().get::<MyComponent>(local2))
+			.to_be(Some(&MyComponent(2)))?;
 
-	for (fns, payload) in events {
-		(fns.send)(world, &payload).ok_or(|e| log::error!("{e}"));
+		push_from(&mut app, vec![(2, Message::Despawn { entity: remote })]);
+		app.update();
+		expect(app.world().get_entity(local1).is_ok()).to_be_true()?;
+		expect(app.world().get_entity(local2).is_ok()).to_be_false()?;
+		expect(local(&app, 2)).to_be_none()?;
+		Ok(())
 	}
 }
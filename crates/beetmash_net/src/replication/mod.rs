@@ -1,6 +1,17 @@
+pub mod erased_component;
+#[allow(unused_imports)]
+pub use self::erased_component::*;
 pub mod incoming;
 #[allow(unused_imports)]
 pub use self::incoming::*;
+#[cfg(feature = "bevy_asset")]
+pub mod replicate_asset;
+#[cfg(feature = "bevy_asset")]
+#[allow(unused_imports)]
+pub use self::replicate_asset::*;
+pub mod remote_changes;
+#[allow(unused_imports)]
+pub use self::remote_changes::*;
 pub mod replicate_component;
 #[allow(unused_imports)]
 pub use self::replicate_component::*;
@@ -19,6 +30,9 @@ pub use self::replicate_observer::*;
 pub mod replicate_plugin;
 #[allow(unused_imports)]
 pub use self::replicate_plugin::*;
+pub mod replicate_reflect;
+#[allow(unused_imports)]
+pub use self::replicate_reflect::*;
 pub mod replicate_registry;
 #[allow(unused_imports)]
 pub use self::replicate_registry::*;
@@ -28,6 +42,17 @@ pub use self::replicate_registry_exporter::*;
 pub mod replicate_resource;
 #[allow(unused_imports)]
 pub use self::replicate_resource::*;
+pub mod replicate_rpc;
+#[allow(unused_imports)]
+pub use self::replicate_rpc::*;
+#[cfg(feature = "bevy_state")]
+pub mod replicate_state;
+#[cfg(feature = "bevy_state")]
+#[allow(unused_imports)]
+pub use self::replicate_state::*;
 pub mod replicate_type;
 #[allow(unused_imports)]
 pub use self::replicate_type::*;
+pub mod snapshot;
+#[allow(unused_imports)]
+pub use self::snapshot::*;
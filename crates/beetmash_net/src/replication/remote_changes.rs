@@ -0,0 +1,226 @@
+use crate::prelude::*;
+use bevy::ecs::component::Tick;
+use bevy::ecs::event::EventId;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy::utils::HashSet;
+use std::any::TypeId;
+
+/// Changes applied from [`MessageIncoming`] this frame, so that outgoing
+/// systems do not echo them back when replicating in both directions.
+/// Local changes made after a remote change are still sent.
+#[derive(Debug, Default, Resource)]
+pub struct RemoteChanges {
+	/// Set while [`handle_incoming`] applies a message,
+	/// so that outgoing observers can ignore it.
+	applying: Applying,
+	components: HashMap<(Entity, RegistrationId), TickRange>,
+	resources: HashMap<RegistrationId, TickRange>,
+	removed_resources: HashSet<RegistrationId>,
+	events: HashMap<TypeId, HashSet<usize>>,
+	/// States set via [`NextState`] by an incoming message, kept until
+	/// the outgoing system sees the transition in a later frame.
+	states: HashMap<RegistrationId, MessagePayload>,
+}
+
+/// What the message currently being applied affects. Observers triggered
+/// by other changes as a consequence, ie an rpc response, are still sent.
+#[derive(Debug, Default, Clone, PartialEq)]
+enum Applying {
+	#[default]
+	None,
+	Component(Entity, RegistrationId),
+	Despawn(Entity),
+	Observer(RegistrationId),
+}
+
+impl Applying {
+	fn new(
+		registrations: &ReplicateRegistry,
+		sender: Option<ClientId>,
+		msg: &Message,
+	) -> Self {
+		match msg {
+			Message::Add { entity, reg_id, .. }
+			| Message::Change { entity, reg_id, .. }
+			| Message::Remove { entity, reg_id } => {
+				match registrations.local_entity(sender, *entity) {
+					Some(local) => Self::Component(local, *reg_id),
+					None => Self::None,
+				}
+			}
+			Message::Despawn { entity } => {
+				match registrations.local_entity(sender, *entity) {
+					Some(local) => Self::Despawn(local),
+					None => Self::None,
+				}
+			}
+			Message::SendObserver { reg_id, .. } => Self::Observer(*reg_id),
+			_ => Self::None,
+		}
+	}
+}
+
+/// Ticks spanned while applying a message, observers triggered by the
+/// message increment the change tick.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct TickRange {
+	start: Tick,
+	end: Tick,
+}
+
+impl TickRange {
+	fn contains(&self, tick: Tick) -> bool {
+		(self.start.get()..=self.end.get()).contains(&tick.get())
+	}
+}
+
+impl RemoteChanges {
+	/// Whether an incoming message is adding or removing this component
+	pub fn is_applying_component(
+		&self,
+		entity: Entity,
+		reg_id: RegistrationId,
+	) -> bool {
+		self.applying == Applying::Component(entity, reg_id)
+	}
+	/// Whether an incoming message is despawning this entity
+	pub fn is_applying_despawn(&self, entity: Entity) -> bool {
+		self.applying == Applying::Despawn(entity)
+	}
+	/// Whether an incoming message is triggering this observer event
+	pub fn is_applying_observer(&self, reg_id: RegistrationId) -> bool {
+		self.applying == Applying::Observer(reg_id)
+	}
+
+	/// Called before applying an incoming message
+	pub(crate) fn start_message(
+		world: &mut World,
+		sender: Option<ClientId>,
+		msg: &Message,
+	) {
+		let applying =
+			Applying::new(world.resource::<ReplicateRegistry>(), sender, msg);
+		world.resource_mut::<RemoteChanges>().applying = applying;
+	}
+
+	/// Whether the component was last changed by an incoming message
+	pub fn is_remote_component(
+		&self,
+		entity: Entity,
+		reg_id: RegistrationId,
+		last_changed: Tick,
+	) -> bool {
+		self.components
+			.get(&(entity, reg_id))
+			.is_some_and(|range| range.contains(last_changed))
+	}
+
+	/// Whether the resource was last changed by an incoming message
+	pub fn is_remote_resource(
+		&self,
+		reg_id: RegistrationId,
+		last_changed: Tick,
+	) -> bool {
+		self.resources
+			.get(&reg_id)
+			.is_some_and(|range| range.contains(last_changed))
+	}
+
+	/// Whether the resource was removed by an incoming message
+	pub fn is_remote_removed_resource(&self, reg_id: RegistrationId) -> bool {
+		self.removed_resources.contains(&reg_id)
+	}
+
+	/// Record an event sent by an incoming message
+	pub(crate) fn record_event<T: Event>(&mut self, id: EventId<T>) {
+		self.events
+			.entry(TypeId::of::<T>())
+			.or_default()
+			.insert(id.id);
+	}
+
+	/// Whether the event was sent by an incoming message
+	pub fn is_remote_event<T: Event>(&self, id: EventId<T>) -> bool {
+		self.events
+			.get(&TypeId::of::<T>())
+			.is_some_and(|ids| ids.contains(&id.id))
+	}
+
+	/// Record a state set by an incoming message
+	pub(crate) fn record_state(
+		&mut self,
+		reg_id: RegistrationId,
+		payload: MessagePayload,
+	) {
+		self.states.insert(reg_id, payload);
+	}
+
+	/// Remove the state last set by an incoming message, if any
+	pub fn take_state(
+		&mut self,
+		reg_id: RegistrationId,
+	) -> Option<MessagePayload> {
+		self.states.remove(&reg_id)
+	}
+
+	/// Record the changes made by an incoming message that has just
+	/// been applied starting at this tick, see [`handle_incoming`].
+	pub(crate) fn record_message(
+		world: &mut World,
+		sender: Option<ClientId>,
+		msg: &Message,
+		start: Tick,
+	) {
+		let range = TickRange {
+			start,
+			end: world.change_tick(),
+		};
+		let registrations = world.resource::<ReplicateRegistry>();
+		let local =
+			|remote: &Entity| registrations.local_entity(sender, *remote);
+		let mut components = Vec::new();
+		match msg {
+			Message::SpawnWith {
+				entity,
+				components: added,
+			} => {
+				if let Some(local) = local(entity) {
+					components.extend(
+						added.iter().map(|(reg_id, _)| (local, *reg_id)),
+					);
+				}
+			}
+			Message::Add { entity, reg_id, .. }
+			| Message::Change { entity, reg_id, .. } => {
+				if let Some(local) = local(entity) {
+					components.push((local, *reg_id));
+				}
+			}
+			_ => {}
+		}
+		let mut remote = world.resource_mut::<RemoteChanges>();
+		remote.applying = Applying::None;
+		for key in components {
+			remote.components.insert(key, range);
+		}
+		match msg {
+			Message::InsertResource { reg_id, .. }
+			| Message::ChangeResource { reg_id, .. } => {
+				remote.resources.insert(*reg_id, range);
+			}
+			Message::RemoveResource { reg_id } => {
+				remote.removed_resources.insert(*reg_id);
+			}
+			_ => {}
+		}
+	}
+}
+
+pub(crate) fn clear_remote_changes(mut remote: ResMut<RemoteChanges>) {
+	let states = std::mem::take(&mut remote.states);
+	*remote = RemoteChanges {
+		states,
+		..default()
+	};
+}
@@ -0,0 +1,671 @@
+use crate::prelude::*;
+use anyhow::Result;
+use bevy::asset::io::memory::Dir;
+use bevy::asset::io::memory::MemoryAssetReader;
+use bevy::asset::io::AssetSource;
+use bevy::asset::io::AssetSourceId;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy::utils::HashSet;
+use forky::prelude::ResultTEExt;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const DEFAULT_ASSET_CHUNK_SIZE: usize = 16 * 1024;
+pub const DEFAULT_ASSET_CHUNKS_PER_UPDATE: usize = 8;
+pub const DEFAULT_ASSET_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_MAX_ASSET_SIZE: usize = 64 * 1024 * 1024;
+/// The [`AssetSourceId`] that streamed assets are loaded from.
+pub const STREAMED_ASSET_SOURCE: &str = "stream";
+
+/// Placeholder for an asset referenced by path, ie `models/tree.glb#Mesh0`.
+/// Once available the asset is loaded and a [`StreamedHandle`] inserted.
+/// If the file does not exist in the local asset root it is requested from
+/// the remote peer, see [`AssetStreamPlugin`].
+#[derive(Component, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct StreamedAsset<A: Asset> {
+	pub path: String,
+	#[serde(skip)]
+	phantom: PhantomData<A>,
+}
+
+impl<A: Asset> StreamedAsset<A> {
+	pub fn new(path: impl Into<String>) -> Self {
+		Self {
+			path: path.into(),
+			phantom: PhantomData,
+		}
+	}
+
+	/// The file path, excluding any label
+	pub fn file_path(&self) -> &str {
+		self.path
+			.split_once('#')
+			.map_or(&self.path, |(path, _)| path)
+	}
+}
+
+/// Handle to the loaded [`StreamedAsset`].
+#[derive(Debug, Clone, PartialEq, Deref, Component)]
+pub struct StreamedHandle<A: Asset>(pub Handle<A>);
+
+/// Streams asset files that a peer is missing over the transport in
+/// chunks. Requests are broadcast, if several peers respond only
+/// the one with the lowest [`PeerId`] keeps sending, and chunks
+/// of files that were not requested are ignored. Received files are stored in memory by [`content_hash`],
+/// and loaded via the [`STREAMED_ASSET_SOURCE`] so that regular asset
+/// loaders insert them into [`Assets`], ie images, meshes and gltf scenes.
+///
+/// Register placeholder types with [`AppExtReplicate::stream_asset`].
+/// This must be added before the [`AssetPlugin`].
+#[derive(Debug, Clone)]
+pub struct AssetStreamPlugin {
+	/// Local files are read from here, this should match
+	/// [`AssetPlugin::file_path`].
+	pub root: PathBuf,
+	/// Peers should use the same chunk size, see [`Self::max_asset_size`].
+	pub chunk_size: usize,
+	/// Requested chunks are queued and sent at most this many per update.
+	pub chunks_per_update: usize,
+	/// Files are requested again if no chunk arrives for this long.
+	pub request_timeout: Duration,
+	/// Larger files are rejected by the receiver, which assumes
+	/// the sender uses the same [`Self::chunk_size`].
+	pub max_asset_size: usize,
+}
+
+impl Default for AssetStreamPlugin {
+	fn default() -> Self {
+		Self {
+			root: PathBuf::from("assets"),
+			chunk_size: DEFAULT_ASSET_CHUNK_SIZE,
+			chunks_per_update: DEFAULT_ASSET_CHUNKS_PER_UPDATE,
+			request_timeout: DEFAULT_ASSET_REQUEST_TIMEOUT,
+			max_asset_size: DEFAULT_MAX_ASSET_SIZE,
+		}
+	}
+}
+
+impl Plugin for AssetStreamPlugin {
+	fn build(&self, app: &mut App) {
+		app.init_resource::<PeerId>();
+		let streams = AssetStreams {
+			peer: *app.world().resource::<PeerId>(),
+			chunks_per_update: self.chunks_per_update,
+			request_timeout: self.request_timeout,
+			max_asset_size: self.max_asset_size,
+			..AssetStreams::new(self.root.clone(), self.chunk_size)
+		};
+		let dir = streams.dir.clone();
+		app.register_asset_source(
+			AssetSourceId::from_static(STREAMED_ASSET_SOURCE),
+			AssetSource::build().with_reader(move || {
+				Box::new(MemoryAssetReader { root: dir.clone() })
+			}),
+		)
+		.insert_resource(streams)
+		.add_systems(
+			Update,
+			(
+				handle_asset_incoming.in_set(MessageIncomingSet),
+				send_asset_chunks.in_set(MessageOutgoingSet),
+			),
+		);
+	}
+}
+
+/// Files sent and received by the [`AssetStreamPlugin`].
+#[derive(Resource)]
+pub struct AssetStreams {
+	/// Sent with each chunk, see [`Self::cancel_chunks`]
+	pub peer: PeerId,
+	pub root: PathBuf,
+	pub chunk_size: usize,
+	pub chunks_per_update: usize,
+	pub request_timeout: Duration,
+	pub max_asset_size: usize,
+	/// Received files by content hash, ie `1a2b3c.png`
+	pub dir: Dir,
+	/// Content hash of each file path sent or received
+	hashes: HashMap<String, u64>,
+	/// Received content hashes
+	cached: HashSet<u64>,
+	/// Map of requested file path to when it was requested
+	/// or its last chunk received
+	requested: HashMap<String, Duration>,
+	pending: HashMap<String, PendingAsset>,
+	/// Chunks waiting to be sent, see [`AssetStreamPlugin::chunks_per_update`]
+	sending: VecDeque<Message>,
+}
+
+struct PendingAsset {
+	hash: u64,
+	chunks: Vec<Option<Vec<u8>>>,
+}
+
+impl AssetStreams {
+	pub fn new(root: PathBuf, chunk_size: usize) -> Self {
+		Self {
+			peer: PeerId::random(),
+			root,
+			chunk_size,
+			chunks_per_update: DEFAULT_ASSET_CHUNKS_PER_UPDATE,
+			request_timeout: DEFAULT_ASSET_REQUEST_TIMEOUT,
+			max_asset_size: DEFAULT_MAX_ASSET_SIZE,
+			dir: Dir::default(),
+			hashes: default(),
+			cached: default(),
+			requested: default(),
+			pending: default(),
+			sending: default(),
+		}
+	}
+
+	/// Whether the file exists in the local asset root,
+	/// always false on wasm.
+	pub fn exists_locally(&self, path: &str) -> bool {
+		#[cfg(target_arch = "wasm32")]
+		return false;
+		#[cfg(not(target_arch = "wasm32"))]
+		return is_relative(path) && self.root.join(path).exists();
+	}
+
+	/// The path to load a received file from, if it has been received.
+	/// Labels are preserved.
+	pub fn streamed_path(&self, path: &str) -> Option<String> {
+		let (file_path, label) = match path.split_once('#') {
+			Some((file_path, label)) => (file_path, Some(label)),
+			None => (path, None),
+		};
+		let hash = self.hashes.get(file_path)?;
+		if !self.cached.contains(hash) {
+			return None;
+		}
+		let mut streamed = format!(
+			"{STREAMED_ASSET_SOURCE}://{}",
+			cache_path(file_path, *hash)
+		);
+		if let Some(label) = label {
+			streamed.push('#');
+			streamed.push_str(label);
+		}
+		Some(streamed)
+	}
+
+	/// Returns a [`Message::AssetRequest`] the first time a file is missing,
+	/// or if no chunk has arrived within the [`Self::request_timeout`].
+	/// Files that have been received are never requested again.
+	/// `now` is the [`Time`] elapsed.
+	pub fn request(&mut self, path: &str, now: Duration) -> Option<Message> {
+		if self.streamed_path(path).is_some() {
+			return None;
+		}
+		if let Some(last) = self.requested.get(path) {
+			if now.saturating_sub(*last) < self.request_timeout {
+				return None;
+			}
+		}
+		self.requested.insert(path.to_string(), now);
+		Some(Message::AssetRequest {
+			path: path.to_string(),
+		})
+	}
+
+	/// Queue the chunks of a local file to be sent,
+	/// unless they are already queued.
+	pub fn queue_chunks(&mut self, path: &str) -> Result<()> {
+		let queued = self.sending.iter().any(|msg| {
+			matches!(msg, Message::AssetChunk { path: queued, .. } if queued == path)
+		});
+		if !queued {
+			let chunks = self.chunks(path)?;
+			self.sending.extend(chunks);
+		}
+		Ok(())
+	}
+
+	/// Called when another peer sends chunks of a file, if it has
+	/// a lower [`PeerId`] stop sending our own so that only one peer
+	/// responds to a broadcast [`Message::AssetRequest`].
+	pub fn cancel_chunks(&mut self, path: &str, sender: PeerId) {
+		if *sender >= *self.peer {
+			return;
+		}
+		self.sending.retain(|msg| {
+			!matches!(msg, Message::AssetChunk { path: queued, .. } if queued == path)
+		});
+	}
+
+	/// Whether a file has been requested and not yet received,
+	/// chunks of other files should not be passed to
+	/// [`Self::receive_chunk`].
+	pub fn is_requested(&self, path: &str) -> bool {
+		self.requested.contains_key(path)
+	}
+
+	/// Take the next [`AssetStreamPlugin::chunks_per_update`] queued chunks
+	pub fn next_chunks(&mut self) -> Vec<Message> {
+		let count = self.chunks_per_update.min(self.sending.len());
+		self.sending.drain(..count).collect()
+	}
+
+	/// Read a local file and split it into [`Message::AssetChunk`].
+	pub fn chunks(&mut self, path: &str) -> Result<Vec<Message>> {
+		if !is_relative(path) {
+			anyhow::bail!("asset path must be relative: {path}");
+		}
+		let bytes = std::fs::read(self.root.join(path))?;
+		let hash = content_hash(&bytes);
+		self.hashes.insert(path.to_string(), hash);
+		let chunks = bytes.chunks(self.chunk_size.max(1)).collect::<Vec<_>>();
+		// empty files are sent as a single empty chunk
+		let total = chunks.len().max(1) as u32;
+		let messages = (0..total)
+			.map(|index| Message::AssetChunk {
+				peer: self.peer,
+				path: path.to_string(),
+				hash,
+				index,
+				total,
+				bytes: chunks
+					.get(index as usize)
+					.map(|chunk| chunk.to_vec())
+					.unwrap_or_default(),
+			})
+			.collect();
+		Ok(messages)
+	}
+
+	/// Store a received chunk, returning true if the file is now available.
+	/// Files already cached by hash are available on the first chunk.
+	pub fn receive_chunk(
+		&mut self,
+		path: &str,
+		hash: u64,
+		index: u32,
+		total: u32,
+		bytes: &[u8],
+	) -> Result<bool> {
+		self.hashes.insert(path.to_string(), hash);
+		if self.cached.contains(&hash) {
+			self.pending.remove(path);
+			self.requested.remove(path);
+			return Ok(true);
+		}
+		let max_chunks = self.max_asset_size.div_ceil(self.chunk_size.max(1));
+		if total as usize > max_chunks.max(1) {
+			self.requested.remove(path);
+			anyhow::bail!(
+				"{path} exceeds the max asset size of {} bytes",
+				self.max_asset_size
+			);
+		}
+		let pending =
+			self.pending.entry(path.to_string()).or_insert_with(|| {
+				PendingAsset {
+					hash,
+					chunks: vec![None; total as usize],
+				}
+			});
+		if pending.hash != hash || pending.chunks.len() != total as usize {
+			// the file changed while streaming, start again
+			*pending = PendingAsset {
+				hash,
+				chunks: vec![None; total as usize],
+			};
+		}
+		let Some(chunk) = pending.chunks.get_mut(index as usize) else {
+			anyhow::bail!("chunk {index} out of range for {path}");
+		};
+		*chunk = Some(bytes.to_vec());
+		if pending.chunks.iter().any(|chunk| chunk.is_none()) {
+			return Ok(false);
+		}
+
+		let pending = self.pending.remove(path).unwrap();
+		self.requested.remove(path);
+		let bytes = pending
+			.chunks
+			.into_iter()
+			.flatten()
+			.flatten()
+			.collect::<Vec<u8>>();
+		if content_hash(&bytes) != hash {
+			self.requested.remove(path);
+			anyhow::bail!("content hash mismatch for {path}");
+		}
+		self.dir
+			.insert_asset(Path::new(&cache_path(path, hash)), bytes);
+		self.cached.insert(hash);
+		Ok(true)
+	}
+}
+
+/// Deterministic 64 bit FNV-1a hash, so that peers built with different
+/// compilers agree.
+pub fn content_hash(bytes: &[u8]) -> u64 {
+	bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+		(hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+	})
+}
+
+/// Files are cached by hash but keep their extension
+/// so that the correct loader is used.
+fn cache_path(path: &str, hash: u64) -> String {
+	match Path::new(path).extension() {
+		Some(extension) => {
+			format!("{hash:016x}.{}", extension.to_string_lossy())
+		}
+		None => format!("{hash:016x}"),
+	}
+}
+
+/// Only serve files inside the asset root
+fn is_relative(path: &str) -> bool {
+	Path::new(path)
+		.components()
+		.all(|component| matches!(component, std::path::Component::Normal(_)))
+}
+
+fn handle_asset_incoming(
+	time: Res<Time>,
+	incoming: Res<MessageIncoming>,
+	mut streams: ResMut<AssetStreams>,
+) {
+	for msg in incoming.iter() {
+		match msg {
+			Message::AssetRequest { path } => {
+				streams
+					.queue_chunks(path)
+					.ok_or(|e| log::warn!("{path}: {e}"));
+			}
+			Message::AssetChunk {
+				peer,
+				path,
+				hash,
+				index,
+				total,
+				bytes,
+			} => {
+				streams.cancel_chunks(path, *peer);
+				if !streams.is_requested(path) {
+					continue;
+				}
+				// still arriving, do not request again
+				if let Some(last) = streams.requested.get_mut(path) {
+					*last = time.elapsed();
+				}
+				streams
+					.receive_chunk(path, *hash, *index, *total, bytes)
+					.ok_or(|e| log::error!("{e}"));
+			}
+			_ => {}
+		}
+	}
+}
+
+fn send_asset_chunks(
+	mut outgoing: ResMut<MessageOutgoing>,
+	mut streams: ResMut<AssetStreams>,
+) {
+	if !streams.sending.is_empty() {
+		outgoing.extend(streams.next_chunks());
+	}
+}
+
+pub fn register_asset_stream<A: Asset>(app: &mut App) {
+	app.add_systems(
+		Update,
+		load_streamed_assets::<A>
+			.after(MessageIncomingSet)
+			.before(MessageOutgoingSet),
+	);
+}
+
+fn load_streamed_assets<A: Asset>(
+	mut commands: Commands,
+	time: Res<Time>,
+	asset_server: Res<AssetServer>,
+	mut streams: ResMut<AssetStreams>,
+	mut outgoing: ResMut<MessageOutgoing>,
+	query: Query<(Entity, &StreamedAsset<A>), Without<StreamedHandle<A>>>,
+) {
+	for (entity, placeholder) in query.iter() {
+		let file_path = placeholder.file_path();
+		let handle = if streams.exists_locally(file_path) {
+			asset_server.load::<A>(placeholder.path.clone())
+		} else if let Some(path) = streams.streamed_path(&placeholder.path) {
+			asset_server.load::<A>(path)
+		} else {
+			if let Some(request) = streams.request(file_path, time.elapsed()) {
+				outgoing.push(request);
+			}
+			continue;
+		};
+		commands.entity(entity).insert(StreamedHandle(handle));
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use bevy::asset::io::Reader;
+	use bevy::asset::AssetLoader;
+	use bevy::asset::LoadContext;
+	use bevy::prelude::*;
+	use std::path::PathBuf;
+	use std::time::Duration;
+	use sweet::*;
+
+	#[derive(Debug, Asset, TypePath)]
+	struct TextAsset(String);
+
+	#[derive(Default)]
+	struct TextLoader;
+
+	impl AssetLoader for TextLoader {
+		type Asset = TextAsset;
+		type Settings = ();
+		type Error = std::io::Error;
+		async fn load(
+			&self,
+			reader: &mut dyn Reader,
+			_settings: &(),
+			_load_context: &mut LoadContext<'_>,
+		) -> Result<TextAsset, Self::Error> {
+			let mut bytes = Vec::new();
+			reader.read_to_end(&mut bytes).await?;
+			Ok(TextAsset(String::from_utf8_lossy(&bytes).to_string()))
+		}
+		fn extensions(&self) -> &[&str] { &["txt"] }
+	}
+
+	/// Asset root containing `hello.txt`, unique per test and process
+	fn root(name: &str) -> Result<PathBuf> {
+		let root = std::env::temp_dir()
+			.join(format!("beetmash_net_{name}_{}", std::process::id()));
+		std::fs::create_dir_all(&root)?;
+		std::fs::write(root.join("hello.txt"), "hello world")?;
+		Ok(root)
+	}
+
+	#[test]
+	fn chunks() -> Result<()> {
+		let root = root("chunks")?;
+
+		let mut host = AssetStreams::new(root.clone(), 4);
+		let chunks = host.chunks("hello.txt")?;
+		expect(chunks.len()).to_be(3)?;
+		expect(host.chunks("../hello.txt").is_err()).to_be_true()?;
+
+		let mut client = AssetStreams::new("missing".into(), 4);
+		expect(client.exists_locally("hello.txt")).to_be_false()?;
+		let timeout = client.request_timeout;
+		expect(client.request("hello.txt", Duration::ZERO).is_some())
+			.to_be_true()?;
+		expect(client.request("hello.txt", Duration::ZERO)).to_be_none()?;
+		// no response, request again
+		expect(client.request("hello.txt", timeout).is_some()).to_be_true()?;
+
+		let mut received = Vec::new();
+		for chunk in chunks.iter().rev() {
+			let Message::AssetChunk {
+				path,
+				hash,
+				index,
+				total,
+				bytes,
+				..
+			} = chunk
+			else {
+				anyhow::bail!("expected chunk");
+			};
+			received.push(
+				client.receive_chunk(path, *hash, *index, *total, bytes)?,
+			);
+		}
+		expect(received).to_be(vec![false, false, true])?;
+		let hash = content_hash(b"hello world");
+		expect(client.streamed_path("hello.txt#label"))
+			.to_be(Some(format!("stream://{hash:016x}.txt#label")))?;
+
+		// cached by content
+		expect(client.receive_chunk("copy.txt", hash, 0, 3, b"hell")?)
+			.to_be_true()?;
+		// received, not requested again
+		expect(client.request("hello.txt", timeout * 2)).to_be_none()?;
+		expect(client.is_requested("hello.txt")).to_be_false()?;
+		std::fs::remove_dir_all(root)?;
+		Ok(())
+	}
+
+	#[test]
+	fn max_asset_size() -> Result<()> {
+		let mut client = AssetStreams::new("missing".into(), 4);
+		client.max_asset_size = 8;
+		expect(client.receive_chunk("big.txt", 0, 0, 2, b"big")?)
+			.to_be_false()?;
+		expect(client.receive_chunk("big.txt", 0, 0, u32::MAX, b"big"))
+			.to_be_err()?;
+		Ok(())
+	}
+
+	#[test]
+	fn paced() -> Result<()> {
+		let root = root("paced")?;
+
+		let mut host = AssetStreams::new(root.clone(), 1);
+		host.chunks_per_update = 4;
+		host.queue_chunks("hello.txt")?;
+		// already queued
+		host.queue_chunks("hello.txt")?;
+		expect(host.next_chunks().len()).to_be(4)?;
+		expect(host.next_chunks().len()).to_be(4)?;
+		expect(host.next_chunks().len()).to_be(3)?;
+		expect(host.next_chunks().len()).to_be(0)?;
+		std::fs::remove_dir_all(root)?;
+		Ok(())
+	}
+
+	#[test]
+	fn first_responder() -> Result<()> {
+		let root = root("first_responder")?;
+		let mut host = AssetStreams::new(root.clone(), 1);
+		host.peer = PeerId(2);
+		host.queue_chunks("hello.txt")?;
+		host.cancel_chunks("hello.txt", PeerId(3));
+		expect(host.next_chunks().len())
+			.to_be(DEFAULT_ASSET_CHUNKS_PER_UPDATE)?;
+		// a peer with a lower id is also responding
+		host.cancel_chunks("hello.txt", PeerId(1));
+		expect(host.next_chunks().len()).to_be(0)?;
+		std::fs::remove_dir_all(root)?;
+		Ok(())
+	}
+
+	fn app(root: &std::path::Path) -> App {
+		let mut app = App::new();
+		app.add_plugins((
+			MinimalPlugins,
+			AssetStreamPlugin {
+				root: root.to_path_buf(),
+				..default()
+			},
+			AssetPlugin {
+				file_path: root.to_string_lossy().to_string(),
+				..default()
+			},
+			ReplicatePlugin,
+		))
+		.init_asset::<TextAsset>()
+		.init_asset_loader::<TextLoader>()
+		.stream_asset::<TextAsset>();
+		app
+	}
+
+	fn loaded(app: &mut App) -> Option<String> {
+		app.world_mut()
+			.query::<&StreamedHandle<TextAsset>>()
+			.iter(app.world())
+			.next()
+			.and_then(|handle| {
+				app.world().resource::<Assets<TextAsset>>().get(&**handle)
+			})
+			.map(|asset| asset.0.clone())
+	}
+
+	#[test]
+	fn unrequested() -> Result<()> {
+		let root = root("unrequested")?;
+		let mut host = AssetStreams::new(root.clone(), 4);
+		let mut client = app(&root.join("missing"));
+		client
+			.world_mut()
+			.resource_mut::<MessageIncoming>()
+			.extend(host.chunks("hello.txt")?);
+		client.update();
+		expect(
+			client
+				.world()
+				.resource::<AssetStreams>()
+				.streamed_path("hello.txt"),
+		)
+		.to_be_none()?;
+		std::fs::remove_dir_all(root)?;
+		Ok(())
+	}
+
+	#[test]
+	fn works() -> Result<()> {
+		let root = root("works")?;
+		let mut host = app(&root);
+		let mut client = app(&root.join("missing"));
+		host.world_mut().spawn((
+			Replicate::default(),
+			StreamedAsset::<TextAsset>::new("hello.txt"),
+		));
+
+		// loading completes on the io task pool, so update until it has
+		let mut loaded_text = None;
+		for _ in 0..100_000 {
+			host.update();
+			Message::loopback(host.world_mut(), client.world_mut());
+			client.update();
+			Message::loopback(client.world_mut(), host.world_mut());
+			loaded_text = loaded(&mut client);
+			if loaded_text.is_some() {
+				break;
+			}
+		}
+		expect(loaded_text).to_be(Some("hello world".to_string()))?;
+		std::fs::remove_dir_all(root)?;
+		Ok(())
+	}
+}
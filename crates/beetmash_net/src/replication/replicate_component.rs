@@ -2,33 +2,58 @@ use crate::prelude::*;
 use anyhow::Result;
 use bevy::ecs::system::EntityCommands;
 use bevy::prelude::*;
+use bevy::reflect::TypeRegistry;
 use forky::prelude::ResultTEExt;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use std::any::TypeId;
+use std::sync::Arc;
+
+pub type ComponentPayloadFn = Arc<
+	dyn Fn(&mut EntityCommands, &MessagePayload) -> Result<()> + Send + Sync,
+>;
+pub type ComponentRemoveFn = Arc<dyn Fn(&mut EntityCommands) + Send + Sync>;
+pub type ComponentErasedFn = Arc<
+	dyn Fn(&mut World, &MessagePayload) -> Result<ErasedComponent>
+		+ Send
+		+ Sync,
+>;
+/// Serialize the component of an outgoing entity, if it has one.
+pub type ComponentSerializeFn = Arc<
+	dyn Fn(&EntityRef, &TypeRegistry) -> Result<Option<MessagePayload>>
+		+ Send
+		+ Sync,
+>;
 
 /// Functions for handling reception of [`Component`] messages.
-#[derive(Copy, Clone)]
+/// These are closures instead of function pointers so that types
+/// only known at runtime, ie [`ComponentFns::reflect`], can be handled.
+#[derive(Clone)]
 pub struct ComponentFns {
-	// pub type_id: std::any::TypeId,
-	pub insert: fn(&mut EntityCommands, payload: &MessagePayload) -> Result<()>,
-	pub change: fn(&mut EntityCommands, payload: &MessagePayload) -> Result<()>,
-	pub remove: fn(&mut EntityCommands),
+	pub insert: ComponentPayloadFn,
+	pub change: ComponentPayloadFn,
+	pub remove: ComponentRemoveFn,
+	/// Used to insert all components of a [`Message::SpawnWith`] at once
+	pub erased: ComponentErasedFn,
 }
 
 impl ComponentFns {
 	pub fn new<T: Component + DeserializeOwned>() -> Self {
 		Self {
-			insert: |commands, payload| {
+			insert: Arc::new(|commands, payload| {
 				commands.insert(payload.deserialize::<T>()?);
 				Ok(())
-			},
-			change: |commands, payload| {
+			}),
+			change: Arc::new(|commands, payload| {
 				commands.insert(payload.deserialize::<T>()?);
 				Ok(())
-			},
-			remove: |commands| {
+			}),
+			remove: Arc::new(|commands| {
 				commands.remove::<T>();
-			},
+			}),
+			erased: Arc::new(|world, payload| {
+				Ok(ErasedComponent::new(world, payload.deserialize::<T>()?))
+			}),
 		}
 	}
 }
@@ -36,10 +61,18 @@ impl ComponentFns {
 fn outgoing_add<T: Component + Serialize>(
 	trigger: Trigger<OnAdd, T>,
 	registrations: Res<ReplicateRegistry>,
+	remote: Res<RemoteChanges>,
 	mut outgoing: ResMut<MessageOutgoing>,
-	query: Query<&T, With<Replicate>>,
+	query: Query<(&T, &Replicate)>,
 ) {
-	if let Ok(component) = query.get(trigger.entity()) {
+	if let Ok((component, replicate)) = query.get(trigger.entity()) {
+		let reg_id = registrations.registration_id::<T>();
+		if !replicate.allows_outgoing(TypeId::of::<T>())
+			|| outgoing.spawned_with(trigger.entity(), reg_id)
+			|| remote.is_applying_component(trigger.entity(), reg_id)
+		{
+			return;
+		}
 		let Some(payload) =
 			MessagePayload::new(component).ok_or(|e| log::error!("{e}"))
 		else {
@@ -48,7 +81,7 @@ fn outgoing_add<T: Component + Serialize>(
 		outgoing.push(
 			Message::Add {
 				entity: trigger.entity(),
-				reg_id: registrations.registration_id::<T>(),
+				reg_id,
 				payload,
 			}
 			.into(),
@@ -61,11 +94,19 @@ fn outgoing_add<T: Component + Serialize>(
 /// This is a system because currently no `OnChange` trigger exists
 fn outgoing_change<T: Component + Serialize>(
 	registrations: Res<ReplicateRegistry>,
+	remote: Res<RemoteChanges>,
 	mut outgoing: ResMut<MessageOutgoing>,
-	query: Query<(Entity, Ref<T>), (Changed<T>, With<Replicate>)>,
+	query: Query<(Entity, Ref<T>, &Replicate), Changed<T>>,
 ) {
-	for (entity, component) in query.iter() {
-		if component.is_added() {
+	let reg_id = registrations.registration_id::<T>();
+	for (entity, component, replicate) in query.iter() {
+		if component.is_added()
+			|| !replicate.allows_outgoing(TypeId::of::<T>())
+			|| remote.is_remote_component(
+				entity,
+				reg_id,
+				component.last_changed(),
+			) {
 			continue;
 		}
 		let Some(payload) = MessagePayload::new(component.into_inner())
@@ -77,7 +118,7 @@ fn outgoing_change<T: Component + Serialize>(
 		outgoing.push(
 			Message::Change {
 				entity,
-				reg_id: registrations.registration_id::<T>(),
+				reg_id,
 				payload,
 			}
 			.into(),
@@ -89,14 +130,20 @@ fn outgoing_change<T: Component + Serialize>(
 fn outgoing_remove<T: Component>(
 	trigger: Trigger<OnRemove, T>,
 	registrations: Res<ReplicateRegistry>,
+	remote: Res<RemoteChanges>,
 	mut outgoing: ResMut<MessageOutgoing>,
-	query: Query<(), With<Replicate>>,
+	query: Query<&Replicate>,
 ) {
-	if query.contains(trigger.entity()) {
+	let reg_id = registrations.registration_id::<T>();
+	if query
+		.get(trigger.entity())
+		.is_ok_and(|replicate| replicate.allows_outgoing(TypeId::of::<T>()))
+		&& !remote.is_applying_component(trigger.entity(), reg_id)
+	{
 		outgoing.push(
 			Message::Remove {
 				entity: trigger.entity(),
-				reg_id: registrations.registration_id::<T>(),
+				reg_id,
 			}
 			.into(),
 		);
@@ -104,6 +151,19 @@ fn outgoing_remove<T: Component>(
 }
 
 pub fn register_component_outgoing<T: Component + Serialize>(app: &mut App) {
+	let reg_id = app
+		.world()
+		.resource::<ReplicateRegistry>()
+		.registration_id::<T>();
+	app.world_mut()
+		.resource_mut::<ReplicateRegistry>()
+		.outgoing_component_fns
+		.insert(
+			reg_id,
+			Arc::new(|entity, _| {
+				entity.get::<T>().map(MessagePayload::new).transpose()
+			}),
+		);
 	app.add_systems(Update, outgoing_change::<T>.in_set(MessageOutgoingSet));
 	app.world_mut().add_observer(outgoing_add::<T>);
 	app.world_mut().add_observer(outgoing_remove::<T>);
@@ -120,6 +180,8 @@ mod test {
 
 	#[derive(Debug, Clone, Component, Serialize, Deserialize, PartialEq)]
 	pub struct MyComponent(pub i32);
+	#[derive(Debug, Clone, Component, Serialize, Deserialize, PartialEq)]
+	pub struct OtherComponent(pub i32);
 
 	#[test]
 	fn outgoing() -> Result<()> {
@@ -143,17 +205,15 @@ mod test {
 		app.update();
 
 		let msg_out = app.world_mut().resource_mut::<MessageOutgoing>();
-		expect(msg_out.len()).to_be(5)?;
-		expect(&msg_out[0]).to_be(&Message::Spawn { entity }.into())?;
+		expect(msg_out.len()).to_be(4)?;
+		expect(&msg_out[0]).to_be(&Message::SpawnWith {
+			entity,
+			components: vec![(
+				RegistrationId::new_with(0),
+				MessagePayload::new(&MyComponent(7))?,
+			)],
+		})?;
 		expect(&msg_out[1]).to_be(
-			&Message::Add {
-				entity,
-				reg_id: RegistrationId::new_with(0),
-				payload: MessagePayload::new(&MyComponent(7))?,
-			}
-			.into(),
-		)?;
-		expect(&msg_out[2]).to_be(
 			&Message::Change {
 				entity,
 				reg_id: RegistrationId::new_with(0),
@@ -161,7 +221,7 @@ mod test {
 			}
 			.into(),
 		)?;
-		expect(&msg_out[3]).to_be(&Message::Despawn { entity }.into())?;
+		expect(&msg_out[2]).to_be(&Message::Despawn { entity }.into())?;
 
 		Ok(())
 	}
@@ -184,7 +244,7 @@ mod test {
 		Message::loopback(app1.world_mut(), app2.world_mut());
 
 		let msg_in = app2.world_mut().resource_mut::<MessageIncoming>();
-		expect(msg_in.len()).to_be(2)?;
+		expect(msg_in.len()).to_be(1)?;
 
 		app2.update();
 		expect(
@@ -225,4 +285,158 @@ mod test {
 
 		Ok(())
 	}
+
+	#[test]
+	fn outgoing_filtered() -> Result<()> {
+		let mut app = App::new();
+		app.add_plugins(ReplicatePlugin)
+			.replicate::<MyComponent>()
+			.replicate::<OtherComponent>();
+
+		let allowed = app
+			.world_mut()
+			.spawn((
+				Replicate::default().allow::<MyComponent>(),
+				MyComponent(1),
+				OtherComponent(2),
+			))
+			.id();
+		let denied = app
+			.world_mut()
+			.spawn((
+				Replicate::default().deny::<MyComponent>(),
+				MyComponent(3),
+				OtherComponent(4),
+			))
+			.id();
+		app.world_mut().spawn((
+			Replicate::default().with_direction(ReplicateDirection::Incoming),
+			MyComponent(5),
+			OtherComponent(6),
+		));
+		app.update();
+
+		let msg_out = app.world_mut().resource_mut::<MessageOutgoing>();
+		expect(msg_out.len()).to_be(2)?;
+		expect(&msg_out[0]).to_be(&Message::SpawnWith {
+			entity: allowed,
+			components: vec![(
+				RegistrationId::new_with(0),
+				MessagePayload::new(&MyComponent(1))?,
+			)],
+		})?;
+		expect(&msg_out[1]).to_be(&Message::SpawnWith {
+			entity: denied,
+			components: vec![(
+				RegistrationId::new_with(1),
+				MessagePayload::new(&OtherComponent(4))?,
+			)],
+		})?;
+
+		Ok(())
+	}
+
+	#[test]
+	fn incoming_filtered() -> Result<()> {
+		let mut app1 = App::new();
+		app1.add_plugins(ReplicatePlugin)
+			.replicate::<MyComponent>()
+			.replicate::<OtherComponent>();
+		let mut app2 = App::new();
+		app2.add_plugins(ReplicatePlugin)
+			.replicate::<MyComponent>()
+			.replicate::<OtherComponent>();
+
+		let entity1 = app1.world_mut().spawn(Replicate::default()).id();
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+
+		// keep MyComponent local on the receiving entity
+		let entity2 =
+			app2.world().resource::<ReplicateRegistry>().entities[&entity1];
+		app2.world_mut().entity_mut(entity2).insert(
+			Replicate::default()
+				.deny::<MyComponent>()
+				.with_direction(ReplicateDirection::Incoming),
+		);
+		app2.update();
+		expect(app2.world().resource::<MessageOutgoing>().len()).to_be(0)?;
+
+		app1.world_mut()
+			.entity_mut(entity1)
+			.insert((MyComponent(1), OtherComponent(2)));
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+
+		let entity2 = app2.world().entity(entity2);
+		expect(entity2.get::<MyComponent>()).to_be_none()?;
+		expect(entity2.get::<OtherComponent>())
+			.as_some()?
+			.to_be(&OtherComponent(2))?;
+
+		Ok(())
+	}
+
+	#[test]
+	fn no_echo() -> Result<()> {
+		let mut app1 = App::new();
+		app1.add_plugins(ReplicatePlugin)
+			.replicate::<MyComponent>()
+			.replicate::<OtherComponent>();
+		let mut app2 = App::new();
+		app2.add_plugins(ReplicatePlugin)
+			.replicate::<MyComponent>()
+			.replicate::<OtherComponent>();
+
+		let entity1 = app1
+			.world_mut()
+			.spawn((Replicate::default(), MyComponent(7)))
+			.id();
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+
+		// replicate back from the receiving entity
+		let entity2 =
+			app2.world().resource::<ReplicateRegistry>().entities[&entity1];
+		app2.world_mut()
+			.entity_mut(entity2)
+			.insert(Replicate::default());
+		app2.update();
+		// the mirror is not spawned again on app1
+		expect(app2.world().resource::<MessageOutgoing>().len()).to_be(0)?;
+
+		app1.world_mut()
+			.entity_mut(entity1)
+			.insert((MyComponent(8), OtherComponent(1)));
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+		app1.world_mut()
+			.entity_mut(entity1)
+			.remove::<OtherComponent>();
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+
+		expect(app2.world().get::<MyComponent>(entity2))
+			.to_be(Some(&MyComponent(8)))?;
+		expect(app2.world().get::<OtherComponent>(entity2)).to_be_none()?;
+		expect(app2.world().resource::<MessageOutgoing>().len()).to_be(0)?;
+
+		// local changes are still sent
+		app2.world_mut().entity_mut(entity2).insert(MyComponent(9));
+		app2.update();
+		expect(&app2.world().resource::<MessageOutgoing>().0).to_be(&vec![
+			Message::Change {
+				entity: entity2,
+				reg_id: RegistrationId::new_with(0),
+				payload: MessagePayload::new(&MyComponent(9))?,
+			},
+		])?;
+
+		Ok(())
+	}
 }
@@ -1,25 +1,98 @@
 use crate::prelude::*;
 use bevy::prelude::*;
+use bevy::reflect::TypeRegistry;
+use forky::prelude::ResultTEExt;
 
 
 pub struct ReplicateEntityPlugin;
 
+/// Sends a [`Message::SpawnWith`] including all replicated components,
+/// replacing any [`Message::Add`] already queued for them.
 pub fn outgoing_spawn(
 	trigger: Trigger<OnAdd, Replicate>,
+	registrations: Res<ReplicateRegistry>,
+	type_registry: Res<AppTypeRegistry>,
 	mut outgoing: ResMut<MessageOutgoing>,
+	query: Query<EntityRef>,
 ) {
-	outgoing.push(
-		Message::Spawn {
-			entity: trigger.entity(),
+	let entity = trigger.entity();
+	let Ok(entity_ref) = query.get(entity) else {
+		return;
+	};
+	if !entity_ref
+		.get::<Replicate>()
+		.is_some_and(|replicate| replicate.is_outgoing())
+	{
+		return;
+	}
+	// mirrors already exist on the peer they were replicated from
+	if registrations.remote_entities.contains_key(&entity) {
+		log::debug!(
+			"not sending spawn of {entity}, it mirrors a remote entity"
+		);
+		return;
+	}
+	let components =
+		outgoing_components(&registrations, &type_registry.read(), &entity_ref);
+	if components.is_empty() {
+		outgoing.push(Message::Spawn { entity });
+		return;
+	}
+	outgoing.retain(|msg| match msg {
+		Message::Add {
+			entity: added,
+			reg_id,
+			..
+		} if *added == entity => {
+			!components.iter().any(|(component, _)| component == reg_id)
 		}
-		.into(),
-	);
+		_ => true,
+	});
+	outgoing.push(Message::SpawnWith { entity, components });
+}
+
+/// The replicated components of an entity with an outgoing [`Replicate`],
+/// ordered by registration.
+pub(crate) fn outgoing_components(
+	registrations: &ReplicateRegistry,
+	type_registry: &TypeRegistry,
+	entity_ref: &EntityRef,
+) -> Vec<(RegistrationId, MessagePayload)> {
+	let Some(replicate) = entity_ref.get::<Replicate>() else {
+		return Vec::new();
+	};
+	let mut components = registrations
+		.outgoing_component_fns
+		.iter()
+		.filter(|(reg_id, _)| {
+			registrations
+				.type_id(**reg_id)
+				.is_some_and(|type_id| replicate.allows_outgoing(type_id))
+		})
+		.filter_map(|(reg_id, serialize)| {
+			serialize(entity_ref, type_registry)
+				.ok_or(|e| log::error!("{e}"))
+				.flatten()
+				.map(|payload| (*reg_id, payload))
+		})
+		.collect::<Vec<_>>();
+	components.sort_by_key(|(reg_id, _)| *reg_id);
+	components
 }
 
 pub fn outgoing_despawn(
 	trigger: Trigger<OnRemove, Replicate>,
+	remote: Res<RemoteChanges>,
 	mut outgoing: ResMut<MessageOutgoing>,
+	query: Query<&Replicate>,
 ) {
+	if remote.is_applying_despawn(trigger.entity())
+		|| !query
+			.get(trigger.entity())
+			.is_ok_and(|replicate| replicate.is_outgoing())
+	{
+		return;
+	}
 	outgoing.push(
 		Message::Despawn {
 			entity: trigger.entity(),
@@ -48,8 +121,15 @@ mod test {
 	use crate::prelude::*;
 	use anyhow::Result;
 	use bevy::prelude::*;
+	use serde::Deserialize;
+	use serde::Serialize;
 	use sweet::*;
 
+	#[derive(Debug, Clone, Component, Serialize, Deserialize, PartialEq)]
+	pub struct Health(pub u32);
+	#[derive(Debug, Clone, Component, Serialize, Deserialize, PartialEq)]
+	pub struct Speed(pub u32);
+
 
 
 	#[test]
@@ -99,4 +179,55 @@ mod test {
 
 		Ok(())
 	}
+
+	#[test]
+	fn spawn_with() -> Result<()> {
+		let mut app1 = App::new();
+		app1.add_plugins(ReplicatePlugin)
+			.replicate::<Health>()
+			.replicate::<Speed>();
+		let mut app2 = App::new();
+		app2.add_plugins(ReplicatePlugin)
+			.replicate::<Health>()
+			.replicate::<Speed>();
+		app2.world_mut().add_observer(
+			|trigger: Trigger<OnAdd, Health>,
+			 query: Query<&Speed>,
+			 mut commands: Commands| {
+				// the whole bundle is visible to observers
+				let speed = query.get(trigger.entity()).unwrap().clone();
+				commands.entity(trigger.entity()).insert(Speed(speed.0 + 1));
+			},
+		);
+
+		// components added before Replicate are included
+		let entity = app1.world_mut().spawn((Health(1), Speed(2))).id();
+		app1.world_mut()
+			.entity_mut(entity)
+			.insert(Replicate::default());
+		app1.update();
+		expect(&app1.world().resource::<MessageOutgoing>().0).to_be(&vec![
+			Message::SpawnWith {
+				entity,
+				components: vec![
+					(
+						RegistrationId::new_with(0),
+						MessagePayload::new(Health(1))?,
+					),
+					(
+						RegistrationId::new_with(1),
+						MessagePayload::new(Speed(2))?,
+					),
+				],
+			},
+		])?;
+
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+		let entity2 =
+			app2.world().resource::<ReplicateRegistry>().entities[&entity];
+		expect(app2.world().get::<Health>(entity2)).to_be(Some(&Health(1)))?;
+		expect(app2.world().get::<Speed>(entity2)).to_be(Some(&Speed(3)))?;
+		Ok(())
+	}
 }
@@ -15,7 +15,12 @@ impl EventFns {
 	pub fn new<T: Event + DeserializeOwned>() -> Self {
 		Self {
 			send: |world, payload| {
-				world.send_event(payload.deserialize::<T>()?);
+				let id = world.send_event(payload.deserialize::<T>()?);
+				if let (Some(id), Some(mut remote)) =
+					(id, world.get_resource_mut::<RemoteChanges>())
+				{
+					remote.record_event(id);
+				}
 				Ok(())
 			},
 		}
@@ -28,10 +33,14 @@ pub fn register_event_outgoing<T: Event + Serialize>(app: &mut App) {
 
 fn outgoing_send<T: Event + Serialize>(
 	registrations: Res<ReplicateRegistry>,
+	remote: Res<RemoteChanges>,
 	mut outgoing: ResMut<MessageOutgoing>,
 	mut events: EventReader<T>,
 ) {
-	for ev in events.read() {
+	for (ev, id) in events.read_with_id() {
+		if remote.is_remote_event(id) {
+			continue;
+		}
 		let Some(payload) =
 			MessagePayload::new(ev).ok_or(|e| log::error!("{e}"))
 		else {
@@ -116,4 +125,33 @@ mod test {
 		expect(events[0]).to_be(&MyEvent(7))?;
 		Ok(())
 	}
+
+	#[test]
+	fn no_echo() -> Result<()> {
+		let mut app1 = App::new();
+		app1.add_plugins(ReplicatePlugin)
+			.add_event::<MyEvent>()
+			.replicate_event::<MyEvent>();
+		let mut app2 = App::new();
+		app2.add_plugins(ReplicatePlugin)
+			.add_event::<MyEvent>()
+			.replicate_event::<MyEvent>();
+
+		app1.world_mut().send_event(MyEvent(7));
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+		expect(app2.world().resource::<MessageOutgoing>().len()).to_be(0)?;
+
+		// local events are still sent
+		app2.world_mut().send_event(MyEvent(8));
+		app2.update();
+		expect(&app2.world().resource::<MessageOutgoing>().0).to_be(&vec![
+			Message::SendEvent {
+				reg_id: RegistrationId::new_with(0),
+				payload: MessagePayload::new(&MyEvent(8))?,
+			},
+		])?;
+		Ok(())
+	}
 }
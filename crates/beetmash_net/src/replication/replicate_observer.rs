@@ -8,14 +8,24 @@ use serde::Serialize;
 /// Functions for handling reception of [`Event`] triggers.
 #[derive(Copy, Clone)]
 pub struct ObserverFns {
-	pub send: fn(&mut Commands, payload: &MessagePayload) -> Result<()>,
+	/// Trigger the event, targeting the provided local entities if any.
+	pub send: fn(
+		&mut Commands,
+		payload: &MessagePayload,
+		targets: Vec<Entity>,
+	) -> Result<()>,
 }
 
 impl ObserverFns {
 	pub fn new<T: Event + DeserializeOwned>() -> Self {
 		Self {
-			send: |commands, payload| {
-				commands.trigger(payload.deserialize::<T>()?);
+			send: |commands, payload, targets| {
+				let event = payload.deserialize::<T>()?;
+				if targets.is_empty() {
+					commands.trigger(event);
+				} else {
+					commands.trigger_targets(event, targets);
+				}
 				Ok(())
 			},
 		}
@@ -29,17 +39,33 @@ pub fn register_observer_outgoing<T: Event + Serialize>(app: &mut App) {
 fn outgoing_send<T: Event + Serialize>(
 	trigger: Trigger<T>,
 	registrations: Res<ReplicateRegistry>,
+	remote: Res<RemoteChanges>,
 	mut outgoing: ResMut<MessageOutgoing>,
 ) {
+	let reg_id = registrations.registration_id::<T>();
+	if remote.is_applying_observer(reg_id) {
+		return;
+	}
 	let Some(payload) =
 		MessagePayload::new(trigger.event()).ok_or(|e| log::error!("{e}"))
 	else {
 		return;
 	};
+	let (mut targets, mut owned_targets) = (Vec::new(), Vec::new());
+	let entity = trigger.entity();
+	if entity != Entity::PLACEHOLDER {
+		// mirrors are sent as the id of the entity they mirror
+		match registrations.remote_entities.get(&entity) {
+			Some(remote) => owned_targets.push(*remote),
+			None => targets.push(entity),
+		}
+	}
 	outgoing.push(
 		Message::SendObserver {
-			reg_id: registrations.registration_id::<T>(),
+			reg_id,
 			payload,
+			targets,
+			owned_targets,
 		}
 		.into(),
 	);
@@ -74,6 +100,8 @@ mod test {
 			&Message::SendObserver {
 				reg_id: RegistrationId::new_with(0),
 				payload: MessagePayload::new(&MyEvent(7))?,
+				targets: Vec::new(),
+				owned_targets: Vec::new(),
 			}
 			.into(),
 		)?;
@@ -110,4 +138,132 @@ mod test {
 
 		Ok(())
 	}
+
+	#[test]
+	fn incoming_targeted() -> Result<()> {
+		let mut app1 = App::new();
+		app1.add_plugins(ReplicatePlugin)
+			.replicate_observer_outgoing::<MyEvent>();
+		let mut app2 = App::new();
+		app2.add_plugins(ReplicatePlugin)
+			.replicate_observer_incoming::<MyEvent>();
+
+		let entity1 = app1.world_mut().spawn(Replicate::default()).id();
+		// test different entity ids
+		app2.world_mut().spawn_empty();
+		app2.world_mut().spawn_empty();
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+		let entity2 = *app2
+			.world()
+			.resource::<ReplicateRegistry>()
+			.entities
+			.get(&entity1)
+			.unwrap();
+		expect(entity2).not().to_be(entity1)?;
+
+		app1.world_mut()
+			.entity_mut(entity1)
+			.flush_trigger(MyEvent(7));
+		app1.update();
+		expect(&app1.world().resource::<MessageOutgoing>()[0]).to_be(
+			&Message::SendObserver {
+				reg_id: RegistrationId::new_with(0),
+				payload: MessagePayload::new(MyEvent(7))?,
+				targets: vec![entity1],
+				owned_targets: Vec::new(),
+			},
+		)?;
+		Message::loopback(app1.world_mut(), app2.world_mut());
+
+		let on_trigger = observe_trigger_entities::<MyEvent>(app2.world_mut());
+		app2.update();
+
+		expect(&on_trigger).to_have_been_called_times(1)?;
+		expect(&on_trigger).to_have_returned_nth_with(0, &entity2)?;
+
+		Ok(())
+	}
+
+	#[test]
+	fn mirror_to_owner() -> Result<()> {
+		let mut app1 = App::new();
+		app1.add_plugins(ReplicatePlugin)
+			.replicate_observer_incoming::<MyEvent>();
+		let mut app2 = App::new();
+		app2.add_plugins(ReplicatePlugin)
+			.replicate_observer_outgoing::<MyEvent>();
+
+		let entity1 = app1.world_mut().spawn(Replicate::default()).id();
+		// test different entity ids
+		app2.world_mut().spawn_empty();
+		app2.world_mut().spawn_empty();
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+		let entity2 =
+			app2.world().resource::<ReplicateRegistry>().entities[&entity1];
+
+		// trigger on the mirror
+		app2.world_mut()
+			.entity_mut(entity2)
+			.flush_trigger(MyEvent(7));
+		app2.update();
+		Message::loopback(app2.world_mut(), app1.world_mut());
+
+		let on_trigger = observe_trigger_entities::<MyEvent>(app1.world_mut());
+		app1.update();
+
+		expect(&on_trigger).to_have_been_called_times(1)?;
+		expect(&on_trigger).to_have_returned_nth_with(0, &entity1)?;
+		Ok(())
+	}
+
+	#[test]
+	fn unmapped_targets() -> Result<()> {
+		let mut app1 = App::new();
+		app1.add_plugins(ReplicatePlugin);
+		let mut app2 = App::new();
+		app2.add_plugins(ReplicatePlugin)
+			.replicate_observer_incoming::<MyEvent>();
+
+		let entity1 = app1.world_mut().spawn(Replicate::default()).id();
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+		let entity2 =
+			app2.world().resource::<ReplicateRegistry>().entities[&entity1];
+
+		let on_trigger = observe_trigger_entities::<MyEvent>(app2.world_mut());
+		let unmapped = Entity::from_raw(99);
+		for targets in [vec![entity1, unmapped], vec![unmapped]] {
+			app2.world_mut().resource_mut::<MessageIncoming>().push(
+				Message::SendObserver {
+					reg_id: RegistrationId::new_with(0),
+					payload: MessagePayload::new(MyEvent(7))?,
+					targets,
+					owned_targets: Vec::new(),
+				},
+			);
+			app2.update();
+		}
+
+		// the second trigger is dropped instead of triggering globally
+		expect(&on_trigger).to_have_been_called_times(1)?;
+		expect(&on_trigger).to_have_returned_nth_with(0, &entity2)?;
+		Ok(())
+	}
+
+	fn observe_trigger_entities<E: Event>(
+		world: &mut World,
+	) -> MockFunc<Entity, Entity, fn(Entity) -> Entity> {
+		let func: MockFunc<Entity, Entity, fn(Entity) -> Entity> =
+			mock_func(|a| a);
+		let func2 = func.clone();
+		world.add_observer(move |trigger: Trigger<E>| {
+			func2.call(trigger.entity());
+		});
+		func
+	}
 }
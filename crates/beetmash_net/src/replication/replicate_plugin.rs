@@ -1,5 +1,7 @@
 use crate::prelude::*;
 use bevy::prelude::*;
+use bevy::utils::HashSet;
+use std::any::TypeId;
 
 
 
@@ -11,9 +13,92 @@ pub struct MessageIncomingSet;
 /// The set in which [`MessageOutgoing`] messages are written.
 pub struct MessageOutgoingSet;
 
-/// Mark an entity for outgoing replication
-#[derive(Default, Component)]
-pub struct Replicate {}
+/// Mark an entity for outgoing replication.
+/// By default every registered component is replicated,
+/// use [`Replicate::allow`] or [`Replicate::deny`] to filter them.
+///
+/// Entities spawned by incoming replication are never sent on,
+/// even with an outgoing [`Replicate`], because messages are broadcast
+/// and the spawn would be echoed back to the peer that owns them.
+/// To forward them, ie from a host app, spawn a new entity instead.
+#[derive(Debug, Default, Clone, PartialEq, Component)]
+pub struct Replicate {
+	pub components: ReplicateComponents,
+	/// Overrides the registered direction for this entity, ie
+	/// [`ReplicateDirection::Incoming`] will receive changes but never send.
+	pub direction: Option<ReplicateDirection>,
+}
+
+impl Replicate {
+	/// Only replicate the allowed components,
+	/// replacing any [`ReplicateComponents::Deny`] list.
+	pub fn allow<T: Component>(mut self) -> Self {
+		let type_id = TypeId::of::<T>();
+		match &mut self.components {
+			ReplicateComponents::Allow(types) => {
+				types.insert(type_id);
+			}
+			_ => {
+				self.components =
+					ReplicateComponents::Allow(HashSet::from([type_id]));
+			}
+		}
+		self
+	}
+	/// Replicate all components except denied ones,
+	/// replacing any [`ReplicateComponents::Allow`] list.
+	pub fn deny<T: Component>(mut self) -> Self {
+		let type_id = TypeId::of::<T>();
+		match &mut self.components {
+			ReplicateComponents::Deny(types) => {
+				types.insert(type_id);
+			}
+			_ => {
+				self.components =
+					ReplicateComponents::Deny(HashSet::from([type_id]));
+			}
+		}
+		self
+	}
+	pub fn with_direction(mut self, direction: ReplicateDirection) -> Self {
+		self.direction = Some(direction);
+		self
+	}
+
+	pub fn is_incoming(&self) -> bool {
+		self.direction.is_none_or(|dir| dir.is_incoming())
+	}
+	pub fn is_outgoing(&self) -> bool {
+		self.direction.is_none_or(|dir| dir.is_outgoing())
+	}
+
+	/// Whether the component should be replicated for this entity,
+	/// regardless of direction.
+	pub fn allows(&self, type_id: TypeId) -> bool {
+		match &self.components {
+			ReplicateComponents::All => true,
+			ReplicateComponents::Allow(types) => types.contains(&type_id),
+			ReplicateComponents::Deny(types) => !types.contains(&type_id),
+		}
+	}
+	pub fn allows_outgoing(&self, type_id: TypeId) -> bool {
+		self.is_outgoing() && self.allows(type_id)
+	}
+	pub fn allows_incoming(&self, type_id: TypeId) -> bool {
+		self.is_incoming() && self.allows(type_id)
+	}
+}
+
+/// Which registered components of a [`Replicate`] entity are replicated.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum ReplicateComponents {
+	#[default]
+	All,
+	/// Only these components are replicated
+	Allow(HashSet<TypeId>),
+	/// All components except these are replicated
+	Deny(HashSet<TypeId>),
+}
 
 /**
 Base replication plugin, excluding [`Transport`] and any registered [`Component`], [`Resource`], or [`Event`] plugins.
@@ -36,13 +121,16 @@ impl Plugin for ReplicatePlugin {
 			)
 			.init_resource::<ReplicateRegistry>()
 			.init_resource::<MessageIncoming>()
+			.init_resource::<MessageIncomingSources>()
 			.init_resource::<MessageOutgoing>()
+			.init_resource::<RemoteChanges>()
 			.add_systems(
 				Update,
 				(
-					handle_incoming_commands.in_set(MessageIncomingSet),
-					handle_incoming_world.in_set(MessageIncomingSet),
+					handle_incoming.in_set(MessageIncomingSet),
 					clear_incoming.after(MessageIncomingSet),
+					clear_incoming_sources.after(MessageIncomingSet),
+					clear_remote_changes.after(MessageOutgoingSet),
 				),
 			);
 
@@ -53,3 +141,7 @@ impl Plugin for ReplicatePlugin {
 
 
 fn clear_incoming(mut incoming: ResMut<MessageIncoming>) { incoming.clear(); }
+
+fn clear_incoming_sources(mut sources: ResMut<MessageIncomingSources>) {
+	sources.clear();
+}
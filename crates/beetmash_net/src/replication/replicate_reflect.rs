@@ -0,0 +1,423 @@
+use crate::prelude::*;
+use anyhow::Result;
+use bevy::ecs::component::ComponentId;
+use bevy::ecs::system::SystemChangeTick;
+use bevy::prelude::*;
+use bevy::reflect::serde::TypedReflectDeserializer;
+use bevy::reflect::serde::TypedReflectSerializer;
+use bevy::reflect::ReflectFromReflect;
+use bevy::reflect::TypeRegistry;
+use forky::prelude::ResultTEExt;
+use std::any::TypeId;
+use std::sync::Arc;
+
+impl ComponentFns {
+	/// Functions for a component only known at runtime. The type must
+	/// be registered in the [`AppTypeRegistry`] with [`ReflectComponent`].
+	/// Deserialization requires world access so is deferred,
+	/// errors are logged instead of returned.
+	pub fn reflect(type_id: TypeId) -> Self {
+		Self {
+			insert: Arc::new(move |commands, payload| {
+				queue_insert_reflect(commands, type_id, payload);
+				Ok(())
+			}),
+			change: Arc::new(move |commands, payload| {
+				queue_insert_reflect(commands, type_id, payload);
+				Ok(())
+			}),
+			remove: Arc::new(move |commands| {
+				commands.queue(move |entity: Entity, world: &mut World| {
+					let registry = world.resource::<AppTypeRegistry>().clone();
+					let registry = registry.read();
+					let Some(reflect_component) =
+						reflect_component(&registry, type_id)
+							.ok_or(|e| log::error!("{e}"))
+					else {
+						return;
+					};
+					if let Ok(mut entity) = world.get_entity_mut(entity) {
+						reflect_component.remove(&mut entity);
+					}
+				});
+			}),
+			erased: Arc::new(move |world, payload| {
+				erased_reflect(world, type_id, payload)
+			}),
+		}
+	}
+}
+
+fn queue_insert_reflect(
+	commands: &mut EntityCommands,
+	type_id: TypeId,
+	payload: &MessagePayload,
+) {
+	let payload = payload.clone();
+	commands.queue(move |entity: Entity, world: &mut World| {
+		insert_reflect(world, entity, type_id, &payload)
+			.ok_or(|e| log::error!("{e}"));
+	});
+}
+
+fn insert_reflect(
+	world: &mut World,
+	entity: Entity,
+	type_id: TypeId,
+	payload: &MessagePayload,
+) -> Result<()> {
+	let registry = world.resource::<AppTypeRegistry>().clone();
+	let registry = registry.read();
+	let registration = registry.get(type_id).ok_or_else(|| {
+		anyhow::anyhow!("type is not registered: {type_id:?}")
+	})?;
+	let reflect_component = reflect_component(&registry, type_id)?;
+	let value = payload.deserialize_seed(TypedReflectDeserializer::new(
+		registration,
+		&registry,
+	))?;
+	let mut entity = world.get_entity_mut(entity)?;
+	reflect_component.insert(&mut entity, value.as_ref(), &registry);
+	Ok(())
+}
+
+fn erased_reflect(
+	world: &mut World,
+	type_id: TypeId,
+	payload: &MessagePayload,
+) -> Result<ErasedComponent> {
+	let registry = world.resource::<AppTypeRegistry>().clone();
+	let registry = registry.read();
+	let registration = registry.get(type_id).ok_or_else(|| {
+		anyhow::anyhow!("type is not registered: {type_id:?}")
+	})?;
+	let from_reflect =
+		registration.data::<ReflectFromReflect>().ok_or_else(|| {
+			anyhow::anyhow!("type does not reflect FromReflect: {type_id:?}")
+		})?;
+	let component_id =
+		reflect_component(&registry, type_id)?.register_component(world);
+	let value = payload.deserialize_seed(TypedReflectDeserializer::new(
+		registration,
+		&registry,
+	))?;
+	let value = from_reflect
+		.from_reflect(value.as_partial_reflect())
+		.ok_or_else(|| anyhow::anyhow!("failed to convert {type_id:?}"))?;
+	ErasedComponent::from_reflect(world, component_id, value)
+}
+
+fn reflect_component(
+	registry: &TypeRegistry,
+	type_id: TypeId,
+) -> Result<&ReflectComponent> {
+	registry
+		.get_type_data::<ReflectComponent>(type_id)
+		.ok_or_else(|| {
+			anyhow::anyhow!(
+				"type does not have `#[reflect(Component)]`: {type_id:?}"
+			)
+		})
+}
+
+fn reflect_payload(
+	registry: &TypeRegistry,
+	type_id: TypeId,
+	entity: &EntityRef,
+) -> Result<Option<MessagePayload>> {
+	let Some(component) = reflect_component(registry, type_id)?.reflect(entity)
+	else {
+		return Ok(None);
+	};
+	let payload = MessagePayload::new(TypedReflectSerializer::new(
+		component.as_partial_reflect(),
+		registry,
+	))?;
+	Ok(Some(payload))
+}
+
+fn allows_outgoing(entity: &EntityRef, type_id: TypeId) -> bool {
+	entity
+		.get::<Replicate>()
+		.is_some_and(|replicate| replicate.allows_outgoing(type_id))
+}
+
+/// Resolve a type path registered in the [`AppTypeRegistry`],
+/// returning its [`TypeId`] and [`ComponentId`].
+/// # Panics
+/// If the type is not registered or does not have `#[reflect(Component)]`
+pub fn reflect_component_ids(
+	world: &mut World,
+	type_path: &str,
+) -> (TypeId, ComponentId) {
+	let registry = world.resource::<AppTypeRegistry>().clone();
+	let registry = registry.read();
+	let Some(registration) = registry.get_with_type_path(type_path) else {
+		panic!(
+			"Type {} is not registered in the AppTypeRegistry",
+			type_path
+		);
+	};
+	let Some(reflect_component) = registration.data::<ReflectComponent>()
+	else {
+		panic!("Type {} does not have #[reflect(Component)]", type_path);
+	};
+	let component_id = reflect_component.register_component(world);
+	(registration.type_id(), component_id)
+}
+
+pub fn register_component_reflect_outgoing(
+	app: &mut App,
+	reg_id: RegistrationId,
+	type_id: TypeId,
+	component_id: ComponentId,
+) {
+	app.world_mut()
+		.resource_mut::<ReplicateRegistry>()
+		.outgoing_component_fns
+		.insert(
+			reg_id,
+			Arc::new(move |entity, registry| {
+				reflect_payload(registry, type_id, entity)
+			}),
+		);
+	app.add_systems(
+		Update,
+		(move |registry: Res<AppTypeRegistry>,
+		       remote: Res<RemoteChanges>,
+		       outgoing: ResMut<MessageOutgoing>,
+		       query: Query<EntityRef, With<Replicate>>,
+		       ticks: SystemChangeTick| {
+			outgoing_change(
+				reg_id,
+				type_id,
+				component_id,
+				(registry, remote),
+				outgoing,
+				query,
+				ticks,
+			)
+		})
+		.in_set(MessageOutgoingSet),
+	);
+
+	let add = Observer::new(
+		move |trigger: Trigger<OnAdd>,
+		      registry: Res<AppTypeRegistry>,
+		      remote: Res<RemoteChanges>,
+		      mut outgoing: ResMut<MessageOutgoing>,
+		      query: Query<EntityRef, With<Replicate>>| {
+			let Ok(entity) = query.get(trigger.entity()) else {
+				// no replicate component
+				return;
+			};
+			if !allows_outgoing(&entity, type_id)
+				|| outgoing.spawned_with(trigger.entity(), reg_id)
+				|| remote.is_applying_component(trigger.entity(), reg_id)
+			{
+				return;
+			}
+			if let Some(Some(payload)) =
+				reflect_payload(&registry.read(), type_id, &entity)
+					.ok_or(|e| log::error!("{e}"))
+			{
+				outgoing.push(Message::Add {
+					entity: trigger.entity(),
+					reg_id,
+					payload,
+				});
+			}
+		},
+	)
+	.with_component(component_id);
+
+	let remove = Observer::new(
+		move |trigger: Trigger<OnRemove>,
+		      remote: Res<RemoteChanges>,
+		      mut outgoing: ResMut<MessageOutgoing>,
+		      query: Query<&Replicate>| {
+			if !remote.is_applying_component(trigger.entity(), reg_id)
+				&& query
+					.get(trigger.entity())
+					.is_ok_and(|replicate| replicate.allows_outgoing(type_id))
+			{
+				outgoing.push(Message::Remove {
+					entity: trigger.entity(),
+					reg_id,
+				});
+			}
+		},
+	)
+	.with_component(component_id);
+
+	app.world_mut().spawn(add);
+	app.world_mut().spawn(remove);
+}
+
+/// Dynamic counterpart to the typed `outgoing_change`,
+/// using change ticks because there is no `Changed<T>` filter
+/// for a [`ComponentId`].
+fn outgoing_change(
+	reg_id: RegistrationId,
+	type_id: TypeId,
+	component_id: ComponentId,
+	(registry, remote): (Res<AppTypeRegistry>, Res<RemoteChanges>),
+	mut outgoing: ResMut<MessageOutgoing>,
+	query: Query<EntityRef, With<Replicate>>,
+	ticks: SystemChangeTick,
+) {
+	let registry = registry.read();
+	for entity in query.iter() {
+		let Some(component_ticks) = entity.get_change_ticks_by_id(component_id)
+		else {
+			continue;
+		};
+		if component_ticks.is_added(ticks.last_run(), ticks.this_run())
+			|| !component_ticks.is_changed(ticks.last_run(), ticks.this_run())
+			|| !allows_outgoing(&entity, type_id)
+			|| remote.is_remote_component(
+				entity.id(),
+				reg_id,
+				component_ticks.changed,
+			) {
+			continue;
+		}
+		if let Some(Some(payload)) =
+			reflect_payload(&registry, type_id, &entity)
+				.ok_or(|e| log::error!("{e}"))
+		{
+			outgoing.push(Message::Change {
+				entity: entity.id(),
+				reg_id,
+				payload,
+			});
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use bevy::prelude::*;
+	use sweet::*;
+
+	/// Does not implement `Serialize` or `Deserialize`
+	#[derive(Debug, Clone, PartialEq, Component, Reflect)]
+	#[reflect(Component)]
+	pub struct MyComponent {
+		value: i32,
+	}
+
+	fn my_component(value: i32) -> MyComponent { MyComponent { value } }
+
+	#[test]
+	fn outgoing() -> Result<()> {
+		let mut app = App::new();
+		app.add_plugins(ReplicatePlugin)
+			.replicate_reflect::<MyComponent>();
+
+		let entity = app
+			.world_mut()
+			.spawn((Replicate::default(), my_component(7)))
+			.id();
+		app.update();
+		app.world_mut().entity_mut(entity).insert(my_component(8));
+		app.update();
+		app.world_mut().entity_mut(entity).remove::<MyComponent>();
+		app.update();
+
+		let msg_out = app.world_mut().resource_mut::<MessageOutgoing>();
+		expect(msg_out.len()).to_be(3)?;
+		expect(&msg_out[0]).to_be(&Message::SpawnWith {
+			entity,
+			components: vec![(
+				RegistrationId::new_with(0),
+				MessagePayload::Dual(vec![7, 0, 0, 0], "{\"value\":7}".into()),
+			)],
+		})?;
+		expect(&msg_out[1]).to_be(&Message::Change {
+			entity,
+			reg_id: RegistrationId::new_with(0),
+			payload: MessagePayload::Dual(
+				vec![8, 0, 0, 0],
+				"{\"value\":8}".into(),
+			),
+		})?;
+		expect(&msg_out[2]).to_be(&Message::Remove {
+			entity,
+			reg_id: RegistrationId::new_with(0),
+		})?;
+
+		Ok(())
+	}
+
+	#[test]
+	fn incoming() -> Result<()> {
+		let mut app1 = App::new();
+		app1.add_plugins(ReplicatePlugin)
+			.replicate_reflect::<MyComponent>();
+
+		// the receiving app only knows the type path
+		let mut app2 = App::new();
+		app2.add_plugins(ReplicatePlugin)
+			.register_type::<MyComponent>()
+			.replicate_reflect_path_with(
+				"beetmash_net::replication::replicate_reflect::test::MyComponent",
+				ReplicateDirection::Incoming,
+			);
+
+		// INSERT
+		let entity1 = app1
+			.world_mut()
+			.spawn((Replicate::default(), my_component(7)))
+			.id();
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+		expect(
+			app2.world_mut()
+				.query::<&MyComponent>()
+				.iter(app2.world())
+				.next(),
+		)
+		.as_some()?
+		.to_be(&my_component(7))?;
+
+		// CHANGE, as json
+		app1.world_mut().entity_mut(entity1).insert(my_component(8));
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		for msg in app2
+			.world_mut()
+			.resource_mut::<MessageIncoming>()
+			.iter_mut()
+		{
+			*msg = msg.with_json_payload()?;
+		}
+		app2.update();
+		expect(
+			app2.world_mut()
+				.query::<&MyComponent>()
+				.iter(app2.world())
+				.next(),
+		)
+		.as_some()?
+		.to_be(&my_component(8))?;
+
+		// REMOVE
+		app1.world_mut().entity_mut(entity1).remove::<MyComponent>();
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+		expect(
+			app2.world_mut()
+				.query::<&MyComponent>()
+				.iter(app2.world())
+				.next(),
+		)
+		.to_be_none()?;
+
+		Ok(())
+	}
+}
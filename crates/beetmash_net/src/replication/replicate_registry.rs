@@ -34,12 +34,24 @@ pub struct ReplicateRegistry {
 
 	types: HashMap<TypeId, RegistrationId>,
 
+	type_ids: HashMap<RegistrationId, TypeId>,
+
 	type_names: HashMap<RegistrationId, String>,
 
-	/// Map of remote to local entity ids
+	/// Map of remote to local entity ids for messages without a
+	/// sender, see [`Self::map_entity`]
 	pub entities: HashMap<Entity, Entity>,
+	/// Like [`Self::entities`] for each relay client, as entity ids
+	/// from different clients may collide, ie on a lobby host.
+	pub client_entities: HashMap<ClientId, HashMap<Entity, Entity>>,
+	/// Map of local to remote entity ids, the inverse of the above
+	pub remote_entities: HashMap<Entity, Entity>,
 	pub incoming_component_fns: HashMap<RegistrationId, ComponentFns>,
+	/// Used to include components in a [`Message::SpawnWith`]
+	pub outgoing_component_fns: HashMap<RegistrationId, ComponentSerializeFn>,
 	pub incoming_resource_fns: HashMap<RegistrationId, ResourceFns>,
+	/// Used to include resources in a [`snapshot_messages`]
+	pub outgoing_resource_fns: HashMap<RegistrationId, ResourceSerializeFn>,
 	pub incoming_event_fns: HashMap<RegistrationId, EventFns>,
 	pub incoming_observer_fns: HashMap<RegistrationId, ObserverFns>,
 	pub directions: HashMap<RegistrationId, ReplicateDirection>,
@@ -55,28 +67,98 @@ impl ReplicateRegistry {
 		}
 	}
 
+	pub fn type_id(&self, reg_id: RegistrationId) -> Option<TypeId> {
+		self.type_ids.get(&reg_id).copied()
+	}
+
+	/// The direction the type was registered with
+	pub fn direction(
+		&self,
+		reg_id: RegistrationId,
+	) -> Option<ReplicateDirection> {
+		self.directions.get(&reg_id).copied()
+	}
+
 	pub fn types_to_json(&self) -> String {
+		self.to_json(|reg_id| format!("{}", **reg_id))
+	}
+
+	/// Like [`Self::types_to_json`] but mapping each type
+	/// to its [`ReplicateDirection`], ie `"Both"`.
+	pub fn directions_to_json(&self) -> String {
+		self.to_json(|reg_id| format!("\"{:?}\"", self.directions[reg_id]))
+	}
+
+	fn to_json(&self, value: impl Fn(&RegistrationId) -> String) -> String {
 		let mut types = self.types.values().collect::<Vec<_>>();
 		types.sort();
 		let types = types
 			.into_iter()
 			.map(|v| {
 				let name = self.type_names.get(v).unwrap();
-				format!("  \"{name}\": {}", **v)
+				format!("  \"{name}\": {}", value(v))
 			})
 			.collect::<Vec<String>>()
 			.join(",\n");
 		format!("{{\n{}\n}}", types)
 	}
 
+	/// The local entity mirroring the remote one sent by this
+	/// relay client, or by the only peer if `None`.
+	pub fn local_entity(
+		&self,
+		sender: Option<ClientId>,
+		remote: Entity,
+	) -> Option<Entity> {
+		match sender {
+			Some(client_id) => self.client_entities.get(&client_id)?,
+			None => &self.entities,
+		}
+		.get(&remote)
+		.copied()
+	}
+
+	/// Record that the local entity mirrors the remote one
+	pub fn map_entity(
+		&mut self,
+		sender: Option<ClientId>,
+		remote: Entity,
+		local: Entity,
+	) {
+		match sender {
+			Some(client_id) => {
+				self.client_entities.entry(client_id).or_default()
+			}
+			None => &mut self.entities,
+		}
+		.insert(remote, local);
+		self.remote_entities.insert(local, remote);
+	}
+
+	/// Remove the mapping of the remote entity, returning the local one
+	pub fn unmap_entity(
+		&mut self,
+		sender: Option<ClientId>,
+		remote: Entity,
+	) -> Option<Entity> {
+		let local = match sender {
+			Some(client_id) => self.client_entities.get_mut(&client_id)?,
+			None => &mut self.entities,
+		}
+		.remove(&remote)?;
+		self.remote_entities.remove(&local);
+		Some(local)
+	}
+
 	pub fn entity_fns(
 		&self,
+		sender: Option<ClientId>,
 		remote: Entity,
 		id: RegistrationId,
 	) -> Option<(Entity, &ComponentFns)> {
-		if let Some(entity) = self.entities.get(&remote) {
+		if let Some(entity) = self.local_entity(sender, remote) {
 			if let Some(fns) = self.incoming_component_fns.get(&id) {
-				return Some((*entity, fns));
+				return Some((entity, fns));
 			}
 		}
 		None
@@ -85,14 +167,27 @@ impl ReplicateRegistry {
 	fn next_id<T: 'static>(
 		&mut self,
 		direction: ReplicateDirection,
+	) -> RegistrationId {
+		self.next_id_with(
+			TypeId::of::<T>(),
+			std::any::type_name::<T>(),
+			direction,
+		)
+	}
+
+	fn next_id_with(
+		&mut self,
+		type_id: TypeId,
+		#[allow(unused)] type_name: &str,
+		direction: ReplicateDirection,
 	) -> RegistrationId {
 		let id = RegistrationId(self.id_incr);
 		self.id_incr += 1;
 		self.directions.insert(id, direction);
-		self.types.insert(std::any::TypeId::of::<T>(), id);
+		self.types.insert(type_id, id);
+		self.type_ids.insert(id, type_id);
 		#[cfg(debug_assertions)]
-		self.type_names
-			.insert(id, std::any::type_name::<T>().to_string());
+		self.type_names.insert(id, type_name.to_string());
 		id
 	}
 
@@ -107,6 +202,21 @@ impl ReplicateRegistry {
 		}
 		id
 	}
+	/// Register a component by its [`TypeId`], using reflection
+	/// for serialization. See [`ComponentFns::reflect`].
+	pub fn register_component_reflect(
+		&mut self,
+		type_id: TypeId,
+		type_path: &str,
+		direction: ReplicateDirection,
+	) -> RegistrationId {
+		let id = self.next_id_with(type_id, type_path, direction);
+		if direction.is_incoming() {
+			self.incoming_component_fns
+				.insert(id, ComponentFns::reflect(type_id));
+		}
+		id
+	}
 	pub fn register_resource<T: Resource + DeserializeOwned>(
 		&mut self,
 		direction: ReplicateDirection,
@@ -118,6 +228,21 @@ impl ReplicateRegistry {
 		}
 		id
 	}
+	/// Register a [`States`] type, see [`ResourceFns::state`].
+	#[cfg(feature = "bevy_state")]
+	pub fn register_state<
+		S: bevy::state::state::FreelyMutableState + Serialize + DeserializeOwned,
+	>(
+		&mut self,
+		direction: ReplicateDirection,
+	) -> RegistrationId {
+		let id = self.next_id::<S>(direction);
+		if direction.is_incoming() {
+			self.incoming_resource_fns
+				.insert(id, ResourceFns::state::<S>());
+		}
+		id
+	}
 	pub fn register_event<T: Event + DeserializeOwned>(
 		&mut self,
 		direction: ReplicateDirection,
@@ -190,4 +315,28 @@ mod test {
 		}
 		Ok(())
 	}
+
+	#[test]
+	fn directions() -> Result<()> {
+		let mut app = App::new();
+		app.replicate_event::<MyEvent>()
+			.replicate_with::<MyComponent>(ReplicateDirection::Incoming)
+			.replicate_resource_outgoing::<MyResource>();
+
+		let registry = app.world().resource::<ReplicateRegistry>();
+		expect(registry.direction(RegistrationId::new_with(0)))
+			.to_be(Some(ReplicateDirection::Both))?;
+		expect(registry.direction(RegistrationId::new_with(1)))
+			.to_be(Some(ReplicateDirection::Incoming))?;
+		expect(registry.direction(RegistrationId::new_with(2)))
+			.to_be(Some(ReplicateDirection::Outgoing))?;
+		expect(registry.incoming_resource_fns.len()).to_be(0)?;
+
+		let json: Value = serde_json::from_str(&registry.directions_to_json())?;
+		expect(json.get(
+			"beetmash_net::replication::replicate_registry::test::MyResource",
+		))
+		.to_be(Some(&Value::String("Outgoing".into())))?;
+		Ok(())
+	}
 }
@@ -6,7 +6,8 @@ use std::fs;
 use std::path::PathBuf;
 
 /// Replicated components and resources have unique ids that
-/// must be consistent among apps. Use this exporter to share them
+/// must be consistent among apps. Use this exporter to share them,
+/// along with their directions in a `_directions.json` sibling file.
 pub struct ReplicateRegistryExporter<P, M> {
 	pub plugin: P,
 	pub path: PathBuf,
@@ -32,6 +33,17 @@ impl<P: Clone + Plugins<M>, M> ReplicateRegistryExporter<P, M> {
 		self.path.set_file_name(name);
 		self
 	}
+	/// The path of the directions file, ie
+	/// `replication_registry_directions.json`.
+	pub fn directions_path(&self) -> PathBuf {
+		let stem = self
+			.path
+			.file_stem()
+			.map(|stem| stem.to_string_lossy().to_string())
+			.unwrap_or_default();
+		self.path.with_file_name(format!("{stem}_directions.json"))
+	}
+
 	/// Build a replication registry and write it to a file.
 	/// Expects the app to have a ReplicateRegistry resource.
 	/// # Errors
@@ -50,6 +62,7 @@ impl<P: Clone + Plugins<M>, M> ReplicateRegistryExporter<P, M> {
 			fs::create_dir_all(parent).ok();
 		}
 		fs::write(&self.path, json)?;
+		fs::write(self.directions_path(), registry.directions_to_json())?;
 		println!(
 			"Exported replicate registry:\nPath: {}",
 			self.path.display(),
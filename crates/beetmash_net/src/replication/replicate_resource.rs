@@ -6,6 +6,9 @@ use serde::de::DeserializeOwned;
 use serde::Serialize;
 
 
+/// Serialize the resource, if it exists.
+pub type ResourceSerializeFn = fn(&World) -> Result<Option<MessagePayload>>;
+
 /// Functions for handling reception of [`Resource`] messages.
 #[derive(Copy, Clone)]
 pub struct ResourceFns {
@@ -33,31 +36,43 @@ impl ResourceFns {
 }
 
 pub fn register_resource_outgoing<T: Resource + Serialize>(app: &mut App) {
+	let reg_id = app
+		.world()
+		.resource::<ReplicateRegistry>()
+		.registration_id::<T>();
+	app.world_mut()
+		.resource_mut::<ReplicateRegistry>()
+		.outgoing_resource_fns
+		.insert(reg_id, |world| {
+			world
+				.get_resource::<T>()
+				.map(MessagePayload::new)
+				.transpose()
+		});
 	app.add_systems(Update, handle_outgoing::<T>.in_set(MessageOutgoingSet));
 }
 
 fn handle_outgoing<T: Resource + Serialize>(
 	registrations: Res<ReplicateRegistry>,
+	remote: Res<RemoteChanges>,
 	mut outgoing: ResMut<MessageOutgoing>,
 	value: Option<Res<T>>,
 	mut exists: Local<bool>,
 ) {
+	let reg_id = registrations.registration_id::<T>();
 	if let Some(value) = value {
-		if *exists && value.is_changed() {
+		if remote.is_remote_resource(reg_id, value.last_changed()) {
+			// applied from incoming, dont echo
+			*exists = true;
+		} else if *exists && value.is_changed() {
 			// CHANGED
 			let Some(payload) =
 				MessagePayload::new(&*value).ok_or(|e| log::error!("{e}"))
 			else {
 				return;
 			};
-			outgoing.push(
-				Message::ChangeResource {
-					reg_id: registrations.registration_id::<T>(),
-					payload,
-				}
-				.into(),
-			);
-		} else {
+			outgoing.push(Message::ChangeResource { reg_id, payload }.into());
+		} else if !*exists {
 			// ADDED
 			*exists = true;
 			let Some(payload) =
@@ -65,23 +80,15 @@ fn handle_outgoing<T: Resource + Serialize>(
 			else {
 				return;
 			};
-			outgoing.push(
-				Message::InsertResource {
-					reg_id: registrations.registration_id::<T>(),
-					payload,
-				}
-				.into(),
-			);
+			outgoing.push(Message::InsertResource { reg_id, payload }.into());
 		}
 	} else if *exists {
 		// REMOVED
 		*exists = false;
-		outgoing.push(
-			Message::RemoveResource {
-				reg_id: registrations.registration_id::<T>(),
-			}
-			.into(),
-		);
+		if remote.is_remote_removed_resource(reg_id) {
+			return;
+		}
+		outgoing.push(Message::RemoveResource { reg_id }.into());
 	}
 }
 #[cfg(test)]
@@ -178,4 +185,42 @@ mod test {
 
 		Ok(())
 	}
+
+	#[test]
+	fn no_echo() -> Result<()> {
+		let mut app1 = App::new();
+		app1.add_plugins(ReplicatePlugin)
+			.replicate_resource::<MyResource>();
+		let mut app2 = App::new();
+		app2.add_plugins(ReplicatePlugin)
+			.replicate_resource::<MyResource>();
+
+		app1.world_mut().insert_resource(MyResource(7));
+		app1.update();
+		app1.world_mut().insert_resource(MyResource(8));
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+		app2.update();
+
+		expect(app2.world()).resource()?.to_be(&MyResource(8))?;
+		expect(app2.world().resource::<MessageOutgoing>().len()).to_be(0)?;
+
+		app1.world_mut().remove_resource::<MyResource>();
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+		expect(app2.world().get_resource::<MyResource>()).to_be_none()?;
+		expect(app2.world().resource::<MessageOutgoing>().len()).to_be(0)?;
+
+		// local changes are still sent
+		app2.world_mut().insert_resource(MyResource(9));
+		app2.update();
+		Message::loopback(app2.world_mut(), app1.world_mut());
+		app1.update();
+		expect(app1.world()).resource()?.to_be(&MyResource(9))?;
+		expect(app1.world().resource::<MessageOutgoing>().len()).to_be(0)?;
+
+		Ok(())
+	}
 }
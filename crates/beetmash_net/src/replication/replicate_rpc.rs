@@ -0,0 +1,380 @@
+use crate::prelude::*;
+use anyhow::Result;
+use bevy::ecs::system::SystemId;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+pub const DEFAULT_RPC_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Correlates an [`RpcRequest`] with its [`RpcResponse`].
+/// Ids are random so that requests from different peers
+/// handled by the same app do not collide.
+#[derive(
+	Debug,
+	Copy,
+	Clone,
+	PartialEq,
+	Eq,
+	Hash,
+	Deref,
+	Serialize,
+	Deserialize,
+	Reflect,
+)]
+pub struct RpcId(u64);
+
+impl RpcId {
+	pub fn next() -> Self { Self(rand::random()) }
+	pub fn new_with(id: u64) -> Self { Self(id) }
+}
+
+/// Trigger this to call a remote handler registered with
+/// [`AppExtRpc::replicate_rpc_incoming`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Event)]
+pub struct RpcRequest<T> {
+	pub id: RpcId,
+	pub request: T,
+}
+
+impl<T> RpcRequest<T> {
+	/// Create a request with a new [`RpcId`]
+	pub fn new(request: T) -> Self {
+		Self {
+			id: RpcId::next(),
+			request,
+		}
+	}
+}
+
+/// Sent by the handling app in response to an [`RpcRequest`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Event)]
+pub struct RpcResponse<T> {
+	pub id: RpcId,
+	pub result: Result<T, RpcError>,
+}
+
+/// Triggered in the calling app when a response is received for a pending
+/// request, or when the request times out.
+#[derive(Debug, Clone, PartialEq, Event)]
+pub struct OnRpcResponse<T> {
+	pub id: RpcId,
+	pub result: Result<T, RpcError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RpcError {
+	/// No response was received before the timeout elapsed
+	Timeout,
+	/// The handler returned an error or could not be run
+	Handler(String),
+}
+
+impl std::fmt::Display for RpcError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			RpcError::Timeout => write!(f, "rpc request timed out"),
+			RpcError::Handler(err) => write!(f, "rpc handler error: {err}"),
+		}
+	}
+}
+
+impl std::error::Error for RpcError {}
+
+/// Requests sent by this app that are awaiting a response.
+#[derive(Resource)]
+pub struct RpcPending<Req, Resp> {
+	pub timeout: Duration,
+	/// Map of request id to the [`Time::elapsed`] it was sent
+	pub requests: HashMap<RpcId, Duration>,
+	phantom: PhantomData<(Req, Resp)>,
+}
+
+impl<Req, Resp> RpcPending<Req, Resp> {
+	pub fn new(timeout: Duration) -> Self {
+		Self {
+			timeout,
+			requests: Default::default(),
+			phantom: PhantomData,
+		}
+	}
+}
+
+#[extend::ext(name=AppExtRpc)]
+pub impl App {
+	/// Send [`RpcRequest<Req>`] and receive [`OnRpcResponse<Resp>`].
+	/// Must be registered in the same order as
+	/// [`Self::replicate_rpc_incoming`] in the handling app.
+	fn replicate_rpc_outgoing<Req, Resp>(&mut self) -> &mut Self
+	where
+		Req: 'static + Send + Sync + Serialize + DeserializeOwned,
+		Resp: 'static + Send + Sync + Clone + Serialize + DeserializeOwned,
+	{
+		self.replicate_rpc_outgoing_with_timeout::<Req, Resp>(
+			DEFAULT_RPC_TIMEOUT,
+		)
+	}
+	fn replicate_rpc_outgoing_with_timeout<Req, Resp>(
+		&mut self,
+		timeout: Duration,
+	) -> &mut Self
+	where
+		Req: 'static + Send + Sync + Serialize + DeserializeOwned,
+		Resp: 'static + Send + Sync + Clone + Serialize + DeserializeOwned,
+	{
+		self.insert_resource(RpcPending::<Req, Resp>::new(timeout))
+			.replicate_observer_outgoing::<RpcRequest<Req>>()
+			.replicate_observer_incoming::<RpcResponse<Resp>>()
+			.add_observer(rpc_request_sent::<Req, Resp>)
+			.add_observer(rpc_response_received::<Req, Resp>)
+			.add_systems(
+				Update,
+				rpc_timeout::<Req, Resp>.in_set(MessageIncomingSet),
+			);
+		self
+	}
+
+	/// Handle incoming [`RpcRequest<Req>`] with a system,
+	/// sending its output as an [`RpcResponse<Resp>`].
+	fn replicate_rpc_incoming<Req, Resp, M>(
+		&mut self,
+		handler: impl IntoSystem<In<Req>, Result<Resp>, M> + 'static,
+	) -> &mut Self
+	where
+		Req: 'static + Send + Sync + Clone + Serialize + DeserializeOwned,
+		Resp: 'static + Send + Sync + Serialize + DeserializeOwned,
+	{
+		let system_id = self.world_mut().register_system(handler);
+		self.replicate_observer_incoming::<RpcRequest<Req>>()
+			.replicate_observer_outgoing::<RpcResponse<Resp>>()
+			.add_observer(
+				move |trigger: Trigger<RpcRequest<Req>>,
+				      mut commands: Commands| {
+					let RpcRequest { id, request } = trigger.event().clone();
+					commands.queue(move |world: &mut World| {
+						run_rpc_handler(world, system_id, id, request);
+					});
+				},
+			);
+		self
+	}
+}
+
+fn run_rpc_handler<Req: 'static, Resp: 'static + Send + Sync>(
+	world: &mut World,
+	system_id: SystemId<In<Req>, Result<Resp>>,
+	id: RpcId,
+	request: Req,
+) {
+	let result = match world.run_system_with_input(system_id, request) {
+		Ok(Ok(response)) => Ok(response),
+		Ok(Err(err)) => Err(RpcError::Handler(err.to_string())),
+		Err(err) => Err(RpcError::Handler(err.to_string())),
+	};
+	world.trigger(RpcResponse { id, result });
+}
+
+fn rpc_request_sent<Req: 'static + Send + Sync, Resp: 'static + Send + Sync>(
+	trigger: Trigger<RpcRequest<Req>>,
+	time: Res<Time>,
+	mut pending: ResMut<RpcPending<Req, Resp>>,
+) {
+	pending.requests.insert(trigger.event().id, time.elapsed());
+}
+
+fn rpc_response_received<
+	Req: 'static + Send + Sync,
+	Resp: 'static + Send + Sync + Clone,
+>(
+	trigger: Trigger<RpcResponse<Resp>>,
+	mut commands: Commands,
+	mut pending: ResMut<RpcPending<Req, Resp>>,
+) {
+	let RpcResponse { id, result } = trigger.event();
+	if pending.requests.remove(id).is_some() {
+		commands.trigger(OnRpcResponse {
+			id: *id,
+			result: result.clone(),
+		});
+	}
+}
+
+fn rpc_timeout<Req: 'static + Send + Sync, Resp: 'static + Send + Sync>(
+	mut commands: Commands,
+	time: Res<Time>,
+	mut pending: ResMut<RpcPending<Req, Resp>>,
+) {
+	let timeout = pending.timeout;
+	let now = time.elapsed();
+	pending.requests.retain(|id, sent| {
+		if now.saturating_sub(*sent) < timeout {
+			return true;
+		}
+		commands.trigger(OnRpcResponse::<Resp> {
+			id: *id,
+			result: Err(RpcError::Timeout),
+		});
+		false
+	});
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use beetmash_scene::prelude::*;
+	use bevy::prelude::*;
+	use bevy::time::TimePlugin;
+	use bevy::time::TimeUpdateStrategy;
+	use serde::Deserialize;
+	use serde::Serialize;
+	use std::time::Duration;
+	use sweet::*;
+
+	type Func<T> = MockFunc<T, T, fn(T) -> T>;
+
+	#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+	pub struct MyRequest(pub i32);
+	#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+	pub struct MyResponse(pub i32);
+
+	fn double(In(req): In<MyRequest>) -> Result<MyResponse> {
+		if req.0 < 0 {
+			anyhow::bail!("negative");
+		}
+		Ok(MyResponse(req.0 * 2))
+	}
+
+	fn caller() -> App {
+		let mut app = App::new();
+		app.add_plugins((TimePlugin, ReplicatePlugin))
+			.replicate_rpc_outgoing::<MyRequest, MyResponse>();
+		app
+	}
+
+	fn handler() -> App {
+		let mut app = App::new();
+		app.add_plugins(ReplicatePlugin)
+			.replicate_rpc_incoming::<MyRequest, MyResponse, _>(double);
+		app
+	}
+
+	fn round_trip(
+		app1: &mut App,
+		app2: &mut App,
+		request: MyRequest,
+	) -> (RpcId, Func<OnRpcResponse<MyResponse>>) {
+		let on_response =
+			observe_triggers::<OnRpcResponse<MyResponse>>(app1.world_mut());
+		let request = RpcRequest::new(request);
+		let id = request.id;
+		app1.world_mut().flush_trigger(request);
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+		Message::loopback(app2.world_mut(), app1.world_mut());
+		app1.update();
+		(id, on_response)
+	}
+
+	#[test]
+	fn works() -> Result<()> {
+		let (mut app1, mut app2) = (caller(), handler());
+		let (id, on_response) = round_trip(&mut app1, &mut app2, MyRequest(2));
+
+		expect(&on_response).to_have_been_called_times(1)?;
+		expect(&on_response).to_have_returned_nth_with(0, &OnRpcResponse {
+			id,
+			result: Ok(MyResponse(4)),
+		})?;
+		expect(
+			app1.world()
+				.resource::<RpcPending<MyRequest, MyResponse>>()
+				.requests
+				.len(),
+		)
+		.to_be(0)?;
+		Ok(())
+	}
+
+	#[test]
+	fn handler_error() -> Result<()> {
+		let (mut app1, mut app2) = (caller(), handler());
+		let (id, on_response) = round_trip(&mut app1, &mut app2, MyRequest(-1));
+
+		expect(&on_response).to_have_returned_nth_with(0, &OnRpcResponse {
+			id,
+			result: Err(RpcError::Handler("negative".into())),
+		})?;
+		Ok(())
+	}
+
+	#[test]
+	fn other_caller() -> Result<()> {
+		let (mut app1, mut app2, mut app3) = (caller(), handler(), caller());
+		let on_response1 =
+			observe_triggers::<OnRpcResponse<MyResponse>>(app1.world_mut());
+		let on_response3 =
+			observe_triggers::<OnRpcResponse<MyResponse>>(app3.world_mut());
+		// both callers send a request before either is handled
+		app1.world_mut()
+			.flush_trigger(RpcRequest::new(MyRequest(1)));
+		app3.world_mut()
+			.flush_trigger(RpcRequest::new(MyRequest(2)));
+		app1.update();
+		app3.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+		// responses are broadcast to every caller
+		let responses = app2
+			.world_mut()
+			.resource_mut::<MessageOutgoing>()
+			.drain(..)
+			.collect::<Vec<_>>();
+		app1.world_mut()
+			.resource_mut::<MessageIncoming>()
+			.extend(responses.clone());
+		app3.world_mut()
+			.resource_mut::<MessageIncoming>()
+			.extend(responses);
+		app1.update();
+		app3.update();
+
+		expect(&on_response1).to_have_been_called_times(1)?;
+		expect(&on_response3).to_have_been_called_times(0)?;
+		Ok(())
+	}
+
+	#[test]
+	fn timeout() -> Result<()> {
+		let mut app = App::new();
+		app.add_plugins((TimePlugin, ReplicatePlugin))
+			.replicate_rpc_outgoing_with_timeout::<MyRequest, MyResponse>(
+				Duration::from_secs(1),
+			)
+			.insert_resource(TimeUpdateStrategy::ManualDuration(
+				Duration::from_millis(250),
+			));
+		let on_response =
+			observe_triggers::<OnRpcResponse<MyResponse>>(app.world_mut());
+		let request = RpcRequest::new(MyRequest(2));
+		let id = request.id;
+		app.update();
+		app.world_mut().flush_trigger(request);
+		for _ in 0..3 {
+			app.update();
+		}
+		expect(&on_response).to_have_been_called_times(0)?;
+		app.update();
+		expect(&on_response).to_have_been_called_times(1)?;
+		expect(&on_response).to_have_returned_nth_with(0, &OnRpcResponse {
+			id,
+			result: Err(RpcError::Timeout),
+		})?;
+		Ok(())
+	}
+}
@@ -0,0 +1,239 @@
+use crate::prelude::*;
+use bevy::prelude::*;
+use bevy::state::state::FreelyMutableState;
+use forky::prelude::ResultTEExt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+impl ResourceFns {
+	/// States are sent as resource messages, applying incoming
+	/// values via [`NextState`] so that transition schedules run as usual.
+	/// Removing a state is not supported so removal is ignored.
+	pub fn state<S: FreelyMutableState + Serialize + DeserializeOwned>() -> Self
+	{
+		Self {
+			insert: |commands, payload| {
+				queue_next_state::<S>(commands, payload.deserialize()?);
+				Ok(())
+			},
+			change: |commands, payload| {
+				queue_next_state::<S>(commands, payload.deserialize()?);
+				Ok(())
+			},
+			remove: |_| {},
+		}
+	}
+}
+
+fn queue_next_state<S: FreelyMutableState + Serialize>(
+	commands: &mut Commands,
+	state: S,
+) {
+	commands.queue(move |world: &mut World| {
+		// setting the current state would trigger a transition,
+		// echoing the state back to the sender
+		if world
+			.get_resource::<State<S>>()
+			.is_some_and(|current| *current.get() == state)
+		{
+			return;
+		}
+		let Some(payload) =
+			MessagePayload::new(&state).ok_or(|e| log::error!("{e}"))
+		else {
+			return;
+		};
+		if let Some(mut next_state) = world.get_resource_mut::<NextState<S>>() {
+			next_state.set(state);
+			// the transition happens in a later frame,
+			// so the changed state cannot be checked by tick
+			let reg_id =
+				world.resource::<ReplicateRegistry>().registration_id::<S>();
+			world
+				.resource_mut::<RemoteChanges>()
+				.record_state(reg_id, payload);
+		} else {
+			log::error!(
+				"State {} is not initialized",
+				std::any::type_name::<S>()
+			);
+		}
+	});
+}
+
+pub fn register_state_outgoing<S: States + Serialize>(app: &mut App) {
+	let reg_id = app
+		.world()
+		.resource::<ReplicateRegistry>()
+		.registration_id::<S>();
+	app.world_mut()
+		.resource_mut::<ReplicateRegistry>()
+		.outgoing_resource_fns
+		.insert(reg_id, |world| {
+			world
+				.get_resource::<State<S>>()
+				.map(|state| MessagePayload::new(state.get()))
+				.transpose()
+		});
+	app.add_systems(
+		Update,
+		handle_state_outgoing::<S>.in_set(MessageOutgoingSet),
+	);
+}
+
+fn handle_state_outgoing<S: States + Serialize>(
+	registrations: Res<ReplicateRegistry>,
+	mut remote: ResMut<RemoteChanges>,
+	mut outgoing: ResMut<MessageOutgoing>,
+	state: Option<Res<State<S>>>,
+	mut exists: Local<bool>,
+) {
+	let reg_id = registrations.registration_id::<S>();
+	match state {
+		Some(state) if !*exists || state.is_changed() => {
+			let Some(payload) =
+				MessagePayload::new(state.get()).ok_or(|e| log::error!("{e}"))
+			else {
+				return;
+			};
+			if remote
+				.take_state(reg_id)
+				.is_some_and(|remote| remote == payload)
+			{
+				// transitioned to the state set by an incoming message
+				*exists = true;
+			} else if *exists {
+				outgoing.push(Message::ChangeResource { reg_id, payload });
+			} else {
+				*exists = true;
+				outgoing.push(Message::InsertResource { reg_id, payload });
+			}
+		}
+		None if *exists => {
+			*exists = false;
+			outgoing.push(Message::RemoveResource { reg_id });
+		}
+		_ => {}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use bevy::prelude::*;
+	use bevy::state::app::StatesPlugin;
+	use serde::Deserialize;
+	use serde::Serialize;
+	use sweet::*;
+
+	#[derive(
+		Debug,
+		Default,
+		Clone,
+		PartialEq,
+		Eq,
+		Hash,
+		States,
+		Serialize,
+		Deserialize,
+	)]
+	pub enum MyState {
+		#[default]
+		Loading,
+		Running,
+	}
+
+	#[test]
+	fn outgoing() -> Result<()> {
+		let mut app = App::new();
+		app.add_plugins((StatesPlugin, ReplicatePlugin))
+			.init_state::<MyState>()
+			.replicate_state_with::<MyState>(ReplicateDirection::Outgoing);
+
+		app.update();
+		app.world_mut()
+			.resource_mut::<NextState<MyState>>()
+			.set(MyState::Running);
+		app.update();
+		// unchanged
+		app.update();
+
+		let reg_id = RegistrationId::new_with(0);
+		let msg_out = app.world_mut().resource_mut::<MessageOutgoing>();
+		expect(msg_out.len()).to_be(2)?;
+		expect(&msg_out[0]).to_be(&Message::InsertResource {
+			reg_id,
+			payload: MessagePayload::new(&MyState::Loading)?,
+		})?;
+		expect(&msg_out[1]).to_be(&Message::ChangeResource {
+			reg_id,
+			payload: MessagePayload::new(&MyState::Running)?,
+		})?;
+		Ok(())
+	}
+
+	#[test]
+	fn incoming() -> Result<()> {
+		let mut app1 = App::new();
+		app1.add_plugins((StatesPlugin, ReplicatePlugin))
+			.init_state::<MyState>()
+			.replicate_state_with::<MyState>(ReplicateDirection::Outgoing);
+		let mut app2 = App::new();
+		app2.add_plugins((StatesPlugin, ReplicatePlugin))
+			.init_state::<MyState>()
+			.replicate_state_with::<MyState>(ReplicateDirection::Incoming);
+
+		app1.world_mut()
+			.resource_mut::<NextState<MyState>>()
+			.set(MyState::Running);
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		// NextState is applied in the following update
+		app2.update();
+		app2.update();
+
+		expect(app2.world().resource::<State<MyState>>().get())
+			.to_be(&MyState::Running)?;
+		Ok(())
+	}
+
+	#[test]
+	fn no_echo() -> Result<()> {
+		let mut app1 = App::new();
+		app1.add_plugins((StatesPlugin, ReplicatePlugin))
+			.init_state::<MyState>()
+			.replicate_state::<MyState>();
+		let mut app2 = App::new();
+		app2.add_plugins((StatesPlugin, ReplicatePlugin))
+			.init_state::<MyState>()
+			.replicate_state::<MyState>();
+		app2.update();
+		app2.world_mut().resource_mut::<MessageOutgoing>().clear();
+
+		app1.world_mut()
+			.resource_mut::<NextState<MyState>>()
+			.set(MyState::Running);
+		app1.update();
+		Message::loopback(app1.world_mut(), app2.world_mut());
+		app2.update();
+		app2.update();
+
+		expect(app2.world().resource::<State<MyState>>().get())
+			.to_be(&MyState::Running)?;
+		expect(app2.world().resource::<MessageOutgoing>().len()).to_be(0)?;
+
+		// local changes are still sent
+		app2.world_mut()
+			.resource_mut::<NextState<MyState>>()
+			.set(MyState::Loading);
+		app2.update();
+		expect(&app2.world().resource::<MessageOutgoing>().0).to_be(&vec![
+			Message::ChangeResource {
+				reg_id: RegistrationId::new_with(0),
+				payload: MessagePayload::new(&MyState::Loading)?,
+			},
+		])?;
+		Ok(())
+	}
+}
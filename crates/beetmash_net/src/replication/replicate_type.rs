@@ -1,5 +1,6 @@
 use crate::prelude::*;
 use bevy::prelude::*;
+use bevy::reflect::GetTypeRegistration;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
@@ -24,66 +25,175 @@ pub impl App {
 		}
 		self
 	}
-	fn replicate_resource_incoming<
-		T: Resource + Serialize + DeserializeOwned,
-	>(
+	/// Replicate a [`Reflect`] component that may not implement
+	/// [`Serialize`] or [`DeserializeOwned`]. It must be registered with
+	/// `#[reflect(Component)]` and is serialized with the [`AppTypeRegistry`].
+	fn replicate_reflect<T: Component + GetTypeRegistration + TypePath>(
 		&mut self,
 	) -> &mut Self {
-		self.init_resource::<ReplicateRegistry>()
+		self.replicate_reflect_with::<T>(ReplicateDirection::Both)
+	}
+	fn replicate_reflect_with<T: Component + GetTypeRegistration + TypePath>(
+		&mut self,
+		direction: ReplicateDirection,
+	) -> &mut Self {
+		self.register_type::<T>()
+			.replicate_reflect_path_with(T::type_path(), direction)
+	}
+	/// Replicate a component by its type path at runtime, useful for types
+	/// from other crates. The type must already be registered
+	/// in the [`AppTypeRegistry`] with `#[reflect(Component)]`.
+	/// # Panics
+	/// If the type is not registered or does not reflect [`Component`]
+	fn replicate_reflect_path(&mut self, type_path: &str) -> &mut Self {
+		self.replicate_reflect_path_with(type_path, ReplicateDirection::Both)
+	}
+	fn replicate_reflect_path_with(
+		&mut self,
+		type_path: &str,
+		direction: ReplicateDirection,
+	) -> &mut Self {
+		let (type_id, component_id) =
+			reflect_component_ids(self.world_mut(), type_path);
+		let reg_id = self
+			.init_resource::<ReplicateRegistry>()
 			.world_mut()
 			.resource_mut::<ReplicateRegistry>()
-			.register_resource::<T>(ReplicateDirection::Incoming);
+			.register_component_reflect(type_id, type_path, direction);
+		if direction.is_outgoing() {
+			register_component_reflect_outgoing(
+				self,
+				reg_id,
+				type_id,
+				component_id,
+			);
+		}
 		self
 	}
+	fn replicate_resource<T: Resource + Serialize + DeserializeOwned>(
+		&mut self,
+	) -> &mut Self {
+		self.replicate_resource_with::<T>(ReplicateDirection::Both)
+	}
+	fn replicate_resource_incoming<
+		T: Resource + Serialize + DeserializeOwned,
+	>(
+		&mut self,
+	) -> &mut Self {
+		self.replicate_resource_with::<T>(ReplicateDirection::Incoming)
+	}
 	fn replicate_resource_outgoing<
 		T: Resource + Serialize + DeserializeOwned,
 	>(
 		&mut self,
+	) -> &mut Self {
+		self.replicate_resource_with::<T>(ReplicateDirection::Outgoing)
+	}
+	fn replicate_resource_with<T: Resource + Serialize + DeserializeOwned>(
+		&mut self,
+		direction: ReplicateDirection,
 	) -> &mut Self {
 		self.init_resource::<ReplicateRegistry>()
 			.world_mut()
 			.resource_mut::<ReplicateRegistry>()
-			.register_resource::<T>(ReplicateDirection::Incoming);
-		register_resource_outgoing::<T>(self);
+			.register_resource::<T>(direction);
+		if direction.is_outgoing() {
+			register_resource_outgoing::<T>(self);
+		}
 		self
 	}
 
-	fn replicate_event_incoming<T: Event + Serialize + DeserializeOwned>(
+	/// Send [`State<S>`] transitions and apply incoming states
+	/// via [`NextState<S>`]. Use [`Self::replicate_state_with`] to choose
+	/// which app is the authority.
+	#[cfg(feature = "bevy_state")]
+	fn replicate_state<
+		S: bevy::state::state::FreelyMutableState + Serialize + DeserializeOwned,
+	>(
 		&mut self,
+	) -> &mut Self {
+		self.replicate_state_with::<S>(ReplicateDirection::Both)
+	}
+	#[cfg(feature = "bevy_state")]
+	fn replicate_state_with<
+		S: bevy::state::state::FreelyMutableState + Serialize + DeserializeOwned,
+	>(
+		&mut self,
+		direction: ReplicateDirection,
 	) -> &mut Self {
 		self.init_resource::<ReplicateRegistry>()
 			.world_mut()
 			.resource_mut::<ReplicateRegistry>()
-			.register_event::<T>(ReplicateDirection::Incoming);
+			.register_state::<S>(direction);
+		if direction.is_outgoing() {
+			register_state_outgoing::<S>(self);
+		}
 		self
 	}
+
+	/// Replicate [`StreamedAsset<A>`] placeholders and load them
+	/// once available, see [`AssetStreamPlugin`].
+	#[cfg(feature = "bevy_asset")]
+	fn stream_asset<A: Asset>(&mut self) -> &mut Self {
+		self.replicate::<StreamedAsset<A>>();
+		register_asset_stream::<A>(self);
+		self
+	}
+
+	fn replicate_event<T: Event + Serialize + DeserializeOwned>(
+		&mut self,
+	) -> &mut Self {
+		self.replicate_event_with::<T>(ReplicateDirection::Both)
+	}
+	fn replicate_event_incoming<T: Event + Serialize + DeserializeOwned>(
+		&mut self,
+	) -> &mut Self {
+		self.replicate_event_with::<T>(ReplicateDirection::Incoming)
+	}
 	fn replicate_event_outgoing<T: Event + Serialize + DeserializeOwned>(
 		&mut self,
+	) -> &mut Self {
+		self.replicate_event_with::<T>(ReplicateDirection::Outgoing)
+	}
+	fn replicate_event_with<T: Event + Serialize + DeserializeOwned>(
+		&mut self,
+		direction: ReplicateDirection,
 	) -> &mut Self {
 		self.init_resource::<ReplicateRegistry>()
 			.world_mut()
 			.resource_mut::<ReplicateRegistry>()
-			.register_event::<T>(ReplicateDirection::Incoming);
-		register_event_outgoing::<T>(self);
+			.register_event::<T>(direction);
+		if direction.is_outgoing() {
+			register_event_outgoing::<T>(self);
+		}
 		self
 	}
+	fn replicate_observer<T: Event + Serialize + DeserializeOwned>(
+		&mut self,
+	) -> &mut Self {
+		self.replicate_observer_with::<T>(ReplicateDirection::Both)
+	}
 	fn replicate_observer_incoming<T: Event + Serialize + DeserializeOwned>(
 		&mut self,
 	) -> &mut Self {
-		self.init_resource::<ReplicateRegistry>()
-			.world_mut()
-			.resource_mut::<ReplicateRegistry>()
-			.register_observer::<T>(ReplicateDirection::Incoming);
-		self
+		self.replicate_observer_with::<T>(ReplicateDirection::Incoming)
 	}
 	fn replicate_observer_outgoing<T: Event + Serialize + DeserializeOwned>(
 		&mut self,
+	) -> &mut Self {
+		self.replicate_observer_with::<T>(ReplicateDirection::Outgoing)
+	}
+	fn replicate_observer_with<T: Event + Serialize + DeserializeOwned>(
+		&mut self,
+		direction: ReplicateDirection,
 	) -> &mut Self {
 		self.init_resource::<ReplicateRegistry>()
 			.world_mut()
 			.resource_mut::<ReplicateRegistry>()
-			.register_observer::<T>(ReplicateDirection::Incoming);
-		register_observer_outgoing::<T>(self);
+			.register_observer::<T>(direction);
+		if direction.is_outgoing() {
+			register_observer_outgoing::<T>(self);
+		}
 		self
 	}
 }
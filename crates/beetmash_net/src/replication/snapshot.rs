@@ -0,0 +1,105 @@
+use crate::prelude::*;
+use bevy::prelude::*;
+use forky::prelude::ResultTEExt;
+
+/// Messages recreating everything currently replicated from this world,
+/// for a peer that joins late, ie a client joining a hosted lobby.
+/// Each entity with an outgoing [`Replicate`] is sent as a
+/// [`Message::SpawnWith`] and each outgoing resource or state
+/// as a [`Message::InsertResource`].
+pub fn snapshot_messages(world: &World) -> Vec<Message> {
+	let registrations = world.resource::<ReplicateRegistry>();
+	let type_registry = world.resource::<AppTypeRegistry>().read();
+
+	let mut entities = world
+		.iter_entities()
+		.filter(|entity| {
+			entity
+				.get::<Replicate>()
+				.is_some_and(|replicate| replicate.is_outgoing())
+		})
+		.collect::<Vec<_>>();
+	entities.sort_by_key(|entity| entity.id());
+	let mut messages = entities
+		.into_iter()
+		.map(|entity_ref| {
+			let entity = entity_ref.id();
+			let components =
+				outgoing_components(registrations, &type_registry, &entity_ref);
+			if components.is_empty() {
+				Message::Spawn { entity }
+			} else {
+				Message::SpawnWith { entity, components }
+			}
+		})
+		.collect::<Vec<_>>();
+
+	let mut resources = registrations
+		.outgoing_resource_fns
+		.iter()
+		.filter_map(|(reg_id, serialize)| {
+			serialize(world)
+				.ok_or(|e| log::error!("{e}"))
+				.flatten()
+				.map(|payload| (*reg_id, payload))
+		})
+		.collect::<Vec<_>>();
+	resources.sort_by_key(|(reg_id, _)| *reg_id);
+	messages.extend(
+		resources.into_iter().map(|(reg_id, payload)| {
+			Message::InsertResource { reg_id, payload }
+		}),
+	);
+	messages
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use bevy::prelude::*;
+	use serde::Deserialize;
+	use serde::Serialize;
+	use sweet::*;
+
+	#[derive(Debug, Clone, Component, Serialize, Deserialize, PartialEq)]
+	pub struct MyComponent(pub i32);
+	#[derive(Debug, Clone, Resource, Serialize, Deserialize, PartialEq)]
+	pub struct MyResource(pub i32);
+
+	#[test]
+	fn works() -> Result<()> {
+		let mut app1 = App::new();
+		app1.add_plugins(ReplicatePlugin)
+			.replicate::<MyComponent>()
+			.replicate_resource_outgoing::<MyResource>();
+		app1.world_mut()
+			.spawn((Replicate::default(), MyComponent(7)));
+		// not replicated
+		app1.world_mut().spawn(MyComponent(8));
+		app1.world_mut().insert_resource(MyResource(9));
+		app1.update();
+
+		let messages = snapshot_messages(app1.world());
+		expect(messages.len()).to_be(2)?;
+
+		let mut app2 = App::new();
+		app2.add_plugins(ReplicatePlugin)
+			.replicate::<MyComponent>()
+			.replicate_resource_incoming::<MyResource>();
+		app2.world_mut()
+			.resource_mut::<MessageIncoming>()
+			.extend(messages);
+		app2.update();
+
+		let components = app2
+			.world_mut()
+			.query::<&MyComponent>()
+			.iter(app2.world())
+			.cloned()
+			.collect::<Vec<_>>();
+		expect(components).to_be(vec![MyComponent(7)])?;
+		expect(app2.world().resource::<MyResource>()).to_be(&MyResource(9))?;
+		Ok(())
+	}
+}
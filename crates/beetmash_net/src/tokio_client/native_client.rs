@@ -1,54 +1,147 @@
 use crate::prelude::*;
 use anyhow::Result;
 use flume::Receiver;
-use futures_util::stream::SplitSink;
+use flume::Sender;
+use forky::prelude::ResultTEExt;
 use futures_util::SinkExt;
 use futures_util::StreamExt;
-use tokio::net::TcpStream;
-use tokio_tungstenite::connect_async;
-use tokio_tungstenite::MaybeTlsStream;
-use tokio_tungstenite::WebSocketStream;
 
 type TungMessage = tokio_tungstenite::tungstenite::protocol::Message;
 
+/// Connection options for [`NativeWsClient`].
+#[derive(Debug, Default, Clone)]
+pub struct NativeWsOptions {
+	/// Sent as the first message, for servers that require authentication.
+	pub credential: Option<String>,
+	/// DER encoded certificates trusted for `wss` urls,
+	/// in addition to the webpki roots, ie self-signed certificates.
+	#[cfg(feature = "tls")]
+	pub root_certs: Vec<Vec<u8>>,
+}
+
+impl NativeWsOptions {
+	pub fn with_credential(mut self, credential: impl Into<String>) -> Self {
+		self.credential = Some(credential.into());
+		self
+	}
+
+	/// Trust each certificate in the pem.
+	/// # Errors
+	/// If the pem is invalid or contains no certificates.
+	#[cfg(feature = "tls")]
+	pub fn with_root_cert_pem(mut self, pem: &[u8]) -> Result<Self> {
+		let certs = rustls_pemfile::certs(&mut &pem[..])
+			.map(|cert| cert.map(|cert| cert.to_vec()))
+			.collect::<Result<Vec<_>, _>>()?;
+		if certs.is_empty() {
+			anyhow::bail!("no certificates in pem");
+		}
+		self.root_certs.extend(certs);
+		Ok(self)
+	}
+
+	#[cfg(feature = "tls")]
+	fn connector(&self) -> Result<Option<tokio_tungstenite::Connector>> {
+		if self.root_certs.is_empty() {
+			return Ok(None);
+		}
+		let mut roots = rustls::RootCertStore::empty();
+		roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+		for cert in self.root_certs.iter() {
+			roots.add(rustls::pki_types::CertificateDer::from(cert.clone()))?;
+		}
+		let config = rustls::ClientConfig::builder()
+			.with_root_certificates(roots)
+			.with_no_client_auth();
+		Ok(Some(tokio_tungstenite::Connector::Rustls(
+			std::sync::Arc::new(config),
+		)))
+	}
+}
 
+/// Can receive binary or json messages, sends as binary.
+/// [`RelayEnvelope`] frames are unwrapped, with the sender
+/// available via [`Transport::recv_with_sender`].
 pub struct NativeWsClient {
-	pub send:
-		SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, TungMessage>,
+	send: Sender<Vec<u8>>,
+	send_task: tokio::task::JoinHandle<Result<()>>,
 	recv_task: tokio::task::JoinHandle<Result<()>>,
-	recv: Receiver<Vec<u8>>,
+	recv: Receiver<RelayFrame>,
+	client_id: Option<ClientId>,
 }
 
-// impl Default for NativeWsClient {
-// 	fn default() -> Self {
-// 		Self {
-// 			user_id: 7,
-// 			channel_id: 16,
-// 			url: "ws://127.0.0.1:3000/ws".into(),
-// 		}
-// 	}
-// }
-
-
 impl NativeWsClient {
 	pub async fn new(url: &str) -> Result<Self> {
-		let (ws_stream, _response) = connect_async(url).await?;
-		let (send, mut recv_stream) = ws_stream.split();
+		Self::new_with_options(url, &NativeWsOptions::default()).await
+	}
+
+	/// Connect and send the credential as the first message,
+	/// for servers that require authentication.
+	pub async fn new_with_credential(
+		url: &str,
+		credential: &str,
+	) -> Result<Self> {
+		Self::new_with_options(
+			url,
+			&NativeWsOptions::default().with_credential(credential),
+		)
+		.await
+	}
+
+	pub async fn new_with_options(
+		url: &str,
+		options: &NativeWsOptions,
+	) -> Result<Self> {
+		#[cfg(feature = "tls")]
+		let (ws_stream, _response) =
+			tokio_tungstenite::connect_async_tls_with_config(
+				url,
+				None,
+				false,
+				options.connector()?,
+			)
+			.await?;
+		#[cfg(not(feature = "tls"))]
+		let (ws_stream, _response) = tokio_tungstenite::connect_async(url).await?;
+		let (mut send_sink, mut recv_stream) = ws_stream.split();
 
-		let (recv_send, recv_recv) = flume::unbounded();
+		if let Some(credential) = &options.credential {
+			send_sink
+				.send(TungMessage::Text(credential.clone()))
+				.await?;
+		}
 
+		let (send, send_recv) = flume::unbounded::<Vec<u8>>();
+		let send_task = tokio::spawn(async move {
+			while let Ok(bytes) = send_recv.recv_async().await {
+				send_sink.send(TungMessage::Binary(bytes)).await?;
+			}
+			Ok(())
+		});
+
+		let (recv_send, recv) = flume::unbounded();
 		let recv_task = tokio::spawn(async move {
 			while let Some(Ok(msg)) = recv_stream.next().await {
 				match msg {
-					// #[allow(unused_variables)]
-					TungWsEvent::Text(txt) => {
-						// #[cfg(feature = "json")]
-						// recv_send.recv(Message::vec_from_json(&txt)?).await?;
-						// 	#[cfg(not(feature = "json"))]
-						// 	anyhow::bail!("received text but feature coora_core/json disabled");
-					}
 					TungMessage::Binary(bytes) => {
-						recv_send.send(bytes)?;
+						if let Some(frame) = RelayFrame::from_bytes(&bytes)
+							.ok_or(|e| log::error!("{e}"))
+						{
+							recv_send.send(frame)?;
+						}
+					}
+					#[cfg(feature = "serde_json")]
+					TungMessage::Text(txt) => {
+						if let Some(messages) = Message::vec_from_json(&txt)
+							.ok_or(|e| log::error!("{e}"))
+						{
+							recv_send
+								.send(RelayFrame::Messages(None, messages))?;
+						}
+					}
+					TungMessage::Close(frame) => {
+						log::info!("connection closed: {frame:?}");
+						break;
 					}
 					_ => {}
 				}
@@ -58,24 +151,54 @@ impl NativeWsClient {
 
 		Ok(Self {
 			send,
+			send_task,
 			recv_task,
-			recv: recv_recv,
+			recv,
+			client_id: None,
 		})
 	}
+
+	/// The id assigned by the relay server,
+	/// available once received, see [`RelayEnvelope::joined`].
+	pub fn client_id(&self) -> Option<ClientId> { self.client_id }
+
+	/// Send messages to specific clients of the relay server
+	/// instead of broadcasting them, see [`RelayEnvelope`].
+	pub fn send_to(
+		&mut self,
+		target: RelayTarget,
+		messages: &Vec<Message>,
+	) -> Result<()> {
+		let envelope = RelayEnvelope::from_messages(target, messages)?;
+		self.send.send(envelope.to_bytes()?)?;
+		Ok(())
+	}
 }
 
 impl Drop for NativeWsClient {
-	fn drop(&mut self) { self.recv_task.abort(); }
+	fn drop(&mut self) {
+		self.send_task.abort();
+		self.recv_task.abort();
+	}
 }
 
 impl Transport for NativeWsClient {
-	async fn send_bytes(&mut self, bytes: Vec<u8>) -> Result<()> {
-		self.send.send(TungMessage::Binary(bytes)).await?;
+	fn send(&mut self, messages: &Vec<Message>) -> Result<()> {
+		let bytes = Message::vec_into_bytes(messages)?;
+		self.send.send(bytes)?;
 		Ok(())
 	}
 
-	fn recv_bytes(&mut self) -> Result<Vec<Vec<u8>>> {
-		let bytes = self.recv.try_recv_all()?;
-		Ok(bytes)
+	fn recv(&mut self) -> Result<Vec<Message>> {
+		Ok(self
+			.recv_with_sender()?
+			.into_iter()
+			.flat_map(|(_, messages)| messages)
+			.collect())
+	}
+
+	fn recv_with_sender(&mut self) -> Result<Vec<SenderMessages>> {
+		let frames = self.recv.try_recv_all()?;
+		Ok(RelayFrame::collect(frames, &mut self.client_id))
 	}
 }
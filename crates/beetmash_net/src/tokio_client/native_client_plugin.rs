@@ -1,4 +1,5 @@
 use super::native_client::NativeWsClient;
+use super::native_client::NativeWsOptions;
 use crate::prelude::*;
 use bevy::prelude::*;
 use bevy::tasks::block_on;
@@ -6,12 +7,36 @@ use forky::prelude::ResultTEExt;
 
 pub struct NativeClientPlugin {
 	pub address: String,
+	/// Sent as the first message, for servers that require authentication.
+	pub credential: Option<String>,
+	/// DER encoded certificates trusted for `wss` addresses,
+	/// see [`NativeWsOptions::root_certs`].
+	#[cfg(feature = "tls")]
+	pub root_certs: Vec<Vec<u8>>,
 }
 
 impl Default for NativeClientPlugin {
 	fn default() -> Self {
 		Self {
 			address: "ws://127.0.0.1:3000/ws".into(),
+			credential: None,
+			#[cfg(feature = "tls")]
+			root_certs: Vec::new(),
+		}
+	}
+}
+
+impl NativeClientPlugin {
+	pub fn with_credential(mut self, credential: impl Into<String>) -> Self {
+		self.credential = Some(credential.into());
+		self
+	}
+
+	fn options(&self) -> NativeWsOptions {
+		NativeWsOptions {
+			credential: self.credential.clone(),
+			#[cfg(feature = "tls")]
+			root_certs: self.root_certs.clone(),
 		}
 	}
 }
@@ -19,9 +44,11 @@ impl Default for NativeClientPlugin {
 impl Plugin for NativeClientPlugin {
 	fn build(&self, app: &mut App) {
 		// TODO async tasks
-		if let Some(client) =
-			block_on(async { NativeWsClient::new(&self.address).await })
-				.ok_or(|e| log::error!("{e}"))
+		if let Some(client) = block_on(NativeWsClient::new_with_options(
+			&self.address,
+			&self.options(),
+		))
+		.ok_or(|e| log::error!("{e}"))
 		{
 			log::info!("client connected");
 
@@ -10,18 +10,42 @@ use js_sys::Uint8Array;
 use wasm_bindgen::JsCast;
 use wasm_bindgen::JsValue;
 use web_sys::BinaryType;
+use web_sys::Event;
 use web_sys::MessageEvent;
 use web_sys::WebSocket;
 
 
 /// Can receive binary or json messages, sends as binary.
+/// [`RelayEnvelope`] frames are unwrapped, with the sender
+/// available via [`Transport::recv_with_sender`].
 pub struct WebWsClient {
 	ws: WebSocket,
-	recv: Receiver<Vec<Message>>,
+	recv: Receiver<RelayFrame>,
+	client_id: Option<ClientId>,
 	#[allow(unused)] // dropping this deregisters the listener
 	listener: HtmlEventListener<MessageEvent>,
+	#[allow(unused)] // dropping this deregisters the listener
+	open_listener: Option<HtmlEventListener<Event>>,
 }
 impl WebWsClient {
+	/// Connect and send the credential as the first message once open,
+	/// for servers that require authentication.
+	pub fn new_with_credential(url: &str, credential: &str) -> Self {
+		let mut client = Self::new(url);
+		let ws = client.ws.clone();
+		let credential = credential.to_string();
+		client.open_listener = Some(HtmlEventListener::new_with_target(
+			"open",
+			move |_: Event| {
+				ws.send_with_str(&credential)
+					.anyhow()
+					.ok_or(|e| log::error!("{e}"));
+			},
+			client.ws.clone(),
+		));
+		client
+	}
+
 	pub fn new(url: &str) -> Self {
 		let ws = WebSocket::new(url).anyhow().unwrap();
 		ws.set_binary_type(BinaryType::Arraybuffer);
@@ -31,15 +55,36 @@ impl WebWsClient {
 		let listener = HtmlEventListener::new_with_target(
 			"message",
 			move |e: MessageEvent| {
-				if let Some(messages) = js_value_to_messages(&e.data())
+				if let Some(frame) = js_value_to_relay_frame(&e.data())
 					.ok_or(|e| log::error!("{e}"))
 				{
-					send.send(messages).ok_or(|e| log::error!("{e}"));
+					send.send(frame).ok_or(|e| log::error!("{e}"));
 				}
 			},
 			ws.clone(),
 		);
-		Self { ws, recv, listener }
+		Self {
+			ws,
+			recv,
+			client_id: None,
+			listener,
+			open_listener: None,
+		}
+	}
+
+	/// The id assigned by the relay server,
+	/// available once received, see [`RelayEnvelope::joined`].
+	pub fn client_id(&self) -> Option<ClientId> { self.client_id }
+
+	/// Send messages to specific clients of the relay server
+	/// instead of broadcasting them, see [`RelayEnvelope`].
+	pub fn send_to(
+		&mut self,
+		target: RelayTarget,
+		messages: &Vec<Message>,
+	) -> Result<()> {
+		let envelope = RelayEnvelope::from_messages(target, messages)?;
+		self.ws.send_with_u8_array(&envelope.to_bytes()?).anyhow()
 	}
 }
 
@@ -49,7 +94,18 @@ impl Transport for WebWsClient {
 		self.ws.send_with_u8_array(&bytes).anyhow()
 	}
 
-	fn recv(&mut self) -> Result<Vec<Message>> { self.recv.try_recv_all_flat() }
+	fn recv(&mut self) -> Result<Vec<Message>> {
+		Ok(self
+			.recv_with_sender()?
+			.into_iter()
+			.flat_map(|(_, messages)| messages)
+			.collect())
+	}
+
+	fn recv_with_sender(&mut self) -> Result<Vec<SenderMessages>> {
+		let frames = self.recv.try_recv_all()?;
+		Ok(RelayFrame::collect(frames, &mut self.client_id))
+	}
 }
 
 impl Drop for WebWsClient {
@@ -86,3 +142,13 @@ pub fn js_value_to_messages(data: &JsValue) -> Result<Vec<Message>> {
 		)
 	}
 }
+
+/// Like [`js_value_to_messages`], unwrapping [`RelayEnvelope`] frames.
+pub fn js_value_to_relay_frame(data: &JsValue) -> Result<RelayFrame> {
+	if let Some(array_buffer) = data.dyn_ref::<ArrayBuffer>() {
+		let bytes = Uint8Array::new(&array_buffer).to_vec();
+		RelayFrame::from_bytes(&bytes)
+	} else {
+		Ok(RelayFrame::Messages(None, js_value_to_messages(data)?))
+	}
+}
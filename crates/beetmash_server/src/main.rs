@@ -0,0 +1,6 @@
+use beetmash_server::prelude::*;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+	ServerConfig::from_cli_args()?.into_server().run().await
+}
@@ -0,0 +1,312 @@
+use super::*;
+use axum::extract::Path;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::header;
+use axum::http::StatusCode;
+use axum::middleware;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::routing::delete;
+use axum::routing::get;
+use axum::routing::post;
+use axum::Json;
+use axum::Router;
+use serde::Deserialize;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Summary of a lobby returned by the api.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LobbyInfo {
+	pub id: LobbyId,
+	pub clients: usize,
+	/// Whether the lobby runs a [`LobbyHost`]
+	pub host: bool,
+	/// Seconds the lobby is kept once empty, or forever if `None`
+	pub lifetime: Option<u64>,
+}
+
+impl LobbyInfo {
+	pub fn new(id: LobbyId, lobby: &LobbyInner) -> Self {
+		Self {
+			id,
+			clients: lobby.num_clients(),
+			host: lobby.has_host(),
+			lifetime: lobby.lifetime.map(|lifetime| lifetime.as_secs()),
+		}
+	}
+}
+
+/// Routes for managing lobbies, nested at `/api` by [`Server`]:
+/// - `GET /lobbies`: list lobbies
+/// - `GET /lobbies/{lobby}/clients`: list clients in a lobby
+///
+/// If `auth` is set, these require an `Authorization: Bearer {credential}`
+/// header accepted by either `auth` or `admin`.
+///
+/// The routes that change lobbies are only added if `admin` is set,
+/// and require a credential it accepts:
+/// - `POST /lobbies/{lobby}`: create a lobby that is kept when empty
+/// - `DELETE /lobbies/{lobby}`: disconnect all clients and remove the lobby
+/// - `DELETE /lobbies/{lobby}/clients/{client}`: kick a client
+pub fn api_router(
+	lobby_map: LobbyMap,
+	auth: Option<Arc<dyn AuthVerifier>>,
+	admin: Option<Arc<dyn AuthVerifier>>,
+) -> Router {
+	let mut router = Router::new()
+		.route("/lobbies", get(list_lobbies))
+		.route("/lobbies/:lobby/clients", get(list_clients))
+		.with_state(lobby_map.clone());
+	if let Some(auth) = auth {
+		let verifiers = std::iter::once(auth).chain(admin.clone()).collect();
+		router = router.layer(middleware::from_fn_with_state(
+			Verifiers(verifiers),
+			require_auth,
+		));
+	}
+	if let Some(admin) = admin {
+		router = router.merge(
+			Router::new()
+				.route(
+					"/lobbies/:lobby",
+					post(create_lobby).delete(close_lobby),
+				)
+				.route("/lobbies/:lobby/clients/:client", delete(kick_client))
+				.with_state(lobby_map)
+				.layer(middleware::from_fn_with_state(
+					Verifiers(vec![admin]),
+					require_auth,
+				)),
+		);
+	}
+	router
+}
+
+/// A credential is accepted if any of these accepts it.
+#[derive(Clone)]
+struct Verifiers(Vec<Arc<dyn AuthVerifier>>);
+
+async fn require_auth(
+	State(Verifiers(verifiers)): State<Verifiers>,
+	request: Request,
+	next: Next,
+) -> Response {
+	let credential = request
+		.headers()
+		.get(header::AUTHORIZATION)
+		.and_then(|value| value.to_str().ok())
+		.and_then(|value| value.strip_prefix("Bearer "));
+	let Some(credential) = credential else {
+		return (StatusCode::UNAUTHORIZED, "missing bearer credential")
+			.into_response();
+	};
+	let mut result = Err(anyhow::anyhow!("no verifier"));
+	for verifier in verifiers.iter() {
+		result = verifier.verify(credential);
+		if result.is_ok() {
+			break;
+		}
+	}
+	match result {
+		Ok(_) => next.run(request).await,
+		Err(err) => (StatusCode::UNAUTHORIZED, err.to_string()).into_response(),
+	}
+}
+
+async fn list_lobbies(State(map): State<LobbyMap>) -> Json<Vec<LobbyInfo>> {
+	let map = map.0.read().await;
+	let mut infos = Vec::with_capacity(map.lobbies.len());
+	for (id, lobby) in map.lobbies.iter() {
+		infos.push(LobbyInfo::new(id.clone(), &*lobby.read().await));
+	}
+	infos.sort_by(|a, b| a.id.cmp(&b.id));
+	Json(infos)
+}
+
+async fn create_lobby(
+	State(map): State<LobbyMap>,
+	Path(lobby_id): Path<LobbyId>,
+) -> Response {
+	let mut map = map.0.write().await;
+	let status = match map.create_lobby(lobby_id.clone()) {
+		Ok(true) => StatusCode::CREATED,
+		Ok(false) => StatusCode::OK,
+		Err(err) => {
+			return (StatusCode::BAD_REQUEST, err.to_string()).into_response();
+		}
+	};
+	let lobby = map.lobbies[&lobby_id].read().await;
+	(status, Json(LobbyInfo::new(lobby_id, &lobby))).into_response()
+}
+
+async fn close_lobby(
+	State(map): State<LobbyMap>,
+	Path(lobby_id): Path<LobbyId>,
+) -> StatusCode {
+	if map.0.write().await.close_lobby(&lobby_id).await {
+		StatusCode::NO_CONTENT
+	} else {
+		StatusCode::NOT_FOUND
+	}
+}
+
+async fn list_clients(
+	State(map): State<LobbyMap>,
+	Path(lobby_id): Path<LobbyId>,
+) -> Response {
+	let map = map.0.read().await;
+	match map.lobbies.get(&lobby_id) {
+		Some(lobby) => Json(lobby.read().await.client_infos()).into_response(),
+		None => StatusCode::NOT_FOUND.into_response(),
+	}
+}
+
+async fn kick_client(
+	State(map): State<LobbyMap>,
+	Path((lobby_id, client_id)): Path<(LobbyId, ClientId)>,
+) -> StatusCode {
+	let map = map.0.read().await;
+	let Some(lobby) = map.lobbies.get(&lobby_id) else {
+		return StatusCode::NOT_FOUND;
+	};
+	if lobby.write().await.kick(client_id) {
+		StatusCode::NO_CONTENT
+	} else {
+		StatusCode::NOT_FOUND
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use axum::body::Body;
+	use axum::http::Method;
+	use axum::http::Request;
+	use axum::http::StatusCode;
+	use axum::Router;
+	use std::sync::Arc;
+	use sweet::*;
+	use tower::ServiceExt;
+
+	fn verifier(secret: &str) -> Option<Arc<dyn AuthVerifier>> {
+		Some(Arc::new(SharedSecretVerifier::new(secret)))
+	}
+
+	async fn request(
+		router: &Router,
+		method: Method,
+		uri: &str,
+	) -> Result<(StatusCode, Vec<u8>)> {
+		request_with(router, method, uri, "admin").await
+	}
+
+	async fn request_with(
+		router: &Router,
+		method: Method,
+		uri: &str,
+		credential: &str,
+	) -> Result<(StatusCode, Vec<u8>)> {
+		let response = router
+			.clone()
+			.oneshot(
+				Request::builder()
+					.method(method)
+					.uri(uri)
+					.header("Authorization", format!("Bearer {credential}"))
+					.body(Body::empty())?,
+			)
+			.await?;
+		let status = response.status();
+		let body =
+			axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+		Ok((status, body.to_vec()))
+	}
+
+	#[tokio::test]
+	async fn lobbies() -> Result<()> {
+		let router = api_router(LobbyMap::default(), None, verifier("admin"));
+
+		let (status, body) =
+			request(&router, Method::POST, "/lobbies/foo").await?;
+		expect(status).to_be(StatusCode::CREATED)?;
+		expect(serde_json::from_slice::<LobbyInfo>(&body)?).to_be(
+			LobbyInfo {
+				id: "foo".into(),
+				clients: 0,
+				host: false,
+				lifetime: None,
+			},
+		)?;
+		let (status, _) =
+			request(&router, Method::POST, "/lobbies/foo").await?;
+		expect(status).to_be(StatusCode::OK)?;
+		let (status, _) =
+			request(&router, Method::POST, "/lobbies/foo.bar").await?;
+		expect(status).to_be(StatusCode::BAD_REQUEST)?;
+
+		let (_, body) = request(&router, Method::GET, "/lobbies").await?;
+		let lobbies = serde_json::from_slice::<Vec<LobbyInfo>>(&body)?;
+		expect(lobbies.len()).to_be(1)?;
+
+		let (status, body) =
+			request(&router, Method::GET, "/lobbies/foo/clients").await?;
+		expect(status).to_be(StatusCode::OK)?;
+		expect(body).to_be(b"[]".to_vec())?;
+		let (status, _) =
+			request(&router, Method::DELETE, "/lobbies/foo/clients/0").await?;
+		expect(status).to_be(StatusCode::NOT_FOUND)?;
+
+		let (status, _) =
+			request(&router, Method::DELETE, "/lobbies/foo").await?;
+		expect(status).to_be(StatusCode::NO_CONTENT)?;
+		let (status, _) =
+			request(&router, Method::GET, "/lobbies/foo/clients").await?;
+		expect(status).to_be(StatusCode::NOT_FOUND)?;
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn auth() -> Result<()> {
+		let router =
+			api_router(LobbyMap::default(), verifier("foo"), verifier("admin"));
+		let (status, _) =
+			request_with(&router, Method::GET, "/lobbies", "bar").await?;
+		expect(status).to_be(StatusCode::UNAUTHORIZED)?;
+		let (status, _) =
+			request_with(&router, Method::GET, "/lobbies", "foo").await?;
+		expect(status).to_be(StatusCode::OK)?;
+		let (status, _) =
+			request_with(&router, Method::GET, "/lobbies", "admin").await?;
+		expect(status).to_be(StatusCode::OK)?;
+
+		// client credentials cannot change lobbies
+		let (status, _) =
+			request_with(&router, Method::POST, "/lobbies/foo", "foo").await?;
+		expect(status).to_be(StatusCode::UNAUTHORIZED)?;
+		let (status, _) =
+			request_with(&router, Method::POST, "/lobbies/foo", "admin")
+				.await?;
+		expect(status).to_be(StatusCode::CREATED)?;
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn no_admin() -> Result<()> {
+		let map = LobbyMap::default();
+		map.0.write().await.create_lobby("foo".into())?;
+		let router = api_router(map, None, None);
+		let (status, _) = request(&router, Method::GET, "/lobbies").await?;
+		expect(status).to_be(StatusCode::OK)?;
+		let (status, _) =
+			request(&router, Method::DELETE, "/lobbies/foo").await?;
+		expect(status).to_be(StatusCode::NOT_FOUND)?;
+		let (status, _) =
+			request(&router, Method::DELETE, "/lobbies/foo/clients/0").await?;
+		expect(status).to_be(StatusCode::NOT_FOUND)?;
+		Ok(())
+	}
+}
@@ -0,0 +1,177 @@
+use anyhow::Result;
+use axum::extract::ws;
+use hmac::Hmac;
+use hmac::Mac;
+use sha2::Sha256;
+use std::borrow::Cow;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Close code sent to sockets that fail authentication.
+pub const CLOSE_CODE_UNAUTHORIZED: u16 = 4001;
+/// How long a client has to send its credential after connecting.
+pub const DEFAULT_AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Validates the credential sent by a client as its first websocket message.
+pub trait AuthVerifier: 'static + Send + Sync {
+	/// Returns the authenticated subject, ie a user id
+	/// # Errors
+	/// If the credential is invalid
+	fn verify(&self, credential: &str) -> Result<String>;
+}
+
+/// Accepts clients that send the same shared secret as the server.
+/// The subject is always `anonymous`.
+pub struct SharedSecretVerifier {
+	secret: String,
+}
+
+impl SharedSecretVerifier {
+	pub fn new(secret: impl Into<String>) -> Self {
+		Self {
+			secret: secret.into(),
+		}
+	}
+}
+
+impl AuthVerifier for SharedSecretVerifier {
+	fn verify(&self, credential: &str) -> Result<String> {
+		// compare macs instead of strings, verify_slice is constant time
+		let expected = hmac_sha256(self.secret.as_bytes())
+			.chain_update(self.secret.as_bytes())
+			.finalize()
+			.into_bytes();
+		hmac_sha256(self.secret.as_bytes())
+			.chain_update(credential.as_bytes())
+			.verify_slice(&expected)
+			.map_err(|_| anyhow::anyhow!("invalid shared secret"))?;
+		Ok(String::from("anonymous"))
+	}
+}
+
+/// Tokens signed with a key shared between the token issuer and the server,
+/// verifiable offline.
+/// Tokens are formatted as `{subject}.{expires}.{signature}`, where expires
+/// is in seconds since the unix epoch and the signature is a hex encoded
+/// HMAC-SHA256 of `{subject}.{expires}`.
+pub struct HmacTokenVerifier {
+	key: Vec<u8>,
+}
+
+impl HmacTokenVerifier {
+	pub fn new(key: impl Into<Vec<u8>>) -> Self { Self { key: key.into() } }
+
+	/// Create a token for the subject that is valid for the given duration.
+	pub fn sign(&self, subject: &str, valid_for: Duration) -> Result<String> {
+		let expires = (SystemTime::now() + valid_for)
+			.duration_since(UNIX_EPOCH)?
+			.as_secs();
+		Ok(self.sign_with_expiry(subject, expires))
+	}
+
+	/// Create a token for the subject that expires at the given
+	/// seconds since the unix epoch.
+	pub fn sign_with_expiry(&self, subject: &str, expires: u64) -> String {
+		let claims = format!("{subject}.{expires}");
+		let signature = hmac_sha256(&self.key)
+			.chain_update(claims.as_bytes())
+			.finalize()
+			.into_bytes();
+		format!("{claims}.{}", hex::encode(signature))
+	}
+}
+
+impl AuthVerifier for HmacTokenVerifier {
+	fn verify(&self, credential: &str) -> Result<String> {
+		// split from the right, the subject may contain dots
+		let mut parts = credential.rsplitn(3, '.');
+		let (Some(signature), Some(expires), Some(subject)) =
+			(parts.next(), parts.next(), parts.next())
+		else {
+			anyhow::bail!("malformed token");
+		};
+		let signature = hex::decode(signature)?;
+		hmac_sha256(&self.key)
+			.chain_update(format!("{subject}.{expires}").as_bytes())
+			.verify_slice(&signature)
+			.map_err(|_| anyhow::anyhow!("invalid token signature"))?;
+
+		let expires = expires.parse::<u64>()?;
+		let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+		if now >= expires {
+			anyhow::bail!("token expired");
+		}
+		Ok(subject.to_string())
+	}
+}
+
+fn hmac_sha256(key: &[u8]) -> Hmac<Sha256> {
+	// hmac accepts keys of any length
+	Hmac::<Sha256>::new_from_slice(key).unwrap()
+}
+
+/// Wait for the first message, which must be a text credential,
+/// closing the socket with [`CLOSE_CODE_UNAUTHORIZED`] on failure.
+pub async fn authenticate(
+	socket: &mut ws::WebSocket,
+	verifier: &dyn AuthVerifier,
+	timeout: Duration,
+) -> Result<String> {
+	let result = match tokio::time::timeout(timeout, socket.recv()).await {
+		Ok(Some(Ok(ws::Message::Text(credential)))) => {
+			verifier.verify(&credential)
+		}
+		Ok(Some(Ok(_))) => Err(anyhow::anyhow!("expected text credential")),
+		Ok(Some(Err(err))) => Err(err.into()),
+		Ok(None) => Err(anyhow::anyhow!("socket closed before auth")),
+		Err(_) => Err(anyhow::anyhow!("auth timed out")),
+	};
+	if let Err(err) = &result {
+		socket
+			.send(ws::Message::Close(Some(ws::CloseFrame {
+				code: CLOSE_CODE_UNAUTHORIZED,
+				reason: Cow::Owned(err.to_string()),
+			})))
+			.await
+			.ok();
+	}
+	result
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use std::time::Duration;
+	use sweet::*;
+
+	#[test]
+	fn shared_secret() -> Result<()> {
+		let verifier = SharedSecretVerifier::new("foo");
+		expect(verifier.verify("foo")).to_be_ok()?;
+		expect(verifier.verify("bar"))
+			.to_be_err_str("invalid shared secret")?;
+		Ok(())
+	}
+
+	#[test]
+	fn hmac_token() -> Result<()> {
+		let verifier = HmacTokenVerifier::new("foo");
+		let token = verifier.sign("my.user", Duration::from_secs(60))?;
+		expect(verifier.verify(&token)?).to_be("my.user".to_string())?;
+
+		let other = HmacTokenVerifier::new("bar");
+		expect(other.verify(&token))
+			.to_be_err_str("invalid token signature")?;
+
+		let tampered = token.replacen("my.user", "admin", 1);
+		expect(verifier.verify(&tampered))
+			.to_be_err_str("invalid token signature")?;
+
+		let expired = verifier.sign_with_expiry("my.user", 0);
+		expect(verifier.verify(&expired)).to_be_err_str("token expired")?;
+		expect(verifier.verify("foo")).to_be_err_str("malformed token")?;
+		Ok(())
+	}
+}
@@ -7,6 +7,9 @@ pub struct Client {
 	pub socket: ws::WebSocket,
 	pub user_agent: String,
 	pub connect_info: ConnectInfo<SocketAddr>,
+	/// The subject returned by the [`AuthVerifier`](super::AuthVerifier),
+	/// if authentication is enabled.
+	pub subject: Option<String>,
 }
 
 impl Client {
@@ -24,8 +27,14 @@ impl Client {
 			socket,
 			user_agent,
 			connect_info,
+			subject: None,
 		}
 	}
+
+	pub fn with_subject(mut self, subject: String) -> Self {
+		self.subject = Some(subject);
+		self
+	}
 }
 
 
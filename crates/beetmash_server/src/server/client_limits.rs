@@ -0,0 +1,255 @@
+use anyhow::Result;
+use std::time::Instant;
+
+/// Close code sent to clients disconnected for exceeding
+/// their [`ClientLimits`].
+pub const CLOSE_CODE_LIMIT_EXCEEDED: u16 = 4004;
+
+/// Limits on frames received from each client, `None` is unlimited.
+/// Frames exceeding a limit are dropped and counted as violations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientLimits {
+	/// Maximum bytes in a single frame
+	pub max_frame_size: Option<usize>,
+	/// Frames per second, allowing bursts of up to a second
+	pub frames_per_second: Option<u32>,
+	/// Bytes per second, allowing bursts of up to a second.
+	/// Must be at least [`Self::max_frame_size`], otherwise the largest
+	/// frames would never fit, see [`Self::validate`].
+	pub bytes_per_second: Option<u64>,
+	/// Close the connection with [`CLOSE_CODE_LIMIT_EXCEEDED`]
+	/// on the first violation
+	pub disconnect: bool,
+}
+
+impl Default for ClientLimits {
+	fn default() -> Self {
+		Self {
+			max_frame_size: Some(1024 * 1024),
+			frames_per_second: None,
+			bytes_per_second: None,
+			disconnect: false,
+		}
+	}
+}
+
+impl ClientLimits {
+	/// Check that every frame allowed by [`Self::max_frame_size`]
+	/// can also pass the byte rate.
+	/// # Errors
+	/// If `bytes_per_second` is set and less than `max_frame_size`,
+	/// or `max_frame_size` is unlimited.
+	pub fn validate(&self) -> Result<()> {
+		match (self.max_frame_size, self.bytes_per_second) {
+			(_, None) => Ok(()),
+			(None, Some(_)) => anyhow::bail!(
+				"max_frame_size must be set if bytes_per_second is"
+			),
+			(Some(max), Some(rate)) if (rate as u128) < max as u128 => {
+				anyhow::bail!(
+					"bytes_per_second ({rate}) must be at least max_frame_size ({max})"
+				)
+			}
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Why a frame was rejected by a [`RateLimiter`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitViolation {
+	FrameSize { size: usize, max: usize },
+	FrameRate,
+	ByteRate,
+}
+
+impl std::fmt::Display for LimitViolation {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			LimitViolation::FrameSize { size, max } => {
+				write!(f, "frame of {size} bytes exceeds {max}")
+			}
+			LimitViolation::FrameRate => write!(f, "frame rate exceeded"),
+			LimitViolation::ByteRate => write!(f, "byte rate exceeded"),
+		}
+	}
+}
+
+/// Refills at `rate` per second up to a capacity of `rate`.
+#[derive(Debug, Clone)]
+struct TokenBucket {
+	rate: f64,
+	tokens: f64,
+	last: Instant,
+}
+
+impl TokenBucket {
+	fn new(rate: f64, now: Instant) -> Self {
+		Self {
+			rate,
+			tokens: rate,
+			last: now,
+		}
+	}
+
+	fn refill(&mut self, now: Instant) {
+		let elapsed = now.saturating_duration_since(self.last);
+		self.tokens =
+			(self.tokens + elapsed.as_secs_f64() * self.rate).min(self.rate);
+		self.last = now;
+	}
+}
+
+/// Enforces [`ClientLimits`] for a single client.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+	pub limits: ClientLimits,
+	frames: Option<TokenBucket>,
+	bytes: Option<TokenBucket>,
+	violations: usize,
+}
+
+impl RateLimiter {
+	pub fn new(limits: ClientLimits) -> Self {
+		Self::new_at(limits, Instant::now())
+	}
+
+	pub fn new_at(limits: ClientLimits, now: Instant) -> Self {
+		Self {
+			frames: limits
+				.frames_per_second
+				.map(|rate| TokenBucket::new(rate as f64, now)),
+			bytes: limits
+				.bytes_per_second
+				.map(|rate| TokenBucket::new(rate as f64, now)),
+			limits,
+			violations: 0,
+		}
+	}
+
+	/// Number of frames rejected so far
+	pub fn violations(&self) -> usize { self.violations }
+
+	/// Check a frame of this size received now, counting
+	/// rejected frames as violations.
+	/// Rejected frames do not use up the rate.
+	pub fn check(
+		&mut self,
+		size: usize,
+		now: Instant,
+	) -> Result<(), LimitViolation> {
+		let result = self.check_inner(size, now);
+		if result.is_err() {
+			self.violations += 1;
+		}
+		result
+	}
+
+	fn check_inner(
+		&mut self,
+		size: usize,
+		now: Instant,
+	) -> Result<(), LimitViolation> {
+		if let Some(max) = self.limits.max_frame_size {
+			if size > max {
+				return Err(LimitViolation::FrameSize { size, max });
+			}
+		}
+		for bucket in [&mut self.frames, &mut self.bytes].into_iter().flatten()
+		{
+			bucket.refill(now);
+		}
+		if let Some(frames) = &self.frames {
+			if frames.tokens < 1. {
+				return Err(LimitViolation::FrameRate);
+			}
+		}
+		if let Some(bytes) = &self.bytes {
+			if bytes.tokens < size as f64 {
+				return Err(LimitViolation::ByteRate);
+			}
+		}
+		if let Some(frames) = &mut self.frames {
+			frames.tokens -= 1.;
+		}
+		if let Some(bytes) = &mut self.bytes {
+			bytes.tokens -= size as f64;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use std::time::Duration;
+	use std::time::Instant;
+	use sweet::*;
+
+	#[test]
+	fn frame_size() -> Result<()> {
+		let mut limiter = RateLimiter::new(ClientLimits {
+			max_frame_size: Some(4),
+			..Default::default()
+		});
+		let now = Instant::now();
+		expect(limiter.check(4, now)).to_be_ok()?;
+		expect(limiter.check(5, now))
+			.to_be(Err(LimitViolation::FrameSize { size: 5, max: 4 }))?;
+		expect(limiter.violations()).to_be(1)?;
+		Ok(())
+	}
+
+	#[test]
+	fn rates() -> Result<()> {
+		let now = Instant::now();
+		let mut limiter = RateLimiter::new_at(
+			ClientLimits {
+				max_frame_size: None,
+				frames_per_second: Some(2),
+				bytes_per_second: Some(100),
+				disconnect: false,
+			},
+			now,
+		);
+		expect(limiter.check(10, now)).to_be_ok()?;
+		expect(limiter.check(10, now)).to_be_ok()?;
+		expect(limiter.check(10, now)).to_be(Err(LimitViolation::FrameRate))?;
+
+		let later = now + Duration::from_secs(1);
+		expect(limiter.check(90, later)).to_be_ok()?;
+		expect(limiter.check(20, later))
+			.to_be(Err(LimitViolation::ByteRate))?;
+		expect(limiter.violations()).to_be(2)?;
+		Ok(())
+	}
+
+	#[test]
+	fn validate() -> Result<()> {
+		expect(ClientLimits::default().validate()).to_be_ok()?;
+		let limits = ClientLimits {
+			max_frame_size: Some(100),
+			bytes_per_second: Some(100),
+			..Default::default()
+		};
+		expect(limits.validate()).to_be_ok()?;
+		expect(
+			ClientLimits {
+				bytes_per_second: Some(99),
+				..limits.clone()
+			}
+			.validate(),
+		)
+		.to_be_err()?;
+		expect(
+			ClientLimits {
+				max_frame_size: None,
+				..limits
+			}
+			.validate(),
+		)
+		.to_be_err()?;
+		Ok(())
+	}
+}
@@ -0,0 +1,419 @@
+use super::*;
+use anyhow::Result;
+use clap::ArgAction;
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// [`Server`] settings loadable from a toml file and command line options,
+/// used by the `beetmash_server` binary.
+/// Durations are in seconds and missing fields use the [`Server`] defaults.
+///
+/// ```toml
+/// address = "0.0.0.0:3000"
+/// assets_dir = "assets"
+/// log_level = "info"
+/// # enables the api routes that change lobbies
+/// admin_token = "my-secret"
+///
+/// [lobby]
+/// create_on_demand = false
+/// # 0 keeps lobbies forever
+/// lifetime = 60
+/// lobbies = ["my-lobby"]
+///
+/// [client]
+/// queue_capacity = 256
+/// overflow = "disconnect"
+/// # 0 disables pings or the idle timeout
+/// ping_interval = 20
+/// idle_timeout = 60
+///
+/// [limits]
+/// # 0 is unlimited
+/// max_frame_size = 1048576
+/// frames_per_second = 60
+/// disconnect = true
+///
+/// [tls]
+/// cert = "cert.pem"
+/// key = "key.pem"
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+	pub address: String,
+	pub assets_dir: Option<PathBuf>,
+	/// See [`Server::log_level`]
+	pub log_level: Option<String>,
+	/// Shared secret required by the admin api routes,
+	/// which are disabled if unset, see [`Server::admin`]
+	pub admin_token: Option<String>,
+	pub lobby: LobbyConfig,
+	pub client: ClientConfig,
+	pub limits: LimitsConfig,
+	pub tls: Option<TlsConfig>,
+}
+
+impl Default for ServerConfig {
+	fn default() -> Self {
+		Self {
+			address: DEFAULT_ADDRESS.to_string(),
+			assets_dir: None,
+			log_level: None,
+			admin_token: None,
+			lobby: LobbyConfig::default(),
+			client: ClientConfig::default(),
+			limits: LimitsConfig::default(),
+			tls: None,
+		}
+	}
+}
+
+/// See [`LobbyPolicy`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LobbyConfig {
+	pub create_on_demand: bool,
+	/// Seconds, 0 keeps lobbies forever
+	pub lifetime: u64,
+	/// Lobbies created on startup
+	pub lobbies: Vec<LobbyId>,
+}
+
+impl Default for LobbyConfig {
+	fn default() -> Self {
+		let policy = LobbyPolicy::default();
+		Self {
+			create_on_demand: policy.create_on_demand,
+			lifetime: secs(policy.lifetime),
+			lobbies: Vec::new(),
+		}
+	}
+}
+
+/// See [`ClientSettings`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+	pub queue_capacity: usize,
+	pub overflow: OverflowPolicy,
+	/// Seconds
+	pub send_timeout: u64,
+	/// Seconds, 0 disables pings
+	pub ping_interval: u64,
+	/// Seconds, 0 disables the idle timeout
+	pub idle_timeout: u64,
+}
+
+impl Default for ClientConfig {
+	fn default() -> Self {
+		let settings = ClientSettings::default();
+		Self {
+			queue_capacity: settings.queue_capacity,
+			overflow: settings.overflow,
+			send_timeout: settings.send_timeout.as_secs(),
+			ping_interval: secs(settings.ping_interval),
+			idle_timeout: secs(settings.idle_timeout),
+		}
+	}
+}
+
+/// See [`ClientLimits`], 0 is unlimited
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+	pub max_frame_size: usize,
+	pub frames_per_second: u32,
+	pub bytes_per_second: u64,
+	pub disconnect: bool,
+}
+
+impl Default for LimitsConfig {
+	fn default() -> Self {
+		let limits = ClientLimits::default();
+		Self {
+			max_frame_size: limits.max_frame_size.unwrap_or_default(),
+			frames_per_second: limits.frames_per_second.unwrap_or_default(),
+			bytes_per_second: limits.bytes_per_second.unwrap_or_default(),
+			disconnect: limits.disconnect,
+		}
+	}
+}
+
+fn secs(duration: Option<Duration>) -> u64 {
+	duration
+		.map(|duration| duration.as_secs())
+		.unwrap_or_default()
+}
+
+fn duration(secs: u64) -> Option<Duration> {
+	(secs > 0).then(|| Duration::from_secs(secs))
+}
+
+fn nonzero<T: Default + PartialEq>(value: T) -> Option<T> {
+	(value != T::default()).then_some(value)
+}
+
+impl ServerConfig {
+	pub fn from_toml(toml: &str) -> Result<Self> { Ok(toml::from_str(toml)?) }
+
+	pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+		let path = path.as_ref();
+		let toml = std::fs::read_to_string(path).map_err(|err| {
+			anyhow::anyhow!("failed to read {}: {err}", path.display())
+		})?;
+		Self::from_toml(&toml)
+	}
+
+	/// Command line options, each overriding the config file.
+	pub fn command() -> clap::Command {
+		clap::Command::new("beetmash_server")
+			.about("Run the beetmash relay server")
+			.arg(
+				clap::Arg::new("config")
+					.short('c')
+					.long("config")
+					.action(ArgAction::Set)
+					.help("Path to a toml config file"),
+			)
+			.arg(
+				clap::Arg::new("address")
+					.short('a')
+					.long("address")
+					.action(ArgAction::Set)
+					.help("The address to bind to, ie 0.0.0.0:3000"),
+			)
+			.arg(
+				clap::Arg::new("assets-dir")
+					.long("assets-dir")
+					.action(ArgAction::Set)
+					.help("Directory of static files to serve"),
+			)
+			.arg(
+				clap::Arg::new("log-level")
+					.long("log-level")
+					.action(ArgAction::Set)
+					.help("Tracing filter used if RUST_LOG is not set"),
+			)
+			.arg(
+				clap::Arg::new("admin-token")
+					.long("admin-token")
+					.action(ArgAction::Set)
+					.help("Shared secret enabling the admin api routes"),
+			)
+			.arg(
+				clap::Arg::new("lobby")
+					.short('l')
+					.long("lobby")
+					.action(ArgAction::Append)
+					.help("Create a lobby on startup, may be repeated"),
+			)
+			.arg(
+				clap::Arg::new("no-create-on-demand")
+					.long("no-create-on-demand")
+					.action(ArgAction::SetTrue)
+					.help("Only allow joining lobbies that already exist"),
+			)
+			.arg(
+				clap::Arg::new("max-frame-size")
+					.long("max-frame-size")
+					.value_parser(clap::value_parser!(usize))
+					.help("Maximum bytes in a client frame, 0 is unlimited"),
+			)
+			.arg(
+				clap::Arg::new("frames-per-second")
+					.long("frames-per-second")
+					.value_parser(clap::value_parser!(u32))
+					.help("Frames each client may send per second"),
+			)
+			.arg(
+				clap::Arg::new("bytes-per-second")
+					.long("bytes-per-second")
+					.value_parser(clap::value_parser!(u64))
+					.help("Bytes each client may send per second"),
+			)
+			.arg(
+				clap::Arg::new("tls-cert")
+					.long("tls-cert")
+					.requires("tls-key")
+					.action(ArgAction::Set)
+					.help("Path to a pem certificate chain"),
+			)
+			.arg(
+				clap::Arg::new("tls-key")
+					.long("tls-key")
+					.requires("tls-cert")
+					.action(ArgAction::Set)
+					.help("Path to a pem private key"),
+			)
+	}
+
+	/// Load the `--config` file if any, then apply the other options.
+	pub fn from_args(args: &clap::ArgMatches) -> Result<Self> {
+		let mut config = match args.get_one::<String>("config") {
+			Some(path) => Self::load(path)?,
+			None => Self::default(),
+		};
+		if let Some(address) = args.get_one::<String>("address") {
+			config.address = address.clone();
+		}
+		if let Some(assets_dir) = args.get_one::<String>("assets-dir") {
+			config.assets_dir = Some(assets_dir.into());
+		}
+		if let Some(log_level) = args.get_one::<String>("log-level") {
+			config.log_level = Some(log_level.clone());
+		}
+		if let Some(admin_token) = args.get_one::<String>("admin-token") {
+			config.admin_token = Some(admin_token.clone());
+		}
+		if let Some(lobbies) = args.get_many::<String>("lobby") {
+			config.lobby.lobbies.extend(lobbies.cloned());
+		}
+		if args.get_flag("no-create-on-demand") {
+			config.lobby.create_on_demand = false;
+		}
+		if let Some(size) = args.get_one::<usize>("max-frame-size") {
+			config.limits.max_frame_size = *size;
+		}
+		if let Some(rate) = args.get_one::<u32>("frames-per-second") {
+			config.limits.frames_per_second = *rate;
+		}
+		if let Some(rate) = args.get_one::<u64>("bytes-per-second") {
+			config.limits.bytes_per_second = *rate;
+		}
+		if let (Some(cert), Some(key)) = (
+			args.get_one::<String>("tls-cert"),
+			args.get_one::<String>("tls-key"),
+		) {
+			config.tls = Some(TlsConfig {
+				cert: cert.into(),
+				key: key.into(),
+			});
+		}
+		Ok(config)
+	}
+
+	/// Parse [`std::env::args`], see [`Self::command`].
+	pub fn from_cli_args() -> Result<Self> {
+		Self::from_args(&Self::command().get_matches())
+	}
+
+	pub fn into_server(self) -> Server {
+		let mut server = Server::new(self.address)
+			.with_lobby_policy(LobbyPolicy {
+				create_on_demand: self.lobby.create_on_demand,
+				lifetime: duration(self.lobby.lifetime),
+			})
+			.with_client_settings(ClientSettings {
+				queue_capacity: self.client.queue_capacity,
+				overflow: self.client.overflow,
+				send_timeout: Duration::from_secs(self.client.send_timeout),
+				ping_interval: duration(self.client.ping_interval),
+				idle_timeout: duration(self.client.idle_timeout),
+				limits: ClientLimits {
+					max_frame_size: nonzero(self.limits.max_frame_size),
+					frames_per_second: nonzero(self.limits.frames_per_second),
+					bytes_per_second: nonzero(self.limits.bytes_per_second),
+					disconnect: self.limits.disconnect,
+				},
+			});
+		if let Some(assets_dir) = self.assets_dir {
+			server = server.with_assets_dir(assets_dir);
+		}
+		if let Some(admin_token) = self.admin_token {
+			server = server.with_admin(SharedSecretVerifier::new(admin_token));
+		}
+		server.log_level = self.log_level;
+		server.lobbies = self.lobby.lobbies;
+		server.tls = self.tls;
+		server
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use std::time::Duration;
+	use sweet::*;
+
+	#[test]
+	fn toml() -> Result<()> {
+		let config = ServerConfig::from_toml(
+			r#"
+address = "127.0.0.1:8080"
+
+[lobby]
+create_on_demand = false
+lifetime = 0
+lobbies = ["foo"]
+
+[client]
+overflow = "disconnect"
+ping_interval = 0
+
+[limits]
+max_frame_size = 0
+frames_per_second = 30
+"#,
+		)?;
+		expect(config.client.queue_capacity).to_be(256)?;
+		let server = config.into_server();
+		expect(server.address.as_str()).to_be("127.0.0.1:8080")?;
+		expect(server.lobby_policy).to_be(LobbyPolicy {
+			create_on_demand: false,
+			lifetime: None,
+		})?;
+		expect(server.lobbies).to_be(vec!["foo".to_string()])?;
+		let settings = server.client_settings;
+		expect(settings.overflow).to_be(OverflowPolicy::Disconnect)?;
+		expect(settings.ping_interval).to_be_none()?;
+		expect(settings.idle_timeout).to_be(Some(Duration::from_secs(60)))?;
+		expect(settings.limits.max_frame_size).to_be_none()?;
+		expect(settings.limits.frames_per_second).to_be(Some(30))?;
+
+		expect(ServerConfig::from_toml("port = 3000")).to_be_err()?;
+		// defaults match the server
+		let server = ServerConfig::default().into_server();
+		expect(server.client_settings).to_be(ClientSettings::default())?;
+		expect(server.lobby_policy).to_be(LobbyPolicy::default())?;
+		Ok(())
+	}
+
+	#[test]
+	fn args() -> Result<()> {
+		let matches = ServerConfig::command().try_get_matches_from([
+			"beetmash_server",
+			"--address",
+			"127.0.0.1:8080",
+			"--lobby",
+			"foo",
+			"--lobby",
+			"bar",
+			"--no-create-on-demand",
+			"--frames-per-second",
+			"10",
+			"--admin-token",
+			"secret",
+		])?;
+		let config = ServerConfig::from_args(&matches)?;
+		expect(config.address.as_str()).to_be("127.0.0.1:8080")?;
+		expect(&config.lobby.lobbies)
+			.to_be(&vec!["foo".to_string(), "bar".to_string()])?;
+		expect(config.lobby.create_on_demand).to_be_false()?;
+		expect(config.limits.frames_per_second).to_be(10)?;
+		expect(config.admin_token.as_deref()).to_be(Some("secret"))?;
+		expect(config.into_server().admin.is_some()).to_be_true()?;
+
+		expect(
+			ServerConfig::command()
+				.try_get_matches_from(["beetmash_server", "--tls-cert", "a"])
+				.is_err(),
+		)
+		.to_be_true()?;
+		Ok(())
+	}
+}
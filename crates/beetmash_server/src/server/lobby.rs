@@ -1,52 +1,300 @@
 use super::*;
 use anyhow::Result;
-use futures::future::try_join_all;
+use beetmash_net::prelude::*;
+use forky::prelude::*;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
 
-pub type LobbyId = usize;
-pub type ClientId = usize;
+/// The lobby name, as in `/ws/{lobby}`.
+pub type LobbyId = String;
+/// Assigned by the lobby, shared with clients via [`RelayEnvelope`].
+pub use beetmash_net::prelude::ClientId;
 
 pub type Lobby = Arc<RwLock<LobbyInner>>;
 
-#[derive(Default)]
 pub struct LobbyInner {
 	client_id_incr: ClientId,
 	clients: HashMap<ClientId, LobbyClient>,
+	/// How long the lobby is kept once empty, or forever if `None`.
+	pub lifetime: Option<Duration>,
+	/// When the last client left, or when the lobby was created.
+	empty_since: Option<Instant>,
+	/// If set, client messages are sent to the host app
+	/// instead of the other clients.
+	host: Option<LobbyHost>,
+	host_task: Option<JoinHandle<()>>,
+	/// Clients that joined a hosted lobby and are not sent host
+	/// broadcasts until their [`HostFrame::Snapshot`] arrives.
+	awaiting_snapshot: HashSet<ClientId>,
+	pub client_settings: ClientSettings,
+	pub stats: LobbyStats,
+	/// Shared with the other lobbies, see [`LobbyMapInner::metrics`]
+	pub metrics: Arc<ServerMetrics>,
+}
+
+impl Default for LobbyInner {
+	fn default() -> Self { Self::new(None) }
+}
+
+impl Drop for LobbyInner {
+	fn drop(&mut self) { self.close(); }
 }
 
 
 impl LobbyInner {
+	pub fn new(lifetime: Option<Duration>) -> Self {
+		Self {
+			client_id_incr: 0,
+			clients: HashMap::default(),
+			lifetime,
+			empty_since: Some(Instant::now()),
+			host: None,
+			host_task: None,
+			awaiting_snapshot: HashSet::default(),
+			client_settings: ClientSettings::default(),
+			stats: LobbyStats::default(),
+			metrics: Arc::default(),
+		}
+	}
+
+	pub fn num_clients(&self) -> usize { self.clients.len() }
+
+	/// Info for each client, ordered by id
+	pub fn client_infos(&self) -> Vec<ClientInfo> {
+		let mut infos = self
+			.clients
+			.values()
+			.map(|client| client.info())
+			.collect::<Vec<_>>();
+		infos.sort_by_key(|info| info.id);
+		infos
+	}
+
+	/// Send a close frame to every client, then [`Self::close`].
+	/// Returns the client writer tasks, which end once
+	/// the close frames are flushed.
+	pub fn close_with(
+		&mut self,
+		code: u16,
+		reason: &str,
+	) -> Vec<JoinHandle<()>> {
+		let tasks = self
+			.clients
+			.values_mut()
+			.filter_map(|client| {
+				client.close(code, reason).ok();
+				self.metrics
+					.record_disconnect(DisconnectReason::from_close_code(code));
+				client.take_write_task()
+			})
+			.collect();
+		self.close();
+		tasks
+	}
+
+	/// Close the connection to the client, returning whether it existed.
+	pub fn kick(&mut self, client_id: ClientId) -> bool {
+		if let Some(client) = self.clients.get_mut(&client_id) {
+			client
+				.close(CLOSE_CODE_KICKED, "kicked")
+				.ok_or(|e| log::error!("{e}"));
+			self.remove_client(client_id, DisconnectReason::Kicked).ok();
+			log::info!("Kicked client: {client_id}");
+			true
+		} else {
+			false
+		}
+	}
+
+	/// Whether the lobby has been empty for longer than its lifetime
+	pub fn is_expired(&self, now: Instant) -> bool {
+		match (self.lifetime, self.empty_since) {
+			(Some(lifetime), Some(empty_since)) => {
+				now.saturating_duration_since(empty_since) >= lifetime
+			}
+			_ => false,
+		}
+	}
+
+	/// Disconnect all clients and stop the host, if any.
+	pub fn close(&mut self) {
+		self.clients.clear();
+		self.empty_since = Some(Instant::now());
+		if let Some(task) = self.host_task.take() {
+			task.abort();
+		}
+		self.host = None;
+	}
+
 	fn next_id(&mut self) -> ClientId {
 		let id = self.client_id_incr;
 		self.client_id_incr += 1;
 		id
 	}
 
+	/// Add the client and send it its id, see [`RelayEnvelope::joined`].
 	pub fn push_client(&mut self, self_arc: Lobby, client: Client) {
 		let id = self.next_id();
-		let lobby_client = LobbyClient::new(self_arc, client, id);
+		let lobby_client = LobbyClient::new(
+			self_arc,
+			client,
+			id,
+			&self.client_settings,
+			self.metrics.clone(),
+		);
 		self.clients.insert(id, lobby_client);
+		self.empty_since = None;
+		if let Some(bytes) = RelayEnvelope::joined(id)
+			.to_bytes()
+			.ok_or(|e| log::error!("{e}"))
+		{
+			self.send_where(bytes, |client_id| client_id == id);
+		}
+		if let Some(host) = &self.host {
+			if host
+				.request_snapshot(id)
+				.ok_or(|e| log::error!("{e}"))
+				.is_some()
+			{
+				self.awaiting_snapshot.insert(id);
+			}
+		}
 	}
 
-	pub async fn handle_message(
+	pub fn has_host(&self) -> bool { self.host.is_some() }
+
+	/// Run a [`LobbyHost`] for this lobby,
+	/// broadcasting its messages to all clients.
+	pub fn start_host(&mut self, self_arc: Lobby, settings: HostSettings) {
+		let (host, recv) = LobbyHost::new(settings);
+		let task = tokio::spawn(async move {
+			while let Ok(frame) = recv.recv_async().await {
+				self_arc.write().await.handle_host_frame(frame);
+			}
+		});
+		self.host = Some(host);
+		self.host_task = Some(task);
+	}
+
+	/// Broadcast to every client that has received its snapshot,
+	/// or send a snapshot to the client that requested it.
+	fn handle_host_frame(&mut self, frame: HostFrame) {
+		let (messages, target) = match frame {
+			HostFrame::Broadcast(messages) => (messages, None),
+			HostFrame::Snapshot(client_id, messages) => {
+				self.awaiting_snapshot.remove(&client_id);
+				if messages.is_empty() {
+					return;
+				}
+				(messages, Some(client_id))
+			}
+		};
+		let Some(bytes) =
+			Message::vec_into_bytes(&messages).ok_or(|e| log::error!("{e}"))
+		else {
+			return;
+		};
+		match target {
+			Some(client_id) => self.send_where(bytes, |id| id == client_id),
+			None => {
+				let awaiting = self.awaiting_snapshot.clone();
+				self.send_where(bytes, |id| !awaiting.contains(&id));
+			}
+		}
+	}
+
+	/// Send to every client
+	pub fn broadcast(&mut self, msg: Vec<u8>) {
+		self.send_where(msg, |_| true);
+	}
+
+	/// Queue the frame for each matching client without waiting.
+	/// Clients that cannot keep up are disconnected
+	/// without affecting the others.
+	pub fn send_where(
 		&mut self,
-		client_id: ClientId,
 		msg: Vec<u8>,
-	) -> Result<()> {
-		let futs = self
+		filter: impl Fn(ClientId) -> bool,
+	) {
+		let stats = &mut self.stats;
+		let metrics = &self.metrics;
+		let failed = self
 			.clients
 			.iter_mut()
-			.filter(|(id, _)| **id != client_id)
-			.map(|(_, client)| client.send(msg.clone()));
+			.filter(|(id, _)| filter(**id))
+			.filter_map(|(id, client)| match client.send(msg.clone()) {
+				Ok(()) => {
+					stats.frames_sent += 1;
+					stats.bytes_sent += msg.len() as u64;
+					None
+				}
+				Err(err) => {
+					log::info!("{err}");
+					metrics.record_send_failure();
+					Some(*id)
+				}
+			})
+			.collect::<Vec<_>>();
+		for client_id in failed {
+			self.disconnect(client_id, CLOSE_CODE_TOO_SLOW, "too slow");
+		}
+	}
 
-		try_join_all(futs).await?;
+	/// Discard frames queued for the client, close the connection
+	/// and remove it from the lobby.
+	pub fn disconnect(&mut self, client_id: ClientId, code: u16, reason: &str) {
+		if let Some(client) = self.clients.get_mut(&client_id) {
+			client.disconnect(code, reason);
+		}
+		self.remove_client(client_id, DisconnectReason::from_close_code(code))
+			.ok();
+	}
+
+	/// Send the frame to the host if there is one, otherwise to every
+	/// other client or the targets of a [`RelayEnvelope`].
+	/// # Errors
+	/// If the frame is an invalid envelope or the host rejects it
+	pub fn handle_message(
+		&mut self,
+		client_id: ClientId,
+		msg: Vec<u8>,
+	) -> Result<()> {
+		self.stats.frames_received += 1;
+		self.stats.bytes_received += msg.len() as u64;
+		if let Some(mut envelope) = RelayEnvelope::from_bytes(&msg)? {
+			if let Some(host) = &self.host {
+				return host.send_bytes(client_id, &envelope.payload);
+			}
+			envelope.sender = Some(client_id);
+			let msg = envelope.to_bytes()?;
+			self.send_where(msg, |id| envelope.target.includes(id, client_id));
+			return Ok(());
+		}
+		if let Some(host) = &self.host {
+			return host.send_bytes(client_id, &msg);
+		}
+		self.send_where(msg, |id| id != client_id);
 		Ok(())
 	}
 
-	pub fn remove_client(&mut self, client_id: ClientId) -> Result<()> {
-		self.clients.remove(&client_id);
+	/// Remove the client, recording why if it was in the lobby.
+	pub fn remove_client(
+		&mut self,
+		client_id: ClientId,
+		reason: DisconnectReason,
+	) -> Result<()> {
+		if self.clients.remove(&client_id).is_some() {
+			self.metrics.record_disconnect(reason);
+		}
+		self.awaiting_snapshot.remove(&client_id);
+		if self.clients.is_empty() {
+			self.empty_since = Some(Instant::now());
+		}
 		Ok(())
 	}
 }
@@ -1,57 +1,405 @@
 use super::*;
 use anyhow::Result;
 use axum::extract::ws;
+use flume::Receiver;
+use flume::Sender;
+use flume::TrySendError;
 use forky::prelude::*;
 use futures::SinkExt;
 use futures_util::stream::SplitSink;
+use futures_util::stream::SplitStream;
 use futures_util::StreamExt;
+use serde::Deserialize;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use tokio::task::JoinHandle;
 
 pub type AxumWsEvent = axum::extract::ws::Message;
 
+/// Close code sent to clients removed by the server, ie kicked
+/// or their lobby was closed.
+pub const CLOSE_CODE_KICKED: u16 = 4002;
+/// Close code sent to clients whose outgoing queue overflowed
+/// with [`OverflowPolicy::Disconnect`].
+pub const CLOSE_CODE_TOO_SLOW: u16 = 4003;
+/// Close code sent to clients that sent nothing, not even a pong,
+/// for longer than [`ClientSettings::idle_timeout`].
+pub const CLOSE_CODE_IDLE_TIMEOUT: u16 = 4005;
 
-pub struct LobbyClient {
-	send: SplitSink<ws::WebSocket, ws::Message>,
-	#[allow(dead_code)]
-	recv_task: tokio::task::JoinHandle<()>,
+/// What to do when a client's outgoing queue is full.
+#[derive(
+	Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+	/// Drop the oldest queued frame to make room
+	#[default]
+	DropOldest,
+	/// Disconnect the client
+	Disconnect,
 }
 
-impl LobbyClient {
-	pub fn new(lobby: Lobby, client: Client, client_id: ClientId) -> Self {
-		let (send, mut recv) = client.socket.split();
-		let recv_task = tokio::spawn(async move {
-			while let Some(Ok(msg)) = recv.next().await {
-				if let Some(msg) =
-					filter_payload(msg).ok_or(|e| log::error!("{e}")).flatten()
-				{
-					lobby
-						.write()
-						.await
-						.handle_message(client_id, msg)
-						.await
-						.ok_or(|e| log::error!("{e}"));
+/// Per client settings, see [`LobbyClient`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientSettings {
+	/// Maximum frames queued for sending to the client
+	pub queue_capacity: usize,
+	pub overflow: OverflowPolicy,
+	/// A client that takes longer than this to accept
+	/// a frame is disconnected.
+	pub send_timeout: Duration,
+	/// Limits on frames received from the client
+	pub limits: ClientLimits,
+	/// How often to ping the client, browsers and most clients
+	/// respond with a pong automatically.
+	pub ping_interval: Option<Duration>,
+	/// Disconnect clients that send nothing for this long,
+	/// should be longer than the ping interval.
+	pub idle_timeout: Option<Duration>,
+}
+
+impl Default for ClientSettings {
+	fn default() -> Self {
+		Self {
+			queue_capacity: 256,
+			overflow: OverflowPolicy::default(),
+			send_timeout: Duration::from_secs(5),
+			limits: ClientLimits::default(),
+			ping_interval: Some(Duration::from_secs(20)),
+			idle_timeout: Some(Duration::from_secs(60)),
+		}
+	}
+}
+
+/// Information about a connected client, see the lobby api.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClientInfo {
+	pub id: ClientId,
+	pub user_agent: String,
+	pub address: String,
+	/// The authenticated subject, if auth is enabled
+	pub subject: Option<String>,
+	/// Seconds since the unix epoch
+	pub connected_since: u64,
+	/// Frames dropped because the outgoing queue was full
+	pub dropped: usize,
+	/// Frames rejected for exceeding the [`ClientLimits`]
+	pub violations: usize,
+}
+
+/// Bounded queue of frames to send to a client,
+/// applying the [`OverflowPolicy`] when full.
+pub struct ClientQueue {
+	overflow: OverflowPolicy,
+	send: Sender<AxumWsEvent>,
+	/// Used to drop the oldest frame on overflow
+	recv: Receiver<AxumWsEvent>,
+	/// Frames dropped due to overflow
+	dropped: usize,
+}
+
+impl ClientQueue {
+	/// Create the queue and the receiver for the writer
+	pub fn new(settings: &ClientSettings) -> (Self, Receiver<AxumWsEvent>) {
+		let (send, recv) = flume::bounded(settings.queue_capacity);
+		(
+			Self {
+				overflow: settings.overflow,
+				send,
+				recv: recv.clone(),
+				dropped: 0,
+			},
+			recv,
+		)
+	}
+
+	/// Frames dropped because the queue was full
+	pub fn dropped(&self) -> usize { self.dropped }
+
+	/// Discard all queued frames
+	pub fn clear(&mut self) { self.recv.drain(); }
+
+	/// Queue a frame without waiting.
+	/// # Errors
+	/// If the receiver was dropped, or the queue is full
+	/// with [`OverflowPolicy::Disconnect`].
+	pub fn push(&mut self, msg: AxumWsEvent) -> Result<()> {
+		match self.send.try_send(msg) {
+			Ok(()) => Ok(()),
+			Err(TrySendError::Full(msg)) => match self.overflow {
+				OverflowPolicy::DropOldest => {
+					self.recv.try_recv().ok();
+					self.dropped += 1;
+					// another frame may have been queued in between,
+					// in which case this one is dropped instead
+					self.send.try_send(msg).ok();
+					Ok(())
 				}
+				OverflowPolicy::Disconnect => anyhow::bail!("queue full"),
+			},
+			Err(TrySendError::Disconnected(_)) => {
+				anyhow::bail!("disconnected")
 			}
-			lobby
-				.write()
-				.await
-				.remove_client(client_id)
-				.ok_or(|e| log::error!("{e}"));
-			log::info!("<<< {}: Disconnected", client_id);
-		});
+		}
+	}
+}
+
+/// A client in a lobby. Frames are queued by [`Self::send`] without
+/// waiting, and written to the socket by a task per client,
+/// so a slow client cannot hold up the rest of the lobby.
+/// Received frames are checked against the [`ClientLimits`].
+pub struct LobbyClient {
+	pub info: ClientInfo,
+	queue: ClientQueue,
+	violations: Arc<AtomicUsize>,
+	recv_task: JoinHandle<()>,
+	write_task: Option<JoinHandle<()>>,
+}
+
+impl LobbyClient {
+	pub fn new(
+		lobby: Lobby,
+		client: Client,
+		client_id: ClientId,
+		settings: &ClientSettings,
+		metrics: Arc<ServerMetrics>,
+	) -> Self {
+		let info = ClientInfo {
+			id: client_id,
+			user_agent: client.user_agent,
+			address: client.connect_info.0.to_string(),
+			subject: client.subject,
+			connected_since: SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.map(|since| since.as_secs())
+				.unwrap_or_default(),
+			dropped: 0,
+			violations: 0,
+		};
+		let (send, recv) = client.socket.split();
+		let (queue, queue_recv) = ClientQueue::new(settings);
+
+		let write_task = tokio::spawn(write_queue(
+			lobby.clone(),
+			client_id,
+			send,
+			queue_recv,
+			settings.clone(),
+			metrics.clone(),
+		));
 
-		Self { send, recv_task }
+		let violations = Arc::new(AtomicUsize::new(0));
+		let recv_task = tokio::spawn(read_socket(
+			lobby,
+			client_id,
+			recv,
+			settings.clone(),
+			violations.clone(),
+			metrics,
+		));
+
+		Self {
+			info,
+			queue,
+			violations,
+			recv_task,
+			write_task: Some(write_task),
+		}
 	}
 
-	pub async fn send(&mut self, msg: Vec<u8>) -> Result<()> {
-		self.send.send(AxumWsEvent::Binary(msg)).await?;
-		Ok(())
+	/// Frames dropped because the queue was full
+	pub fn dropped(&self) -> usize { self.queue.dropped() }
+
+	/// Frames rejected for exceeding the [`ClientLimits`]
+	pub fn violations(&self) -> usize {
+		self.violations.load(Ordering::Relaxed)
+	}
+
+	/// The client info with up to date counters
+	pub fn info(&self) -> ClientInfo {
+		ClientInfo {
+			dropped: self.dropped(),
+			violations: self.violations(),
+			..self.info.clone()
+		}
+	}
+
+	/// Queue a close frame, the socket is closed once the queue
+	/// is flushed and the client is dropped.
+	pub fn close(&mut self, code: u16, reason: &str) -> Result<()> {
+		self.push(AxumWsEvent::Close(Some(ws::CloseFrame {
+			code,
+			reason: Cow::Owned(reason.to_string()),
+		})))
+	}
+
+	/// Discard queued frames and queue a close frame instead,
+	/// used when the client cannot keep up.
+	pub fn disconnect(&mut self, code: u16, reason: &str) {
+		self.queue.clear();
+		self.close(code, reason).ok();
+	}
+
+	/// The task writing queued frames to the socket, which ends once
+	/// the queue is flushed after a close frame or the client is dropped.
+	pub fn take_write_task(&mut self) -> Option<JoinHandle<()>> {
+		self.write_task.take()
+	}
+
+	/// Queue a binary frame without waiting, see [`ClientQueue::push`].
+	pub fn send(&mut self, msg: Vec<u8>) -> Result<()> {
+		self.push(AxumWsEvent::Binary(msg))
+	}
+
+	fn push(&mut self, msg: AxumWsEvent) -> Result<()> {
+		self.queue
+			.push(msg)
+			.map_err(|err| anyhow::anyhow!("{}: {err}", self.info.id))
 	}
 }
 
 impl Drop for LobbyClient {
+	// the writer task flushes the queue and ends
+	// once the queue sender is dropped
 	fn drop(&mut self) { self.recv_task.abort(); }
 }
 
+/// Handle frames received from the client until the socket closes,
+/// disconnecting the client if it exceeds its [`ClientLimits`]
+/// or is idle for longer than [`ClientSettings::idle_timeout`].
+async fn read_socket(
+	lobby: Lobby,
+	client_id: ClientId,
+	mut recv: SplitStream<ws::WebSocket>,
+	settings: ClientSettings,
+	violations: Arc<AtomicUsize>,
+	metrics: Arc<ServerMetrics>,
+) {
+	let mut limiter = RateLimiter::new(settings.limits);
+	loop {
+		let next = recv.next();
+		let frame = match settings.idle_timeout {
+			Some(timeout) => tokio::time::timeout(timeout, next).await,
+			None => Ok(next.await),
+		};
+		let msg = match frame {
+			Ok(Some(Ok(msg))) => msg,
+			Ok(_) => break,
+			Err(_) => {
+				log::info!("{client_id}: idle timeout");
+				lobby.write().await.disconnect(
+					client_id,
+					CLOSE_CODE_IDLE_TIMEOUT,
+					"idle timeout",
+				);
+				return;
+			}
+		};
+		let Some(msg) =
+			filter_payload(msg).ok_or(|e| log::error!("{e}")).flatten()
+		else {
+			continue;
+		};
+		if let Err(violation) = limiter.check(msg.len(), Instant::now()) {
+			violations.store(limiter.violations(), Ordering::Relaxed);
+			metrics.record_violation();
+			log::info!("{client_id}: {violation}");
+			if limiter.limits.disconnect {
+				lobby.write().await.disconnect(
+					client_id,
+					CLOSE_CODE_LIMIT_EXCEEDED,
+					&violation.to_string(),
+				);
+				return;
+			}
+			continue;
+		}
+		lobby
+			.write()
+			.await
+			.handle_message(client_id, msg)
+			.ok_or(|e| log::error!("{e}"));
+	}
+	lobby
+		.write()
+		.await
+		.remove_client(client_id, DisconnectReason::Closed)
+		.ok_or(|e| log::error!("{e}"));
+	log::info!("<<< {}: Disconnected", client_id);
+}
+
+/// Write queued frames to the socket, and a ping every
+/// [`ClientSettings::ping_interval`], removing the client
+/// from the lobby if a write fails or times out.
+async fn write_queue(
+	lobby: Lobby,
+	client_id: ClientId,
+	mut send: SplitSink<ws::WebSocket, ws::Message>,
+	queue: Receiver<AxumWsEvent>,
+	settings: ClientSettings,
+	metrics: Arc<ServerMetrics>,
+) {
+	let mut ping = settings.ping_interval.map(|interval| {
+		tokio::time::interval_at(
+			tokio::time::Instant::now() + interval,
+			interval,
+		)
+	});
+	loop {
+		let msg = tokio::select! {
+			msg = queue.recv_async() => match msg {
+				Ok(msg) => msg,
+				Err(_) => break,
+			},
+			_ = tick(&mut ping) => AxumWsEvent::Ping(Vec::new()),
+		};
+		let is_close = matches!(msg, AxumWsEvent::Close(_));
+		match tokio::time::timeout(settings.send_timeout, send.send(msg)).await
+		{
+			Ok(Ok(())) if is_close => break,
+			Ok(Ok(())) => {}
+			Ok(Err(err)) => {
+				log::info!("{client_id}: send failed: {err}");
+				metrics.record_send_failure();
+				lobby
+					.write()
+					.await
+					.remove_client(client_id, DisconnectReason::SendFailed)
+					.ok();
+				break;
+			}
+			Err(_) => {
+				log::info!("{client_id}: send timed out");
+				metrics.record_send_failure();
+				lobby
+					.write()
+					.await
+					.remove_client(client_id, DisconnectReason::SendFailed)
+					.ok();
+				break;
+			}
+		}
+	}
+	send.close().await.ok();
+}
+
+/// Wait for the next tick, or forever if there is no interval
+async fn tick(interval: &mut Option<tokio::time::Interval>) {
+	match interval {
+		Some(interval) => {
+			interval.tick().await;
+		}
+		None => std::future::pending().await,
+	}
+}
+
 fn filter_payload(msg: AxumWsEvent) -> Result<Option<Vec<u8>>> {
 	match msg {
 		// AxumWsEvent::Text(txt) => Ok(Some(Message::from_string(&txt)?)),
@@ -59,3 +407,41 @@ fn filter_payload(msg: AxumWsEvent) -> Result<Option<Vec<u8>>> {
 		_ => Ok(None),
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use sweet::*;
+
+	fn frame(value: u8) -> AxumWsEvent { AxumWsEvent::Binary(vec![value]) }
+
+	#[test]
+	fn drop_oldest() -> Result<()> {
+		let (mut queue, recv) = ClientQueue::new(&ClientSettings {
+			queue_capacity: 2,
+			..Default::default()
+		});
+		for value in 0..4 {
+			queue.push(frame(value))?;
+		}
+		expect(queue.dropped()).to_be(2)?;
+		expect(recv.drain().collect::<Vec<_>>())
+			.to_be(vec![frame(2), frame(3)])?;
+		Ok(())
+	}
+
+	#[test]
+	fn disconnect() -> Result<()> {
+		let (mut queue, recv) = ClientQueue::new(&ClientSettings {
+			queue_capacity: 1,
+			overflow: OverflowPolicy::Disconnect,
+			..Default::default()
+		});
+		queue.push(frame(0))?;
+		expect(queue.push(frame(1))).to_be_err_str("queue full")?;
+		queue.clear();
+		expect(recv.is_empty()).to_be_true()?;
+		Ok(())
+	}
+}
@@ -0,0 +1,357 @@
+use anyhow::Result;
+use beetmash_net::prelude::*;
+use bevy::prelude::*;
+use bevy::time::TimePlugin;
+use flume::Receiver;
+use flume::Sender;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How often the host app is updated.
+pub const DEFAULT_HOST_TICK: Duration = Duration::from_micros(16_667);
+
+/// Builds the authoritative app for a lobby, ie adding simulation systems
+/// and replication registrations matching the clients.
+/// Called on the host thread, so the app does not need to be [`Send`].
+pub type HostAppFactory = Arc<dyn Fn() -> App + Send + Sync>;
+
+/// Settings for running a [`LobbyHost`] in each lobby,
+/// see [`Server::with_host`](super::Server::with_host).
+#[derive(Clone)]
+pub struct HostSettings {
+	pub factory: HostAppFactory,
+	pub tick: Duration,
+}
+
+impl HostSettings {
+	pub fn new(factory: impl 'static + Fn() -> App + Send + Sync) -> Self {
+		Self {
+			factory: Arc::new(factory),
+			tick: DEFAULT_HOST_TICK,
+		}
+	}
+
+	pub fn with_tick(mut self, tick: Duration) -> Self {
+		self.tick = tick;
+		self
+	}
+}
+
+/// Sent by the host thread in the order produced,
+/// see [`LobbyHost::new`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostFrame {
+	/// Messages for every client
+	Broadcast(Vec<Message>),
+	/// A [`snapshot_messages`] requested for a client that just joined,
+	/// covering every [`HostFrame::Broadcast`] sent before it.
+	Snapshot(ClientId, Vec<Message>),
+}
+
+/// A headless Bevy app acting as the authoritative peer of a lobby.
+/// It runs on its own thread, receiving decoded client messages and
+/// sending its outgoing messages to be broadcast to all clients.
+/// Each message is received with the id of the client that sent it,
+/// so entities spawned by different clients are mapped separately.
+///
+/// [`TimePlugin`] and [`ReplicatePlugin`] are added if the factory
+/// did not add them.
+pub struct LobbyHost {
+	send: Sender<(ClientId, Vec<Message>)>,
+	snapshots: Sender<ClientId>,
+	exit: Arc<AtomicBool>,
+}
+
+impl LobbyHost {
+	/// Start the host thread, returning the receiver
+	/// for frames sent by the host app.
+	pub fn new(settings: HostSettings) -> (Self, Receiver<HostFrame>) {
+		let (send, host_recv) = flume::unbounded();
+		let (host_send, recv) = flume::unbounded();
+		let (snapshots, snapshot_requests) = flume::unbounded();
+		let exit = Arc::new(AtomicBool::new(false));
+		let exit2 = exit.clone();
+		std::thread::spawn(move || {
+			let transport = HostTransport {
+				send: host_send.clone(),
+				recv: host_recv,
+			};
+			let mut app = build_app(&settings, transport);
+			while !exit2.load(Ordering::Relaxed) {
+				let start = Instant::now();
+				app.update();
+				for client_id in snapshot_requests.try_iter() {
+					send_snapshot(app.world_mut(), &host_send, client_id);
+				}
+				std::thread::sleep(
+					settings.tick.saturating_sub(start.elapsed()),
+				);
+			}
+		});
+		(
+			Self {
+				send,
+				snapshots,
+				exit,
+			},
+			recv,
+		)
+	}
+
+	/// Decode bytes received from a client and send them to the host app.
+	/// # Errors
+	/// If the bytes are not valid messages or the host thread has exited.
+	pub fn send_bytes(&self, client_id: ClientId, bytes: &[u8]) -> Result<()> {
+		let messages = Message::vec_from_bytes(bytes)?;
+		self.send.send((client_id, messages))?;
+		Ok(())
+	}
+
+	/// Request a [`HostFrame::Snapshot`] for the client,
+	/// sent after the next update.
+	/// # Errors
+	/// If the host thread has exited.
+	pub fn request_snapshot(&self, client_id: ClientId) -> Result<()> {
+		self.snapshots.send(client_id)?;
+		Ok(())
+	}
+}
+
+impl Drop for LobbyHost {
+	// the thread is not joined, this may be called from an async
+	// task holding the lobby lock. It exits after the current update.
+	fn drop(&mut self) { self.exit.store(true, Ordering::Relaxed); }
+}
+
+/// Sends the host app's outgoing messages as [`HostFrame::Broadcast`].
+struct HostTransport {
+	send: Sender<HostFrame>,
+	recv: Receiver<(ClientId, Vec<Message>)>,
+}
+
+impl Transport for HostTransport {
+	fn send(&mut self, messages: &Vec<Message>) -> Result<()> {
+		self.send.send(HostFrame::Broadcast(messages.clone()))?;
+		Ok(())
+	}
+
+	fn recv(&mut self) -> Result<Vec<Message>> {
+		Ok(self
+			.recv_with_sender()?
+			.into_iter()
+			.flat_map(|(_, messages)| messages)
+			.collect())
+	}
+
+	fn recv_with_sender(&mut self) -> Result<Vec<SenderMessages>> {
+		Ok(self
+			.recv
+			.try_recv_all()?
+			.into_iter()
+			.map(|(client_id, messages)| (Some(client_id), messages))
+			.collect())
+	}
+}
+
+/// Flush messages not yet sent by the transport so they are not
+/// received after the snapshot, then send the snapshot.
+fn send_snapshot(
+	world: &mut World,
+	send: &Sender<HostFrame>,
+	client_id: ClientId,
+) {
+	let mut pending = world
+		.resource_mut::<MessageOutgoing>()
+		.drain(..)
+		.collect::<Vec<_>>();
+	if !pending.is_empty() {
+		if let Some(peer) = world.get_resource::<PeerId>().copied() {
+			if let Some(mut sequence) =
+				world.get_resource_mut::<MessageSequence>()
+			{
+				pending.insert(0, sequence.next_marker(peer));
+			}
+		}
+		send.send(HostFrame::Broadcast(pending)).ok();
+	}
+	send.send(HostFrame::Snapshot(client_id, snapshot_messages(world)))
+		.ok();
+}
+
+fn build_app(settings: &HostSettings, transport: HostTransport) -> App {
+	let mut app = (settings.factory)();
+	if !app.is_plugin_added::<TimePlugin>() {
+		app.add_plugins(TimePlugin);
+	}
+	if !app.is_plugin_added::<ReplicatePlugin>() {
+		app.add_plugins(ReplicatePlugin);
+	}
+	app.add_transport_with_duration(transport, settings.tick);
+	app.finish();
+	app.cleanup();
+	app
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use beetmash_net::prelude::*;
+	use bevy::prelude::*;
+	use serde::Deserialize;
+	use serde::Serialize;
+	use std::time::Duration;
+	use sweet::*;
+
+	#[derive(Debug, Clone, PartialEq, Event, Serialize, Deserialize)]
+	struct Count(u32);
+
+	fn increment(mut events: ResMut<Events<Count>>) {
+		let next = events
+			.iter_current_update_events()
+			.map(|count| Count(count.0 + 1))
+			.collect::<Vec<_>>();
+		events.extend(next);
+	}
+
+	#[derive(Debug, Clone, PartialEq, Component, Serialize, Deserialize)]
+	struct Score(u32);
+
+	#[test]
+	fn works() -> Result<()> {
+		let (host, recv) = LobbyHost::new(
+			HostSettings::new(|| {
+				let mut app = App::new();
+				app.add_event::<Count>()
+					.replicate_event::<Count>()
+					.add_systems(Update, increment.after(MessageIncomingSet));
+				app
+			})
+			.with_tick(Duration::from_millis(1)),
+		);
+
+		host.send_bytes(
+			0,
+			&Message::vec_into_bytes(&vec![Message::SendEvent {
+				reg_id: RegistrationId::new_with(0),
+				payload: MessagePayload::new(Count(1))?,
+			}])?,
+		)?;
+
+		let received = recv.recv_timeout(Duration::from_secs(5))?;
+		expect(received).to_be(HostFrame::Broadcast(vec![
+			Message::SendEvent {
+				reg_id: RegistrationId::new_with(0),
+				payload: MessagePayload::new(Count(2))?,
+			},
+		]))?;
+		expect(host.send_bytes(0, &[1, 2, 3]).is_err()).to_be_true()?;
+		Ok(())
+	}
+
+	#[test]
+	fn snapshot() -> Result<()> {
+		let (host, recv) = LobbyHost::new(
+			HostSettings::new(|| {
+				let mut app = App::new();
+				app.add_plugins(ReplicatePlugin)
+					.replicate::<Score>()
+					.add_systems(Startup, |mut commands: Commands| {
+						commands.spawn((Replicate::default(), Score(3)));
+					});
+				app
+			})
+			.with_tick(Duration::from_millis(1)),
+		);
+		host.request_snapshot(7)?;
+		let snapshot = loop {
+			match recv.recv_timeout(Duration::from_secs(5))? {
+				HostFrame::Broadcast(_) => continue,
+				HostFrame::Snapshot(client_id, messages) => {
+					expect(client_id).to_be(7)?;
+					break messages;
+				}
+			}
+		};
+		expect(snapshot.len()).to_be(1)?;
+		let Message::SpawnWith { components, .. } = &snapshot[0] else {
+			anyhow::bail!("expected spawn with");
+		};
+		expect(components[0].1.deserialize::<Score>()?).to_be(Score(3))?;
+		Ok(())
+	}
+
+	#[derive(Debug, Clone, PartialEq, Event, Serialize, Deserialize)]
+	struct Total(u32);
+
+	fn send_total(
+		mut events: EventWriter<Total>,
+		changed: Query<(), Changed<Score>>,
+		scores: Query<&Score>,
+	) {
+		if !changed.is_empty() {
+			events.send(Total(scores.iter().map(|score| score.0).sum()));
+		}
+	}
+
+	#[test]
+	fn senders() -> Result<()> {
+		let (host, recv) = LobbyHost::new(
+			HostSettings::new(|| {
+				let mut app = App::new();
+				app.add_plugins(ReplicatePlugin)
+					.add_event::<Total>()
+					.replicate::<Score>()
+					.replicate_event_outgoing::<Total>()
+					.add_systems(Update, send_total.after(MessageIncomingSet));
+				app
+			})
+			.with_tick(Duration::from_millis(1)),
+		);
+		let reg_id = RegistrationId::new_with(0);
+		// each client spawns its first entity, with the same id
+		let entity = Entity::from_raw(0);
+		let send = |client_id, message: Message| {
+			host.send_bytes(
+				client_id,
+				&Message::vec_into_bytes(&vec![message])?,
+			)
+		};
+		for (client_id, score) in [(1, 1), (2, 2)] {
+			send(client_id, Message::Spawn { entity })?;
+			send(client_id, Message::Add {
+				entity,
+				reg_id,
+				payload: MessagePayload::new(Score(score))?,
+			})?;
+		}
+		send(1, Message::Change {
+			entity,
+			reg_id,
+			payload: MessagePayload::new(Score(10))?,
+		})?;
+
+		let total = loop {
+			let HostFrame::Broadcast(messages) =
+				recv.recv_timeout(Duration::from_secs(5))?
+			else {
+				continue;
+			};
+			let total = messages.iter().find_map(|message| match message {
+				Message::SendEvent { payload, .. } => {
+					payload.deserialize::<Total>().ok()
+				}
+				_ => None,
+			});
+			match total {
+				Some(Total(total)) if total >= 12 => break total,
+				_ => continue,
+			}
+		};
+		expect(total).to_be(12)?;
+		Ok(())
+	}
+}
@@ -1,29 +1,132 @@
 use super::*;
+use anyhow::Result;
 use axum::extract::ConnectInfo;
 use axum::extract::WebSocketUpgrade;
+use axum::http::StatusCode;
 use axum::response::IntoResponse;
+use axum::response::Response;
 use axum_extra::TypedHeader;
+use serde::Deserialize;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 use tokio::sync::RwLock;
 
+/// The lobby used by `/ws` when no lobby is specified.
+pub const DEFAULT_LOBBY: &str = "default";
+/// How long lobbies created on demand are kept once empty.
+pub const DEFAULT_LOBBY_LIFETIME: Duration = Duration::from_secs(60);
+/// How often expired lobbies are removed.
+pub const DEFAULT_LOBBY_CLEANUP_INTERVAL: Duration = Duration::from_secs(5);
+/// Lobby names longer than this are rejected.
+pub const MAX_LOBBY_NAME_LEN: usize = 64;
+
+/// How lobbies are created and removed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LobbyPolicy {
+	/// Create lobbies when the first client connects, otherwise
+	/// clients connecting to a lobby that does not exist are rejected.
+	pub create_on_demand: bool,
+	/// How long lobbies created on demand are kept once empty,
+	/// or forever if `None`. Explicitly created lobbies are always kept.
+	pub lifetime: Option<Duration>,
+}
+
+impl Default for LobbyPolicy {
+	fn default() -> Self {
+		Self {
+			create_on_demand: true,
+			lifetime: Some(DEFAULT_LOBBY_LIFETIME),
+		}
+	}
+}
+
+/// Lobby names are 1 to [`MAX_LOBBY_NAME_LEN`] ascii alphanumeric,
+/// `-` or `_` characters.
+pub fn validate_lobby_name(name: &str) -> Result<()> {
+	if name.is_empty() || name.len() > MAX_LOBBY_NAME_LEN {
+		anyhow::bail!(
+			"lobby name must be 1 to {MAX_LOBBY_NAME_LEN} characters"
+		);
+	}
+	if !name
+		.chars()
+		.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+	{
+		anyhow::bail!("lobby name may only contain a-z, 0-9, - and _");
+	}
+	Ok(())
+}
+
+/// Query parameters for `/ws`, ie `/ws?lobby=foo`.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct LobbyQuery {
+	pub lobby: Option<LobbyId>,
+}
 
 #[derive(Default, Clone)]
 pub struct LobbyMap(pub Arc<RwLock<LobbyMapInner>>);
 
 
 impl LobbyMap {
+	pub fn new(inner: LobbyMapInner) -> Self {
+		Self(Arc::new(RwLock::new(inner)))
+	}
+
 	pub async fn handle_socket(
 		self,
+		lobby_id: LobbyId,
 		ws: WebSocketUpgrade,
 		user_agent: Option<TypedHeader<headers::UserAgent>>,
 		connect_info: ConnectInfo<SocketAddr>,
-	) -> impl IntoResponse {
+		auth: Option<Arc<dyn AuthVerifier>>,
+	) -> Response {
+		if let Err(err) = validate_lobby_name(&lobby_id) {
+			return (StatusCode::BAD_REQUEST, err.to_string()).into_response();
+		}
+		let max_frame_size = {
+			let map = self.0.read().await;
+			if !map.can_join(&lobby_id) {
+				return (
+					StatusCode::NOT_FOUND,
+					format!("lobby not found: {lobby_id}"),
+				)
+					.into_response();
+			}
+			map.client_settings.limits.max_frame_size
+		};
+		// reject oversized frames before they are buffered,
+		// the rate limits are checked by the lobby client
+		let ws = match max_frame_size {
+			Some(max) => ws.max_message_size(max).max_frame_size(max),
+			None => ws,
+		};
 		let lobby = self.clone();
-		ws.on_upgrade(move |socket| {
-			lobby.handle_upgrade(Client::new(socket, user_agent, connect_info))
+		ws.on_upgrade(move |socket| async move {
+			let mut client = Client::new(socket, user_agent, connect_info);
+			if let Some(auth) = auth {
+				match authenticate(
+					&mut client.socket,
+					auth.as_ref(),
+					DEFAULT_AUTH_TIMEOUT,
+				)
+				.await
+				{
+					Ok(subject) => {
+						log::info!("Authenticated: {subject}");
+						client = client.with_subject(subject);
+					}
+					Err(err) => {
+						log::info!("Authentication failed: {err}");
+						return;
+					}
+				}
+			}
+			lobby.handle_upgrade(lobby_id, client).await
 		})
+		.into_response()
 	}
 
 
@@ -38,8 +141,76 @@ impl LobbyMap {
 	// 	})
 	// }
 
-	async fn handle_upgrade(self, client: Client) {
-		self.0.write().await.push_client(client).await;
+	async fn handle_upgrade(self, lobby_id: LobbyId, client: Client) {
+		if let Err(err) =
+			self.0.write().await.push_client(lobby_id, client).await
+		{
+			log::info!("Failed to join lobby: {err}");
+		}
+	}
+
+	/// Periodically remove lobbies that have been empty
+	/// for longer than their lifetime.
+	pub fn spawn_cleanup(
+		&self,
+		interval: Duration,
+	) -> tokio::task::JoinHandle<()> {
+		let map = self.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(interval);
+			loop {
+				interval.tick().await;
+				map.remove_expired(Instant::now()).await;
+			}
+		})
+	}
+
+	/// Remove lobbies that have been empty for longer than their lifetime.
+	/// Lobbies are checked without holding the map lock, which is only
+	/// taken to remove them.
+	pub async fn remove_expired(&self, now: Instant) {
+		let lobbies = self
+			.0
+			.read()
+			.await
+			.lobbies
+			.iter()
+			.map(|(lobby_id, lobby)| (lobby_id.clone(), lobby.clone()))
+			.collect::<Vec<_>>();
+		let mut expired = Vec::new();
+		for (lobby_id, lobby) in lobbies {
+			if lobby.read().await.is_expired(now) {
+				expired.push((lobby_id, lobby));
+			}
+		}
+		if expired.is_empty() {
+			return;
+		}
+		let mut removed = Vec::new();
+		{
+			let mut map = self.0.write().await;
+			for (lobby_id, lobby) in expired {
+				// skip lobbies replaced or joined since they were checked,
+				// joining holds the map lock so try_read cannot miss it
+				let unchanged = map
+					.lobbies
+					.get(&lobby_id)
+					.is_some_and(|current| Arc::ptr_eq(current, &lobby));
+				let still_expired =
+					lobby.try_read().is_ok_and(|lobby| lobby.is_expired(now));
+				if unchanged && still_expired {
+					map.lobbies.remove(&lobby_id);
+					removed.push((lobby_id, lobby));
+				}
+			}
+		}
+		for (lobby_id, lobby) in removed {
+			lobby
+				.write()
+				.await
+				.close_with(CLOSE_CODE_KICKED, "lobby closed");
+			log::info!("Closed lobby: {lobby_id}");
+		}
 	}
 }
 
@@ -47,15 +218,154 @@ impl LobbyMap {
 #[derive(Default)]
 pub struct LobbyMapInner {
 	pub lobbies: HashMap<LobbyId, Lobby>,
+	/// If set, each lobby runs an authoritative [`LobbyHost`].
+	pub host: Option<HostSettings>,
+	pub policy: LobbyPolicy,
+	pub client_settings: ClientSettings,
+	/// Counters shared by every lobby, see [`render_metrics`]
+	pub metrics: Arc<ServerMetrics>,
 }
 
 impl LobbyMapInner {
-	pub async fn push_client(&mut self, client: Client) {
-		let lobby_id = LobbyId::default();
+	/// Whether a client may connect to this lobby,
+	/// either because it exists or can be created on demand.
+	pub fn can_join(&self, lobby_id: &str) -> bool {
+		self.policy.create_on_demand || self.lobbies.contains_key(lobby_id)
+	}
+
+	/// Create a lobby that is kept when empty, doing nothing
+	/// if it already exists. Returns whether it was created.
+	/// # Errors
+	/// If the name is invalid, see [`validate_lobby_name`].
+	pub fn create_lobby(&mut self, lobby_id: LobbyId) -> Result<bool> {
+		validate_lobby_name(&lobby_id)?;
+		if self.lobbies.contains_key(&lobby_id) {
+			return Ok(false);
+		}
+		self.insert_lobby(lobby_id, None);
+		Ok(true)
+	}
+
+	/// Disconnect all clients and remove the lobby,
+	/// returning whether it existed.
+	pub async fn close_lobby(&mut self, lobby_id: &str) -> bool {
+		if let Some(lobby) = self.lobbies.remove(lobby_id) {
+			lobby
+				.write()
+				.await
+				.close_with(CLOSE_CODE_KICKED, "lobby closed");
+			log::info!("Closed lobby: {lobby_id}");
+			true
+		} else {
+			false
+		}
+	}
+
+	/// Send a close frame to every client and remove all lobbies,
+	/// waiting up to `timeout` for the close frames to be flushed.
+	pub async fn close_all(
+		&mut self,
+		code: u16,
+		reason: &str,
+		timeout: Duration,
+	) {
+		let mut tasks = Vec::new();
+		for (_, lobby) in self.lobbies.drain() {
+			tasks.extend(lobby.write().await.close_with(code, reason));
+		}
+		if tokio::time::timeout(timeout, futures::future::join_all(tasks))
+			.await
+			.is_err()
+		{
+			log::info!("Timed out closing client connections");
+		}
+	}
+
+	fn insert_lobby(
+		&mut self,
+		lobby_id: LobbyId,
+		lifetime: Option<Duration>,
+	) -> Lobby {
+		log::info!("Created lobby: {lobby_id}");
+		let mut inner = LobbyInner::new(lifetime);
+		inner.client_settings = self.client_settings.clone();
+		inner.metrics = self.metrics.clone();
+		let lobby = Lobby::new(RwLock::new(inner));
+		self.lobbies.insert(lobby_id, lobby.clone());
+		lobby
+	}
+
+	/// Add the client to the lobby, creating it if allowed by the policy.
+	pub async fn push_client(
+		&mut self,
+		lobby_id: LobbyId,
+		client: Client,
+	) -> Result<()> {
+		let lobby_arc = match self.lobbies.get(&lobby_id) {
+			Some(lobby) => lobby.clone(),
+			None if self.policy.create_on_demand => {
+				let lifetime = self.policy.lifetime;
+				self.insert_lobby(lobby_id.clone(), lifetime)
+			}
+			None => anyhow::bail!("lobby not found: {lobby_id}"),
+		};
+		let mut lobby = lobby_arc.write().await;
+		if let Some(host) = &self.host {
+			if !lobby.has_host() {
+				lobby.start_host(lobby_arc.clone(), host.clone());
+			}
+		}
+		lobby.push_client(lobby_arc.clone(), client);
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use std::time::Duration;
+	use std::time::Instant;
+	use sweet::*;
+
+	#[test]
+	fn lobby_names() -> Result<()> {
+		expect(validate_lobby_name("my-lobby_2")).to_be_ok()?;
+		expect(validate_lobby_name("")).to_be_err()?;
+		expect(validate_lobby_name("foo/bar")).to_be_err()?;
+		expect(validate_lobby_name(&"a".repeat(MAX_LOBBY_NAME_LEN + 1)))
+			.to_be_err()?;
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn lifetime() -> Result<()> {
+		let map = LobbyMap::new(LobbyMapInner {
+			policy: LobbyPolicy {
+				create_on_demand: false,
+				lifetime: Some(Duration::from_secs(1)),
+			},
+			..Default::default()
+		});
+		{
+			let mut map = map.0.write().await;
+			expect(map.can_join("foo")).to_be_false()?;
+			expect(map.create_lobby("foo".into())?).to_be_true()?;
+			expect(map.create_lobby("foo".into())?).to_be_false()?;
+			expect(map.can_join("foo")).to_be_true()?;
 
-		let lobby = self.lobbies.entry(lobby_id).or_insert_with(Lobby::default);
+			// on demand lobbies use the policy lifetime
+			let lifetime = map.policy.lifetime;
+			map.insert_lobby("bar".into(), lifetime);
+		}
+		let later = Instant::now() + Duration::from_secs(2);
+		map.remove_expired(later).await;
+		let mut map = map.0.write().await;
+		expect(map.lobbies.contains_key("foo")).to_be_true()?;
+		expect(map.lobbies.contains_key("bar")).to_be_false()?;
 
-		let lobby_arc = lobby.clone();
-		lobby.write().await.push_client(lobby_arc, client);
+		expect(map.close_lobby("foo").await).to_be_true()?;
+		expect(map.close_lobby("foo").await).to_be_false()?;
+		Ok(())
 	}
 }
@@ -0,0 +1,291 @@
+use super::*;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use std::fmt::Write;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+/// Why a client left its lobby, see [`ServerMetrics`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DisconnectReason {
+	/// The client closed the connection
+	Closed,
+	/// Kicked, or the lobby was closed
+	Kicked,
+	/// The outgoing queue overflowed
+	TooSlow,
+	/// Exceeded its [`ClientLimits`]
+	LimitExceeded,
+	IdleTimeout,
+	/// Writing to the socket failed or timed out
+	SendFailed,
+	/// The server shut down
+	Shutdown,
+}
+
+impl DisconnectReason {
+	pub const ALL: [Self; 7] = [
+		Self::Closed,
+		Self::Kicked,
+		Self::TooSlow,
+		Self::LimitExceeded,
+		Self::IdleTimeout,
+		Self::SendFailed,
+		Self::Shutdown,
+	];
+
+	/// The reason for a close frame sent by the server
+	pub fn from_close_code(code: u16) -> Self {
+		match code {
+			CLOSE_CODE_TOO_SLOW => Self::TooSlow,
+			CLOSE_CODE_LIMIT_EXCEEDED => Self::LimitExceeded,
+			CLOSE_CODE_IDLE_TIMEOUT => Self::IdleTimeout,
+			CLOSE_CODE_GOING_AWAY => Self::Shutdown,
+			_ => Self::Kicked,
+		}
+	}
+
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::Closed => "closed",
+			Self::Kicked => "kicked",
+			Self::TooSlow => "too_slow",
+			Self::LimitExceeded => "limit_exceeded",
+			Self::IdleTimeout => "idle_timeout",
+			Self::SendFailed => "send_failed",
+			Self::Shutdown => "shutdown",
+		}
+	}
+}
+
+/// Counters shared by every lobby, which outlive the lobbies themselves.
+#[derive(Debug, Default)]
+pub struct ServerMetrics {
+	send_failures: AtomicU64,
+	violations: AtomicU64,
+	disconnects: [AtomicU64; DisconnectReason::ALL.len()],
+}
+
+impl ServerMetrics {
+	/// A frame could not be queued or written to a client
+	pub fn record_send_failure(&self) {
+		self.send_failures.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// A received frame exceeded the [`ClientLimits`]
+	pub fn record_violation(&self) {
+		self.violations.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn record_disconnect(&self, reason: DisconnectReason) {
+		self.disconnects[reason as usize].fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn send_failures(&self) -> u64 {
+		self.send_failures.load(Ordering::Relaxed)
+	}
+
+	pub fn violations(&self) -> u64 { self.violations.load(Ordering::Relaxed) }
+
+	pub fn disconnects(&self, reason: DisconnectReason) -> u64 {
+		self.disconnects[reason as usize].load(Ordering::Relaxed)
+	}
+}
+
+/// Frames relayed by a lobby, reset when the lobby is removed.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LobbyStats {
+	pub frames_received: u64,
+	pub bytes_received: u64,
+	pub frames_sent: u64,
+	pub bytes_sent: u64,
+}
+
+impl LobbyStats {
+	/// Metric name and help for each of [`Self::values`]
+	const COUNTERS: [(&'static str, &'static str); 4] = [
+		(
+			"lobby_frames_received_total",
+			"Frames received from clients per lobby.",
+		),
+		(
+			"lobby_bytes_received_total",
+			"Bytes received from clients per lobby.",
+		),
+		(
+			"lobby_frames_sent_total",
+			"Frames queued for clients per lobby.",
+		),
+		(
+			"lobby_bytes_sent_total",
+			"Bytes queued for clients per lobby.",
+		),
+	];
+
+	fn values(&self) -> [u64; 4] {
+		[
+			self.frames_received,
+			self.bytes_received,
+			self.frames_sent,
+			self.bytes_sent,
+		]
+	}
+}
+
+/// Serves [`render_metrics`] at `/metrics`, merged by [`Server`].
+/// Unlike the lobby api it does not require auth.
+pub fn metrics_router(lobby_map: LobbyMap) -> Router {
+	Router::new()
+		.route("/metrics", get(handle_metrics))
+		.with_state(lobby_map)
+}
+
+async fn handle_metrics(State(map): State<LobbyMap>) -> impl IntoResponse {
+	let body = render_metrics(&*map.0.read().await).await;
+	(
+		[(
+			header::CONTENT_TYPE,
+			"text/plain; version=0.0.4; charset=utf-8",
+		)],
+		body,
+	)
+}
+
+/// The metrics in the Prometheus text format.
+/// Lobby names are validated so do not need escaping in labels.
+pub async fn render_metrics(map: &LobbyMapInner) -> String {
+	let mut lobbies = Vec::with_capacity(map.lobbies.len());
+	for (id, lobby) in map.lobbies.iter() {
+		let lobby = lobby.read().await;
+		lobbies.push((id.clone(), lobby.num_clients(), lobby.stats.clone()));
+	}
+	lobbies.sort_by(|a, b| a.0.cmp(&b.0));
+	let metrics = &map.metrics;
+	let mut out = String::new();
+
+	let clients: usize = lobbies.iter().map(|(_, clients, _)| clients).sum();
+	header(&mut out, "clients", "gauge", "Connected clients.");
+	writeln!(out, "beetmash_clients {clients}").ok();
+	header(&mut out, "lobbies", "gauge", "Open lobbies.");
+	writeln!(out, "beetmash_lobbies {}", lobbies.len()).ok();
+	header(
+		&mut out,
+		"lobby_clients",
+		"gauge",
+		"Connected clients per lobby.",
+	);
+	for (id, clients, _) in lobbies.iter() {
+		writeln!(out, "beetmash_lobby_clients{{lobby=\"{id}\"}} {clients}")
+			.ok();
+	}
+
+	for (i, (name, help)) in LobbyStats::COUNTERS.iter().enumerate() {
+		header(&mut out, name, "counter", help);
+		for (id, _, stats) in lobbies.iter() {
+			writeln!(
+				out,
+				"beetmash_{name}{{lobby=\"{id}\"}} {}",
+				stats.values()[i]
+			)
+			.ok();
+		}
+	}
+
+	header(
+		&mut out,
+		"send_failures_total",
+		"counter",
+		"Frames that could not be queued or written to a client.",
+	);
+	writeln!(
+		out,
+		"beetmash_send_failures_total {}",
+		metrics.send_failures()
+	)
+	.ok();
+	header(
+		&mut out,
+		"disconnects_total",
+		"counter",
+		"Clients that left a lobby, by reason.",
+	);
+	for reason in DisconnectReason::ALL {
+		writeln!(
+			out,
+			"beetmash_disconnects_total{{reason=\"{}\"}} {}",
+			reason.as_str(),
+			metrics.disconnects(reason)
+		)
+		.ok();
+	}
+	header(
+		&mut out,
+		"limit_violations_total",
+		"counter",
+		"Received frames exceeding the client limits.",
+	);
+	writeln!(
+		out,
+		"beetmash_limit_violations_total {}",
+		metrics.violations()
+	)
+	.ok();
+	out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+	writeln!(out, "# HELP beetmash_{name} {help}").ok();
+	writeln!(out, "# TYPE beetmash_{name} {kind}").ok();
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use axum::body::Body;
+	use axum::http::header;
+	use axum::http::Request;
+	use axum::http::StatusCode;
+	use sweet::*;
+	use tower::ServiceExt;
+
+	#[tokio::test]
+	async fn works() -> Result<()> {
+		let map = LobbyMap::default();
+		{
+			let mut inner = map.0.write().await;
+			inner.create_lobby("foo".into())?;
+			inner.lobbies["foo"]
+				.write()
+				.await
+				.handle_message(0, vec![1, 2, 3])?;
+			inner.metrics.record_disconnect(DisconnectReason::TooSlow);
+			inner.metrics.record_violation();
+		}
+		let response = metrics_router(map)
+			.oneshot(Request::builder().uri("/metrics").body(Body::empty())?)
+			.await?;
+		expect(response.status()).to_be(StatusCode::OK)?;
+		expect(response.headers()[header::CONTENT_TYPE].to_str()?)
+			.to_start_with("text/plain; version=0.0.4")?;
+		let body =
+			axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+		let body = String::from_utf8(body.to_vec())?;
+		for line in [
+			"# TYPE beetmash_clients gauge",
+			"beetmash_clients 0",
+			"beetmash_lobbies 1",
+			"beetmash_lobby_frames_received_total{lobby=\"foo\"} 1",
+			"beetmash_lobby_bytes_received_total{lobby=\"foo\"} 3",
+			"beetmash_disconnects_total{reason=\"too_slow\"} 1",
+			"beetmash_disconnects_total{reason=\"kicked\"} 0",
+			"beetmash_limit_violations_total 1",
+		] {
+			expect(body.lines().any(|l| l == line)).to_be_true()?;
+		}
+		Ok(())
+	}
+}
@@ -1,18 +1,42 @@
+pub mod api;
+#[allow(unused_imports)]
+pub use self::api::*;
+pub mod auth;
+#[allow(unused_imports)]
+pub use self::auth::*;
 pub mod client;
 #[allow(unused_imports)]
 pub use self::client::*;
+pub mod client_limits;
+#[allow(unused_imports)]
+pub use self::client_limits::*;
+pub mod config;
+#[allow(unused_imports)]
+pub use self::config::*;
 pub mod lobby;
 #[allow(unused_imports)]
 pub use self::lobby::*;
 pub mod lobby_client;
 #[allow(unused_imports)]
 pub use self::lobby_client::*;
+pub mod lobby_host;
+#[allow(unused_imports)]
+pub use self::lobby_host::*;
 pub mod lobby_map;
 #[allow(unused_imports)]
 pub use self::lobby_map::*;
+pub mod metrics;
+#[allow(unused_imports)]
+pub use self::metrics::*;
 pub mod server;
 #[allow(unused_imports)]
 pub use self::server::*;
+pub mod shutdown;
+#[allow(unused_imports)]
+pub use self::shutdown::*;
+pub mod tls;
+#[allow(unused_imports)]
+pub use self::tls::*;
 pub mod tracing_utils;
 #[allow(unused_imports)]
 pub use self::tracing_utils::*;
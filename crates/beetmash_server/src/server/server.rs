@@ -1,9 +1,12 @@
 use super::*;
+use axum::extract::Path;
+use axum::extract::Query;
 use axum::response::Html;
 use axum::routing::get;
 use axum::Router;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::Arc;
 use tower_http::services::ServeDir;
 
 
@@ -12,12 +15,47 @@ pub const DEFAULT_ADDRESS: &str = "0.0.0.0:3000";
 
 pub struct Server {
 	pub address: String,
+	/// If set, clients must send a credential as their first message.
+	pub auth: Option<Arc<dyn AuthVerifier>>,
+	/// If set, the `/api` routes that change lobbies are enabled
+	/// for requests with a credential it accepts, see [`api_router`].
+	pub admin: Option<Arc<dyn AuthVerifier>>,
+	/// If set, each lobby runs a headless authoritative app
+	/// instead of relaying messages between clients.
+	pub host: Option<HostSettings>,
+	pub lobby_policy: LobbyPolicy,
+	pub client_settings: ClientSettings,
+	/// Lobbies created on startup, these are kept when empty.
+	pub lobbies: Vec<LobbyId>,
+	/// Stops the server, see [`Self::shutdown_handle`].
+	pub shutdown: ShutdownHandle,
+	/// Also shut down on SIGINT or SIGTERM.
+	pub handle_signals: bool,
+	/// Static files served at `/`
+	pub assets_dir: PathBuf,
+	/// The tracing filter used by [`Self::run`] if `RUST_LOG` is not set,
+	/// ie `info` or `beetmash_server=debug`.
+	pub log_level: Option<String>,
+	/// If set, serve `https` and `wss` instead of `http` and `ws`.
+	pub tls: Option<TlsConfig>,
 }
 
 impl Default for Server {
 	fn default() -> Self {
 		Self {
 			address: DEFAULT_ADDRESS.to_string(),
+			auth: None,
+			admin: None,
+			host: None,
+			lobby_policy: LobbyPolicy::default(),
+			client_settings: ClientSettings::default(),
+			lobbies: Vec::new(),
+			shutdown: ShutdownHandle::default(),
+			handle_signals: true,
+			assets_dir: PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+				.join("assets"),
+			log_level: None,
+			tls: None,
 		}
 	}
 }
@@ -29,44 +67,465 @@ impl Server {
 			..Default::default()
 		}
 	}
+
+	/// Require clients to authenticate, see [`AuthVerifier`].
+	pub fn with_auth(mut self, verifier: impl AuthVerifier) -> Self {
+		self.auth = Some(Arc::new(verifier));
+		self
+	}
+
+	/// Enable the admin routes of the api, see [`api_router`].
+	pub fn with_admin(mut self, verifier: impl AuthVerifier) -> Self {
+		self.admin = Some(Arc::new(verifier));
+		self
+	}
+
+	/// Run an authoritative [`LobbyHost`] in each lobby, see [`HostSettings`].
+	pub fn with_host(mut self, settings: HostSettings) -> Self {
+		self.host = Some(settings);
+		self
+	}
+
+	pub fn with_lobby_policy(mut self, policy: LobbyPolicy) -> Self {
+		self.lobby_policy = policy;
+		self
+	}
+
+	pub fn with_client_settings(mut self, settings: ClientSettings) -> Self {
+		self.client_settings = settings;
+		self
+	}
+
+	/// Create a lobby on startup, see [`LobbyMapInner::create_lobby`].
+	pub fn with_lobby(mut self, lobby: impl Into<LobbyId>) -> Self {
+		self.lobbies.push(lobby.into());
+		self
+	}
+
+	pub fn with_assets_dir(mut self, assets_dir: impl Into<PathBuf>) -> Self {
+		self.assets_dir = assets_dir.into();
+		self
+	}
+
+	/// Terminate TLS with the certificate and key files.
+	pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+		self.tls = Some(tls);
+		self
+	}
+
+	/// Whether to shut down on SIGINT or SIGTERM, defaults to true.
+	pub fn with_signals(mut self, handle_signals: bool) -> Self {
+		self.handle_signals = handle_signals;
+		self
+	}
+
+	/// A handle to gracefully stop the server, sending
+	/// a close frame to every client.
+	pub fn shutdown_handle(&self) -> ShutdownHandle { self.shutdown.clone() }
+
+	/// Bind to [`Self::address`] and [`Self::serve`].
 	pub async fn run(self) -> anyhow::Result<()> {
-		init_tracing();
+		match &self.log_level {
+			Some(log_level) => init_tracing_with(log_level),
+			None => init_tracing(),
+		}
 		::tracing::debug!("listenin");
+		let listener = tokio::net::TcpListener::bind(&self.address).await?;
+		println!("listening on {}", listener.local_addr()?);
+		// tracing::debug!("listening on {}", listener.local_addr().unwrap());
+		self.serve(listener).await
+	}
 
-		let assets_dir =
-			PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets");
-
-		let lobby_map = LobbyMap::default();
+	/// Serve on the listener until shut down, see [`Self::shutdown_handle`].
+	pub async fn serve(
+		self,
+		listener: tokio::net::TcpListener,
+	) -> anyhow::Result<()> {
+		self.client_settings.limits.validate()?;
+		let acceptor =
+			self.tls.as_ref().map(TlsConfig::acceptor).transpose()?;
+		let lobby_map = LobbyMap::new(LobbyMapInner {
+			host: self.host.clone(),
+			policy: self.lobby_policy.clone(),
+			client_settings: self.client_settings.clone(),
+			..Default::default()
+		});
+		for lobby in self.lobbies.iter() {
+			lobby_map.0.write().await.create_lobby(lobby.clone())?;
+		}
+		let cleanup = lobby_map.spawn_cleanup(DEFAULT_LOBBY_CLEANUP_INTERVAL);
+		let auth = self.auth.clone();
+		let auth2 = auth.clone();
+		let lobby_map2 = lobby_map.clone();
+		let shutdown_map = lobby_map.clone();
 
 		let app = Router::new()
 			.fallback_service(
-				ServeDir::new(assets_dir.clone())
+				ServeDir::new(self.assets_dir.clone())
 					.append_index_html_on_directories(true),
 			)
 			// .ser
 			.route("/", get(Self::handle_root))
-			// .nest("/api", rest_router(pool1))
+			.nest(
+				"/api",
+				api_router(lobby_map.clone(), auth.clone(), self.admin.clone()),
+			)
+			.merge(metrics_router(lobby_map.clone()))
 			.route(
 				"/ws",
-				get(move |ws, user_agent, connect_info| {
-					lobby_map.handle_socket(ws, user_agent, connect_info)
-				}),
+				get(
+					move |Query(query): Query<LobbyQuery>,
+					      ws,
+					      user_agent,
+					      connect_info| {
+						lobby_map.handle_socket(
+							query.lobby.unwrap_or(DEFAULT_LOBBY.into()),
+							ws,
+							user_agent,
+							connect_info,
+							auth.clone(),
+						)
+					},
+				),
+			)
+			.route(
+				"/ws/:lobby",
+				get(
+					move |Path(lobby): Path<LobbyId>,
+					      ws,
+					      user_agent,
+					      connect_info| {
+						lobby_map2.handle_socket(
+							lobby,
+							ws,
+							user_agent,
+							connect_info,
+							auth2.clone(),
+						)
+					},
+				),
 			)
 			.layer(tracing_layer());
 
-		let listener = tokio::net::TcpListener::bind(self.address).await?;
-		println!("listening on {}", listener.local_addr().unwrap());
-		// tracing::debug!("listening on {}", listener.local_addr().unwrap());
+		let shutdown = self.shutdown.clone();
+		let handle_signals = self.handle_signals;
+		let shutdown = async move {
+			if handle_signals {
+				tokio::select! {
+					_ = shutdown.wait() => {},
+					_ = shutdown_signal() => {},
+				}
+			} else {
+				shutdown.wait().await;
+			}
+			log::info!("Shutting down");
+			cleanup.abort();
+			shutdown_map
+				.0
+				.write()
+				.await
+				.close_all(
+					CLOSE_CODE_GOING_AWAY,
+					"server shutting down",
+					DEFAULT_SHUTDOWN_TIMEOUT,
+				)
+				.await;
+		};
+		match acceptor {
+			Some(acceptor) => {
+				serve_tls(listener, acceptor, app, shutdown).await?;
+			}
+			None => {
+				axum::serve(
+					listener,
+					app.into_make_service_with_connect_info::<SocketAddr>(),
+				)
+				.with_graceful_shutdown(shutdown)
+				.await?;
+			}
+		}
+		Ok(())
+	}
 
-		axum::serve(
-			listener,
-			app.into_make_service_with_connect_info::<SocketAddr>(),
+	async fn handle_root() -> Html<&'static str> {
+		Html("Welcome to the beetmash server.")
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use beetmash_net::prelude::RelayFrame;
+	use futures::StreamExt;
+	use std::net::SocketAddr;
+	use std::time::Duration;
+	use sweet::*;
+	use tokio::task::JoinHandle;
+	use tokio_tungstenite::tungstenite::Message as TungMessage;
+
+	type WsClient = tokio_tungstenite::WebSocketStream<
+		tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+	>;
+
+	async fn start(
+		server: Server,
+	) -> Result<(SocketAddr, ShutdownHandle, JoinHandle<Result<()>>)> {
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+		let addr = listener.local_addr()?;
+		let server = server.with_signals(false);
+		let handle = server.shutdown_handle();
+		let task = tokio::spawn(server.serve(listener));
+		Ok((addr, handle, task))
+	}
+
+	async fn recv_frame(client: &mut WsClient) -> Result<RelayFrame> {
+		match client.next().await.transpose()? {
+			Some(TungMessage::Binary(bytes)) => RelayFrame::from_bytes(&bytes),
+			other => anyhow::bail!("expected a binary frame, got {other:?}"),
+		}
+	}
+
+	/// Wait for the client to join, returning the id sent by the lobby
+	async fn joined(client: &mut WsClient) -> Result<ClientId> {
+		match recv_frame(client).await? {
+			RelayFrame::Joined(client_id) => Ok(client_id),
+			other => anyhow::bail!("expected a joined frame, got {other:?}"),
+		}
+	}
+
+	fn close_code(msg: Option<TungMessage>) -> Option<u16> {
+		match msg {
+			Some(TungMessage::Close(Some(frame))) => Some(frame.code.into()),
+			_ => None,
+		}
+	}
+
+	#[tokio::test]
+	async fn shutdown() -> Result<()> {
+		let (addr, handle, task) = start(Server::default()).await?;
+		let (mut client, _) =
+			tokio_tungstenite::connect_async(format!("ws://{addr}/ws/foo"))
+				.await?;
+		joined(&mut client).await?;
+		handle.shutdown();
+		let msg = client.next().await.transpose()?;
+		expect(close_code(msg)).to_be(Some(CLOSE_CODE_GOING_AWAY))?;
+		tokio::time::timeout(Duration::from_secs(5), task).await???;
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn routes() -> Result<()> {
+		use futures::SinkExt;
+
+		let (addr, handle, _task) = start(Server::default()).await?;
+		let connect = |path: &str| {
+			tokio_tungstenite::connect_async(format!("ws://{addr}{path}"))
+		};
+		let (mut client1, _) = connect("/ws/foo").await?;
+		let (mut client2, _) = connect("/ws?lobby=foo").await?;
+		let (mut client3, _) = connect("/ws/bar").await?;
+		for client in [&mut client1, &mut client2, &mut client3] {
+			joined(client).await?;
+		}
+		client1.send(TungMessage::Binary(vec![1])).await?;
+		let msg = client2.next().await.transpose()?;
+		expect(msg).to_be(Some(TungMessage::Binary(vec![1])))?;
+		let other_lobby =
+			tokio::time::timeout(Duration::from_millis(50), client3.next())
+				.await;
+		expect(other_lobby.is_err()).to_be_true()?;
+		handle.shutdown();
+
+		let (addr, handle, _task) = start(
+			Server::default()
+				.with_lobby_policy(LobbyPolicy {
+					create_on_demand: false,
+					..Default::default()
+				})
+				.with_lobby("foo"),
 		)
 		.await?;
+		let connect = |path: &str| {
+			tokio_tungstenite::connect_async(format!("ws://{addr}{path}"))
+		};
+		expect(connect("/ws/foo").await.is_ok()).to_be_true()?;
+		expect(connect("/ws?lobby=foo").await.is_ok()).to_be_true()?;
+		for path in ["/ws/bar", "/ws?lobby=bar"] {
+			let status = match connect(path).await {
+				Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+					Some(response.status().as_u16())
+				}
+				_ => None,
+			};
+			expect(status).to_be(Some(404))?;
+		}
+		handle.shutdown();
 		Ok(())
 	}
 
-	async fn handle_root() -> Html<&'static str> {
-		Html("Welcome to the beetmash server.")
+	#[tokio::test]
+	async fn heartbeat() -> Result<()> {
+		let (addr, handle, _task) =
+			start(Server::default().with_client_settings(ClientSettings {
+				ping_interval: Some(Duration::from_millis(20)),
+				idle_timeout: Some(Duration::from_millis(100)),
+				..Default::default()
+			}))
+			.await?;
+		let (mut client, _) =
+			tokio_tungstenite::connect_async(format!("ws://{addr}/ws")).await?;
+		// reading responds to pings so the client is not idle
+		let read = tokio::time::timeout(Duration::from_millis(300), async {
+			while let Some(msg) = client.next().await {
+				if let TungMessage::Close(_) = msg? {
+					anyhow::bail!("closed");
+				}
+			}
+			Ok(())
+		})
+		.await;
+		expect(read.is_err()).to_be_true()?;
+		handle.shutdown();
+
+		let (addr, _handle, _task) =
+			start(Server::default().with_client_settings(ClientSettings {
+				ping_interval: None,
+				idle_timeout: Some(Duration::from_millis(50)),
+				..Default::default()
+			}))
+			.await?;
+		let (mut client, _) =
+			tokio_tungstenite::connect_async(format!("ws://{addr}/ws")).await?;
+		joined(&mut client).await?;
+		let msg = client.next().await.transpose()?;
+		expect(close_code(msg)).to_be(Some(CLOSE_CODE_IDLE_TIMEOUT))?;
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn limit_exceeded() -> Result<()> {
+		use futures::SinkExt;
+
+		let (addr, handle, _task) =
+			start(Server::default().with_client_settings(ClientSettings {
+				limits: ClientLimits {
+					frames_per_second: Some(1),
+					disconnect: true,
+					..Default::default()
+				},
+				..Default::default()
+			}))
+			.await?;
+		let (mut client, _) =
+			tokio_tungstenite::connect_async(format!("ws://{addr}/ws")).await?;
+		joined(&mut client).await?;
+		for _ in 0..2 {
+			client.send(TungMessage::Binary(vec![0])).await?;
+		}
+		let msg = client.next().await.transpose()?;
+		expect(close_code(msg)).to_be(Some(CLOSE_CODE_LIMIT_EXCEEDED))?;
+		handle.shutdown();
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn unauthorized() -> Result<()> {
+		use futures::SinkExt;
+
+		let (addr, handle, _task) = start(
+			Server::default().with_auth(SharedSecretVerifier::new("secret")),
+		)
+		.await?;
+		let (mut client, _) =
+			tokio_tungstenite::connect_async(format!("ws://{addr}/ws")).await?;
+		client.send(TungMessage::Text("wrong".into())).await?;
+		let msg = client.next().await.transpose()?;
+		expect(close_code(msg)).to_be(Some(CLOSE_CODE_UNAUTHORIZED))?;
+		handle.shutdown();
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn relay() -> Result<()> {
+		use beetmash_net::prelude::Message;
+		use beetmash_net::prelude::NativeWsClient;
+		use beetmash_net::prelude::RelayTarget;
+		use beetmash_net::prelude::Transport;
+		use bevy::prelude::Entity;
+
+		let (addr, handle, _task) = start(Server::default()).await?;
+		let url = format!("ws://{addr}/ws/foo");
+		let mut client1 = NativeWsClient::new(&url).await?;
+		let mut client2 = NativeWsClient::new(&url).await?;
+		let mut client3 = NativeWsClient::new(&url).await?;
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		// receive the joined frames
+		for client in [&mut client1, &mut client2, &mut client3] {
+			expect(client.recv_with_sender()?.len()).to_be(0)?;
+		}
+		let id1 = client1.client_id().unwrap();
+		let id2 = client2.client_id().unwrap();
+		expect(id1).not().to_be(id2)?;
+
+		let messages = vec![Message::Spawn {
+			entity: Entity::from_raw(1),
+		}];
+		client1.send_to(RelayTarget::Client(id2), &messages)?;
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		expect(client2.recv_with_sender()?)
+			.to_be(vec![(Some(id1), messages.clone())])?;
+		client2.send_to(RelayTarget::Client(id1), &messages)?;
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		expect(client1.recv_with_sender()?)
+			.to_be(vec![(Some(id2), messages.clone())])?;
+		// not a target
+		expect(client3.recv()?.len()).to_be(0)?;
+		handle.shutdown();
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn tls() -> Result<()> {
+		use beetmash_net::prelude::Message;
+		use beetmash_net::prelude::NativeWsClient;
+		use beetmash_net::prelude::NativeWsOptions;
+		use beetmash_net::prelude::Transport;
+		use bevy::prelude::Entity;
+
+		let cert =
+			rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
+		let cert_pem = cert.serialize_pem()?;
+		let dir = std::env::temp_dir()
+			.join(format!("beetmash_server_tls_{}", std::process::id()));
+		std::fs::create_dir_all(&dir)?;
+		std::fs::write(dir.join("cert.pem"), &cert_pem)?;
+		std::fs::write(dir.join("key.pem"), cert.serialize_private_key_pem())?;
+		let tls = TlsConfig::new(dir.join("cert.pem"), dir.join("key.pem"));
+		let (addr, handle, _task) =
+			start(Server::default().with_tls(tls)).await?;
+
+		let url = format!("wss://localhost:{}/ws", addr.port());
+		// self-signed certs are not trusted by default
+		expect(NativeWsClient::new(&url).await.is_err()).to_be_true()?;
+
+		let options = NativeWsOptions::default()
+			.with_root_cert_pem(cert_pem.as_bytes())?;
+		let mut client1 =
+			NativeWsClient::new_with_options(&url, &options).await?;
+		let mut client2 =
+			NativeWsClient::new_with_options(&url, &options).await?;
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		let messages = vec![Message::Spawn {
+			entity: Entity::from_raw(1),
+		}];
+		client1.send(&messages)?;
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		expect(client2.recv()?).to_be(messages)?;
+		expect(client2.client_id()).to_be(Some(1))?;
+		handle.shutdown();
+		std::fs::remove_dir_all(&dir).ok();
+		Ok(())
 	}
 }
@@ -0,0 +1,84 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Close code sent to every client when the server shuts down.
+pub const CLOSE_CODE_GOING_AWAY: u16 = 1001;
+/// How long to wait for close frames to be flushed on shutdown.
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Stops a running [`Server`](super::Server) when
+/// [`Self::shutdown`] is called on any clone.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle(Arc<watch::Sender<bool>>);
+
+impl Default for ShutdownHandle {
+	fn default() -> Self { Self(Arc::new(watch::channel(false).0)) }
+}
+
+impl ShutdownHandle {
+	pub fn new() -> Self { Self::default() }
+
+	/// Request the server to shut down, this may be called more than once.
+	pub fn shutdown(&self) { self.0.send_replace(true); }
+
+	pub fn is_shutdown(&self) -> bool { *self.0.borrow() }
+
+	/// Resolves once [`Self::shutdown`] has been called.
+	pub async fn wait(&self) {
+		let mut recv = self.0.subscribe();
+		recv.wait_for(|shutdown| *shutdown).await.ok();
+	}
+}
+
+/// Resolves on SIGINT, or SIGTERM on unix.
+pub async fn shutdown_signal() {
+	let ctrl_c = async {
+		if let Err(err) = tokio::signal::ctrl_c().await {
+			log::error!("failed to listen for ctrl-c: {err}");
+			std::future::pending::<()>().await;
+		}
+	};
+	#[cfg(unix)]
+	let terminate = async {
+		match tokio::signal::unix::signal(
+			tokio::signal::unix::SignalKind::terminate(),
+		) {
+			Ok(mut signal) => {
+				signal.recv().await;
+			}
+			Err(err) => {
+				log::error!("failed to listen for SIGTERM: {err}");
+				std::future::pending::<()>().await;
+			}
+		}
+	};
+	#[cfg(not(unix))]
+	let terminate = std::future::pending::<()>();
+
+	tokio::select! {
+		_ = ctrl_c => {},
+		_ = terminate => {},
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::prelude::*;
+	use anyhow::Result;
+	use sweet::*;
+
+	#[tokio::test]
+	async fn works() -> Result<()> {
+		let handle = ShutdownHandle::new();
+		expect(handle.is_shutdown()).to_be_false()?;
+		let handle2 = handle.clone();
+		let task = tokio::spawn(async move { handle2.wait().await });
+		handle.shutdown();
+		task.await?;
+		expect(handle.is_shutdown()).to_be_true()?;
+		// resolves immediately once shut down
+		handle.wait().await;
+		Ok(())
+	}
+}
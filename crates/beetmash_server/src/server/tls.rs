@@ -0,0 +1,141 @@
+use anyhow::Result;
+use axum::extract::ConnectInfo;
+use axum::Router;
+use hyper::body::Incoming;
+use hyper_util::rt::TokioExecutor;
+use hyper_util::rt::TokioIo;
+use serde::Deserialize;
+use serde::Serialize;
+use std::future::Future;
+use std::io::BufReader;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+use tokio_rustls::rustls;
+use tokio_rustls::TlsAcceptor;
+use tower::Service;
+
+/// Connections that do not complete the tls handshake
+/// within this time are dropped.
+pub const DEFAULT_TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Paths to pem encoded files, used to serve `https` and `wss`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+	/// The certificate chain
+	pub cert: PathBuf,
+	/// The private key
+	pub key: PathBuf,
+}
+
+impl TlsConfig {
+	pub fn new(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+		Self {
+			cert: cert.into(),
+			key: key.into(),
+		}
+	}
+
+	/// Load the certificate chain and key.
+	/// # Errors
+	/// If either file cannot be read or does not contain pem items.
+	pub fn acceptor(&self) -> Result<TlsAcceptor> {
+		let certs = rustls_pemfile::certs(&mut read_pem(&self.cert)?)
+			.collect::<Result<Vec<_>, _>>()?;
+		if certs.is_empty() {
+			anyhow::bail!("no certificates in {}", self.cert.display());
+		}
+		let key = rustls_pemfile::private_key(&mut read_pem(&self.key)?)?
+			.ok_or_else(|| {
+				anyhow::anyhow!("no private key in {}", self.key.display())
+			})?;
+		let config = rustls::ServerConfig::builder()
+			.with_no_client_auth()
+			.with_single_cert(certs, key)?;
+		Ok(TlsAcceptor::from(Arc::new(config)))
+	}
+}
+
+fn read_pem(path: &Path) -> Result<BufReader<std::fs::File>> {
+	let file = std::fs::File::open(path).map_err(|err| {
+		anyhow::anyhow!("failed to read {}: {err}", path.display())
+	})?;
+	Ok(BufReader::new(file))
+}
+
+/// Like [`axum::serve`] but terminating TLS, stops accepting
+/// connections once `shutdown` resolves, then waits up to
+/// [`DEFAULT_SHUTDOWN_TIMEOUT`](super::DEFAULT_SHUTDOWN_TIMEOUT)
+/// for open connections to finish.
+pub async fn serve_tls(
+	listener: TcpListener,
+	acceptor: TlsAcceptor,
+	app: Router,
+	shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+	tokio::pin!(shutdown);
+	let mut connections = JoinSet::new();
+	loop {
+		let (stream, address) = tokio::select! {
+			accepted = listener.accept() => match accepted {
+				Ok(accepted) => accepted,
+				Err(err) => {
+					log::info!("failed to accept connection: {err}");
+					continue;
+				}
+			},
+			// remove finished connections
+			Some(_) = connections.join_next() => continue,
+			_ = &mut shutdown => break,
+		};
+		let acceptor = acceptor.clone();
+		let app = app.clone();
+		connections.spawn(async move {
+			let stream = match tokio::time::timeout(
+				DEFAULT_TLS_HANDSHAKE_TIMEOUT,
+				acceptor.accept(stream),
+			)
+			.await
+			{
+				Ok(Ok(stream)) => stream,
+				Ok(Err(err)) => {
+					log::info!("{address}: tls handshake failed: {err}");
+					return;
+				}
+				Err(_) => {
+					log::info!("{address}: tls handshake timed out");
+					return;
+				}
+			};
+			let service = hyper::service::service_fn(
+				move |mut request: hyper::Request<Incoming>| {
+					request.extensions_mut().insert(ConnectInfo(address));
+					app.clone().call(request)
+				},
+			);
+			if let Err(err) = hyper_util::server::conn::auto::Builder::new(
+				TokioExecutor::new(),
+			)
+			.serve_connection_with_upgrades(TokioIo::new(stream), service)
+			.await
+			{
+				log::info!("{address}: {err}");
+			}
+		});
+	}
+	// upgraded websockets are closed by the shutdown future,
+	// in flight requests are given time to complete
+	let drain = async { while connections.join_next().await.is_some() {} };
+	if tokio::time::timeout(super::DEFAULT_SHUTDOWN_TIMEOUT, drain)
+		.await
+		.is_err()
+	{
+		log::info!("Timed out waiting for connections to close");
+	}
+	// dropping the set aborts the remaining connections
+	Ok(())
+}
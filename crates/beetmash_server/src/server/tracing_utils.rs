@@ -6,12 +6,15 @@ use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
 pub fn init_tracing() {
+	init_tracing_with("example_websockets=debug,tower_http=debug");
+}
+
+/// Init tracing with the filter, used if `RUST_LOG` is not set.
+pub fn init_tracing_with(default_filter: &str) {
 	tracing_subscriber::registry()
 		.with(
 			tracing_subscriber::EnvFilter::try_from_default_env()
-				.unwrap_or_else(|_| {
-					"example_websockets=debug,tower_http=debug".into()
-				}),
+				.unwrap_or_else(|_| default_filter.into()),
 		)
 		.with(tracing_subscriber::fmt::layer())
 		.init();